@@ -1,4 +1,10 @@
 use std::ffi::{OsStr, OsString};
+use std::time::Duration;
+
+use log::Level;
+
+use crate::duration::parse_duration;
+use crate::logging::ColorMode;
 
 
 fn exit_with_usage() -> ! {
@@ -6,13 +12,40 @@ fn exit_with_usage() -> ! {
         .map(|path| path.display().to_string())
         .unwrap_or_else(|_| "servicerestarter".to_owned());
 
-    eprintln!("Usage: {} [MODE [SERVICENAME]]", myself);
+    eprintln!("Usage: {} [OPTIONS] [MODE [SERVICENAME]]", myself);
+    eprintln!("       {} import-config [SERVICENAME] FILE", myself);
+    eprintln!("       {} install|service SERVICENAME...", myself);
     eprintln!();
     eprintln!("MODE is one of:");
     eprintln!();
     eprintln!("  run        Runs this application as a console application. This is the default");
     eprintln!("             if no mode is given.");
     eprintln!();
+    eprintln!("  check      Checks each watched service once, starting those that are stopped,");
+    eprintln!("             then exits. The exit code is 0 if nothing needed to be done, 2 if");
+    eprintln!("             at least one service was started and 3 if errors occurred.");
+    eprintln!();
+    eprintln!("  validate   Checks the configuration and whether each watched service can be");
+    eprintln!("             opened, without starting any services, then exits. The exit code");
+    eprintln!("             is 0 if no problems were found and 1 otherwise.");
+    eprintln!();
+    eprintln!("  export-config");
+    eprintln!("             Writes the configuration in the registry to standard output in the");
+    eprintln!("             format of a .reg file, which can be imported on another machine.");
+    eprintln!();
+    eprintln!("  import-config");
+    eprintln!("             Writes the parameters set in FILE, which consists of lines of the");
+    eprintln!("             form key = value, into the registry.");
+    eprintln!();
+    eprintln!("  list       Lists the servicerestarter services installed on this machine with");
+    eprintln!("             their state and the services they watch. SERVICENAME is ignored.");
+    eprintln!();
+    eprintln!("  logs       Writes the last lines of the log file given by LogPath to stdout.");
+    eprintln!();
+    eprintln!("  scm-lock-status");
+    eprintln!("             Shows whether the service database is locked, by whom and for");
+    eprintln!("             how long. SERVICENAME is ignored.");
+    eprintln!();
     eprintln!("  service    Runs this application as a service. This option only makes sense");
     eprintln!("             when passed by the operating system's service control manager.");
     eprintln!();
@@ -21,47 +54,346 @@ fn exit_with_usage() -> ! {
     eprintln!("  stop       Stops the service corresponding to this application.");
     eprintln!();
     eprintln!("  install    Installs this application as a service into the operating system.");
+    eprintln!("             If multiple SERVICENAMEs are given, one service is installed for");
+    eprintln!("             each and they share a single process.");
     eprintln!();
     eprintln!("  delete     Removes this application's corresponding service from the operating");
     eprintln!("             system. If the service is running, it is stopped first.");
     eprintln!();
+    eprintln!("  reinstall  Deletes the services if they exist, waits for them to be gone and");
+    eprintln!("             installs them again.");
+    eprintln!();
     eprintln!("SERVICENAME is used as the service name when operating the service as well as");
     eprintln!("reading the configuration from the registry. If it is missing, the name of the");
     eprintln!("executable binary (without the file extension) is used as the service name.");
+    eprintln!();
+    eprintln!("OPTIONS are:");
+    eprintln!();
+    eprintln!("  --follow   Only with logs: keep writing lines as they are appended to the");
+    eprintln!("             log file until Ctrl+C is pressed.");
+    eprintln!();
+    eprintln!("  --iterations N");
+    eprintln!("             Only with run and check: stop after N checks of the services.");
+    eprintln!();
+    eprintln!("  --json     Only with list: write the services as a JSON array instead of");
+    eprintln!("             one line per service.");
+    eprintln!();
+    eprintln!("  --lines N  Only with logs: how many lines to write (default 20).");
+    eprintln!();
+    eprintln!("  --max-runtime DURATION");
+    eprintln!("             Only with run: stop cleanly once DURATION (e.g. 30s, 5m, 1h) has");
+    eprintln!("             passed.");
+    eprintln!();
+    eprintln!("  --discard-parameters");
+    eprintln!("             Only with reinstall: do not restore the values of the services'");
+    eprintln!("             Parameters keys, which are deleted along with the services.");
+    eprintln!();
+    eprintln!("  --start    Only with install and reinstall: start the services once they are");
+    eprintln!("             installed and wait for them to be running.");
+    eprintln!();
+    eprintln!("  --stop-timeout DURATION");
+    eprintln!("             Only with delete and reinstall: how long to wait for the service to");
+    eprintln!("             stop before giving up (default 30s).");
+
+    eprintln!();
+    eprintln!("  -v, --verbose");
+    eprintln!("             Log more details to stderr: once for debug messages, twice (-vv)");
+    eprintln!("             for trace messages. Has no effect in service mode.");
+    eprintln!();
+    eprintln!("  -q, --quiet");
+    eprintln!("             Log less to stderr: once for warnings and errors only, twice (-qq)");
+    eprintln!("             for errors only. Has no effect in service mode.");
+    eprintln!();
+    eprintln!("  --params-key KEY");
+    eprintln!("             Read the parameters from KEY below HKEY_LOCAL_MACHINE instead of");
+    eprintln!("             SYSTEM\\CurrentControlSet\\Services\\SERVICENAME\\Parameters. With");
+    eprintln!("             install, the service is set up to use KEY as well. Only one");
+    eprintln!("             SERVICENAME may be given.");
+    eprintln!();
+    eprintln!("  --color WHEN");
+    eprintln!("             Whether to color the level of messages logged to stderr: auto (the");
+    eprintln!("             default; only if stderr is a console and NO_COLOR is not set),");
+    eprintln!("             always or never.");
 
     std::process::exit(1);
 }
 
 
+/// Obtains the value of an option, either from the same argument (`--option=value`) or the next
+/// one (`--option value`).
+fn option_value(
+    option_name: &str,
+    inline_value: Option<&str>,
+    rest: &mut impl Iterator<Item = OsString>,
+) -> OsString {
+    if let Some(iv) = inline_value {
+        return OsString::from(iv);
+    }
+    match rest.next() {
+        Some(v) => v,
+        None => {
+            eprintln!("option {} requires a value", option_name);
+            exit_with_usage();
+        },
+    }
+}
+
+/// Exits with an error if a value has been passed to an option that does not take one.
+fn no_option_value(option_name: &str, inline_value: Option<&str>) {
+    if inline_value.is_some() {
+        eprintln!("option {} does not take a value", option_name);
+        exit_with_usage();
+    }
+}
+
+/// The level of messages logged to stderr, depending on the number of `-v` minus the number of
+/// `-q` options.
+fn stderr_level(verbosity: i32) -> Level {
+    match verbosity {
+        i32::MIN..=-2 => Level::Error,
+        -1 => Level::Warn,
+        0 => Level::Info,
+        1 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+fn option_positive_integer_value(option_name: &str, value: OsString) -> u64 {
+    let value_str = match value.to_str() {
+        Some(v) => v,
+        None => {
+            eprintln!("value {:?} of option {} is not valid Unicode", value, option_name);
+            exit_with_usage();
+        },
+    };
+    match value_str.parse() {
+        Ok(0) | Err(_) => {
+            eprintln!("invalid value {:?} of option {}: expected a positive integer", value_str, option_name);
+            exit_with_usage();
+        },
+        Ok(i) => i,
+    }
+}
+
+fn option_duration_value(option_name: &str, value: OsString) -> Duration {
+    let value_str = match value.to_str() {
+        Some(v) => v,
+        None => {
+            eprintln!("value {:?} of option {} is not valid Unicode", value, option_name);
+            exit_with_usage();
+        },
+    };
+    match parse_duration(value_str) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("invalid value {:?} of option {}: {}", value_str, option_name, e);
+            exit_with_usage();
+        },
+    }
+}
+
+
 /// The arguments to the program.
 pub(crate) struct Args {
     pub mode: OperMode,
     pub service_name: OsString,
+    /// All the service names given; only `service`, `install` and `reinstall` accept more than one.
+    /// The first one is `service_name`.
+    pub service_names: Vec<OsString>,
+    pub max_runtime: Option<Duration>,
+    pub iterations: Option<u64>,
+    pub lines: Option<u64>,
+    pub follow: bool,
+    pub stop_timeout: Option<Duration>,
+    pub start_after_install: bool,
+    pub discard_parameters: bool,
+    pub json: bool,
+    pub config_file: Option<OsString>,
+    pub params_key: Option<OsString>,
+    pub stderr_level: Level,
+    pub color: ColorMode,
 }
 impl Args {
     pub fn parse_args(args: impl Iterator<Item = impl Into<OsString>>) -> Args {
-        let arg_vec: Vec<OsString> = args
+        let mut arg_iter = args
             .map(|a| a.into())
-            .collect();
+            .skip(1);
 
-        if arg_vec.len() > 3 {
-            eprintln!("too many arguments");
-            exit_with_usage();
+        let mut positional: Vec<OsString> = Vec::new();
+        let mut max_runtime: Option<Duration> = None;
+        let mut iterations: Option<u64> = None;
+        let mut lines: Option<u64> = None;
+        let mut follow = false;
+        let mut stop_timeout: Option<Duration> = None;
+        let mut start_after_install = false;
+        let mut discard_parameters = false;
+        let mut json = false;
+        let mut verbosity: i32 = 0;
+        let mut color = ColorMode::default();
+        let mut params_key: Option<OsString> = None;
+        while let Some(arg) = arg_iter.next() {
+            let option = match arg.to_str() {
+                Some(a) if a.starts_with("--") => a.to_owned(),
+                Some(a) if a.len() > 1 && a.starts_with('-') => {
+                    // short options, which may be combined (e.g. -vv)
+                    for c in a[1..].chars() {
+                        match c {
+                            'v' => verbosity = verbosity.saturating_add(1),
+                            'q' => verbosity = verbosity.saturating_sub(1),
+                            other => {
+                                eprintln!("unknown option -{}", other);
+                                exit_with_usage();
+                            },
+                        }
+                    }
+                    continue;
+                },
+                _ => {
+                    positional.push(arg);
+                    continue;
+                },
+            };
+            let (option_name, inline_value) = match option.split_once('=') {
+                Some((n, v)) => (n, Some(v)),
+                None => (option.as_str(), None),
+            };
+
+            match option_name {
+                "--max-runtime" => {
+                    let value = option_value(option_name, inline_value, &mut arg_iter);
+                    max_runtime = Some(option_duration_value(option_name, value));
+                },
+                "--iterations" => {
+                    let value = option_value(option_name, inline_value, &mut arg_iter);
+                    iterations = Some(option_positive_integer_value(option_name, value));
+                },
+                "--lines" => {
+                    let value = option_value(option_name, inline_value, &mut arg_iter);
+                    lines = Some(option_positive_integer_value(option_name, value));
+                },
+                "--follow" => {
+                    no_option_value(option_name, inline_value);
+                    follow = true;
+                },
+                "--stop-timeout" => {
+                    let value = option_value(option_name, inline_value, &mut arg_iter);
+                    stop_timeout = Some(option_duration_value(option_name, value));
+                },
+                "--color" => {
+                    let value = option_value(option_name, inline_value, &mut arg_iter);
+                    color = if value == "auto" {
+                        ColorMode::Auto
+                    } else if value == "always" {
+                        ColorMode::Always
+                    } else if value == "never" {
+                        ColorMode::Never
+                    } else {
+                        eprintln!("invalid value {:?} of option {}: expected auto, always or never", value, option_name);
+                        exit_with_usage();
+                    };
+                },
+                "--params-key" => {
+                    let value = option_value(option_name, inline_value, &mut arg_iter);
+                    if value.is_empty() {
+                        eprintln!("option {} requires a non-empty value", option_name);
+                        exit_with_usage();
+                    }
+                    params_key = Some(value);
+                },
+                "--start" => {
+                    no_option_value(option_name, inline_value);
+                    start_after_install = true;
+                },
+                "--discard-parameters" => {
+                    no_option_value(option_name, inline_value);
+                    discard_parameters = true;
+                },
+                "--json" => {
+                    no_option_value(option_name, inline_value);
+                    json = true;
+                },
+                "--verbose" => {
+                    no_option_value(option_name, inline_value);
+                    verbosity = verbosity.saturating_add(1);
+                },
+                "--quiet" => {
+                    no_option_value(option_name, inline_value);
+                    verbosity = verbosity.saturating_sub(1);
+                },
+                other => {
+                    eprintln!("unknown option {}", other);
+                    exit_with_usage();
+                },
+            }
         }
 
-        let mode: OperMode = if arg_vec.len() < 2 {
+        let mode: OperMode = if positional.is_empty() {
             OperMode::default()
         } else {
-            match arg_vec[1].as_os_str().try_into() {
+            match positional[0].as_os_str().try_into() {
                 Ok(om) => om,
                 Err(_) => {
-                    eprintln!("unknown mode {:?}", arg_vec[1]);
+                    eprintln!("unknown mode {:?}", positional[0]);
                     exit_with_usage();
                 },
             }
         };
 
-        let service_name: OsString = if arg_vec.len() < 3 {
+        // the configuration file is the last argument
+        let config_file = if mode == OperMode::ImportConfig {
+            if positional.len() < 2 {
+                eprintln!("import-config requires a file name");
+                exit_with_usage();
+            }
+            positional.pop()
+        } else {
+            None
+        };
+
+        let accepts_multiple_names = mode == OperMode::Service
+            || mode == OperMode::Install
+            || mode == OperMode::Reinstall;
+        if positional.len() > 2 && !accepts_multiple_names {
+            eprintln!("too many arguments");
+            exit_with_usage();
+        }
+
+        if max_runtime.is_some() && mode != OperMode::Run {
+            eprintln!("--max-runtime is only supported in run mode");
+            exit_with_usage();
+        }
+
+        if iterations.is_some() && mode != OperMode::Run && mode != OperMode::Check {
+            eprintln!("--iterations is only supported in run and check mode");
+            exit_with_usage();
+        }
+
+        if (lines.is_some() || follow) && mode != OperMode::Logs {
+            eprintln!("--lines and --follow are only supported in logs mode");
+            exit_with_usage();
+        }
+
+        if start_after_install && mode != OperMode::Install && mode != OperMode::Reinstall {
+            eprintln!("--start is only supported in install and reinstall mode");
+            exit_with_usage();
+        }
+
+        if discard_parameters && mode != OperMode::Reinstall {
+            eprintln!("--discard-parameters is only supported in reinstall mode");
+            exit_with_usage();
+        }
+
+        if stop_timeout.is_some() && mode != OperMode::Delete && mode != OperMode::Reinstall {
+            eprintln!("--stop-timeout is only supported in delete and reinstall mode");
+            exit_with_usage();
+        }
+
+        if json && mode != OperMode::List {
+            eprintln!("--json is only supported in list mode");
+            exit_with_usage();
+        }
+
+        let service_names: Vec<OsString> = if positional.len() < 2 {
             // take from .exe name
             let exe_path = match std::env::current_exe() {
                 Ok(pb) => pb,
@@ -71,19 +403,37 @@ impl Args {
                 },
             };
             match exe_path.file_stem() {
-                Some(fs) => fs.to_os_string(),
+                Some(fs) => vec![fs.to_os_string()],
                 None => {
                     eprintln!("no service name given and executable path does not contain file name");
                     exit_with_usage();
                 },
             }
         } else {
-            arg_vec[2].clone()
+            positional.split_off(1)
         };
 
+        if params_key.is_some() && service_names.len() > 1 {
+            eprintln!("--params-key cannot be combined with multiple service names");
+            exit_with_usage();
+        }
+
         Args {
             mode,
-            service_name,
+            service_name: service_names[0].clone(),
+            service_names,
+            max_runtime,
+            iterations,
+            lines,
+            follow,
+            stop_timeout,
+            start_after_install,
+            discard_parameters,
+            json,
+            config_file,
+            params_key,
+            stderr_level: stderr_level(verbosity),
+            color,
         }
     }
 
@@ -99,6 +449,31 @@ pub(crate) enum OperMode {
     /// Run as a standard console application.
     Run,
 
+    /// Perform a single check of all watched services, then exit.
+    Check,
+
+    /// Check the configuration without starting any services, then exit.
+    Validate,
+
+    /// Output the configuration as a .reg file, then exit.
+    ExportConfig,
+
+    /// Write the parameters from a configuration file into the registry, then exit.
+    ImportConfig,
+
+    /// List the installed servicerestarter services, then exit.
+    List,
+
+    /// Output the last lines of the log file, then exit (or keep following it).
+    Logs,
+
+    /// Show whether the service database is locked, then exit.
+    ScmLockStatus,
+
+    /// Check that registry values can be written and read back, then exit. Not listed in the usage
+    /// information.
+    SelfTest,
+
     /// Run as a service. Triggered only by the operating system Service Control Manager.
     Service,
 
@@ -113,6 +488,9 @@ pub(crate) enum OperMode {
 
     /// Delete the service. Stop it first if it is running.
     Delete,
+
+    /// Delete the service if it exists, then install it again.
+    Reinstall,
 }
 impl Default for OperMode {
     fn default() -> Self { Self::Run }
@@ -123,6 +501,22 @@ impl TryFrom<&OsStr> for OperMode {
     fn try_from(value: &OsStr) -> Result<Self, Self::Error> {
         if value == "run" {
             Ok(Self::Run)
+        } else if value == "check" {
+            Ok(Self::Check)
+        } else if value == "validate" {
+            Ok(Self::Validate)
+        } else if value == "export-config" {
+            Ok(Self::ExportConfig)
+        } else if value == "import-config" {
+            Ok(Self::ImportConfig)
+        } else if value == "list" {
+            Ok(Self::List)
+        } else if value == "logs" {
+            Ok(Self::Logs)
+        } else if value == "scm-lock-status" {
+            Ok(Self::ScmLockStatus)
+        } else if value == "self-test" {
+            Ok(Self::SelfTest)
         } else if value == "service" {
             Ok(Self::Service)
         } else if value == "start" {
@@ -133,6 +527,8 @@ impl TryFrom<&OsStr> for OperMode {
             Ok(Self::Install)
         } else if value == "delete" {
             Ok(Self::Delete)
+        } else if value == "reinstall" {
+            Ok(Self::Reinstall)
         } else {
             Err(())
         }
@@ -0,0 +1,366 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::config_file::{self, ConfigFileError, ConfigValue};
+use crate::duration::parse_duration;
+use crate::ParameterError;
+use crate::registry::RegistryAccess;
+
+
+/// Whether [`without_duplicates`] has already warned about duplicate entries. The list is read
+/// anew for every check, so the warning is only logged the first time.
+static WARNED_ABOUT_DUPLICATES: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub(crate) enum ConfigLoadError {
+    Io(std::io::Error),
+    Syntax(ConfigFileError),
+    InvalidValue { name: String, message: String },
+}
+impl fmt::Display for ConfigLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Syntax(e) => write!(f, "syntax error: {}", e),
+            Self::InvalidValue { name, message } => write!(f, "invalid value for {}: {}", name, message),
+        }
+    }
+}
+impl std::error::Error for ConfigLoadError {
+}
+impl From<std::io::Error> for ConfigLoadError {
+    fn from(e: std::io::Error) -> Self { Self::Io(e) }
+}
+impl From<ConfigFileError> for ConfigLoadError {
+    fn from(e: ConfigFileError) -> Self { Self::Syntax(e) }
+}
+
+
+/// The settings that may be given either in the registry or in the configuration file.
+///
+/// Each source produces a `Config` in which the settings it does not contain are `None`; they are
+/// then combined using [`Config::or`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Config {
+    pub watched_services: Option<Vec<OsString>>,
+    pub excluded_services: Option<Vec<OsString>>,
+    pub sleep_duration: Option<Duration>,
+    pub minimum_sleep_duration: Option<Duration>,
+    pub sleep_jitter: Option<Duration>,
+    pub initial_sleep_duration: Option<Duration>,
+    pub log_path: Option<OsString>,
+    pub log_level: Option<u64>,
+}
+impl Config {
+    /// Reads the configuration file at the given path. Returns `Ok(None)` if there is no such file.
+    ///
+    /// The file has the format accepted by `import-config`; parameters that can only be set in the
+    /// registry are skipped with a warning.
+    pub fn from_file(path: &Path) -> Result<Option<Self>, ConfigLoadError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let entries = config_file::parse(&text)?;
+
+        let mut config = Self::default();
+        let mut sleep_duration_ms = None;
+        for (name, value) in entries {
+            let invalid = |message: &str| ConfigLoadError::InvalidValue {
+                name: name.clone(),
+                message: message.to_owned(),
+            };
+            match (name.as_str(), value) {
+                ("ServicesExpectedRunning", ConfigValue::List(names)) => {
+                    config.watched_services = Some(names.into_iter().map(OsString::from).collect());
+                },
+                ("ServicesExpectedRunning", ConfigValue::String(name)) => {
+                    config.watched_services = Some(vec![OsString::from(name)]);
+                },
+                ("ServicesExcluded", ConfigValue::List(names)) => {
+                    config.excluded_services = Some(names.into_iter().map(OsString::from).collect());
+                },
+                ("ServicesExcluded", ConfigValue::String(name)) => {
+                    config.excluded_services = Some(vec![OsString::from(name)]);
+                },
+                ("SleepDuration", ConfigValue::String(s)) => {
+                    let duration = parse_duration(&s)
+                        .map_err(|e| invalid(&e.to_string()))?;
+                    config.sleep_duration = Some(duration);
+                },
+                ("SleepDurationMilliseconds", ConfigValue::Integer(ms)) => {
+                    sleep_duration_ms = Some(Duration::from_millis(ms));
+                },
+                ("MinimumSleepDurationMilliseconds", ConfigValue::Integer(ms)) => {
+                    config.minimum_sleep_duration = Some(Duration::from_millis(ms));
+                },
+                ("SleepJitterMilliseconds", ConfigValue::Integer(ms)) => {
+                    config.sleep_jitter = Some(Duration::from_millis(ms));
+                },
+                ("InitialSleepDurationMilliseconds", ConfigValue::Integer(ms)) => {
+                    config.initial_sleep_duration = Some(Duration::from_millis(ms));
+                },
+                ("LogPath", ConfigValue::String(s)) => {
+                    config.log_path = Some(OsString::from(s));
+                },
+                ("LogLevel", ConfigValue::Integer(l)) => {
+                    config.log_level = Some(l);
+                },
+                ("ServicesExpectedRunning", _) | ("ServicesExcluded", _) => return Err(invalid("expected a list of strings")),
+                ("SleepDuration", _) | ("LogPath", _) => return Err(invalid("expected a string")),
+                ("SleepDurationMilliseconds", _)
+                        | ("MinimumSleepDurationMilliseconds", _)
+                        | ("SleepJitterMilliseconds", _)
+                        | ("InitialSleepDurationMilliseconds", _)
+                        | ("LogLevel", _) => {
+                    return Err(invalid("expected an integer"));
+                },
+                (other, _) => {
+                    if config_file::parameter_kind(other).is_some() {
+                        warn!("parameter {} can only be set in the registry; ignoring it in the configuration file", other);
+                    } else {
+                        warn!("unknown parameter {:?} in the configuration file; ignoring it", other);
+                    }
+                },
+            }
+        }
+
+        // as in the registry, the human-friendly variant wins
+        if config.sleep_duration.is_none() {
+            config.sleep_duration = sleep_duration_ms;
+        }
+
+        Ok(Some(config))
+    }
+
+    /// Reads the settings from the parameters key in the registry.
+    ///
+    /// Returns an error if a setting cannot be read or is invalid.
+    pub fn load_from_registry<R: RegistryAccess>(registry: &R) -> Result<Self, ParameterError> {
+        Ok(Self {
+            watched_services: crate::read_watched_services(registry)?,
+            excluded_services: crate::try_read_optional_string_list_param(registry, "ServicesExcluded")?,
+            sleep_duration: crate::read_optional_sleep_duration(registry)?,
+            minimum_sleep_duration: crate::try_read_optional_integer_param(registry, "MinimumSleepDurationMilliseconds")?
+                .map(Duration::from_millis),
+            sleep_jitter: crate::try_read_optional_integer_param(registry, "SleepJitterMilliseconds")?
+                .map(Duration::from_millis),
+            initial_sleep_duration: crate::try_read_optional_integer_param(registry, "InitialSleepDurationMilliseconds")?
+                .map(Duration::from_millis),
+            log_path: crate::try_read_optional_string_param(registry, "LogPath")?,
+            log_level: crate::try_read_optional_integer_param(registry, "LogLevel")?,
+        })
+    }
+
+    /// Takes each setting from `self` or, if it is not set there, from `fallback`.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            watched_services: self.watched_services.or(fallback.watched_services),
+            excluded_services: self.excluded_services.or(fallback.excluded_services),
+            sleep_duration: self.sleep_duration.or(fallback.sleep_duration),
+            minimum_sleep_duration: self.minimum_sleep_duration.or(fallback.minimum_sleep_duration),
+            sleep_jitter: self.sleep_jitter.or(fallback.sleep_jitter),
+            initial_sleep_duration: self.initial_sleep_duration.or(fallback.initial_sleep_duration),
+            log_path: self.log_path.or(fallback.log_path),
+            log_level: self.log_level.or(fallback.log_level),
+        }
+    }
+
+    /// The watched services without the excluded ones, those commented out (see
+    /// [`without_comments`]) and duplicates (see [`without_duplicates`]), or `None` if no services
+    /// are configured.
+    ///
+    /// Service names are compared case-insensitively, as Windows does.
+    pub fn services_to_check(&self) -> Option<Vec<OsString>> {
+        let watched = self.watched_services.as_ref()?;
+        let excluded = self.excluded_services.as_deref().unwrap_or(&[]);
+        Some(
+            without_duplicates(without_comments(watched.clone())).into_iter()
+                .filter(|w| !excluded.iter().any(|e| e.eq_ignore_ascii_case(w)))
+                .collect()
+        )
+    }
+}
+
+
+/// Drops the entries of a list of watched services that are empty, consist only of whitespace or
+/// are commented out by starting with `#` or `;` (after any whitespace), logging each one that is
+/// dropped. The remaining entries are returned without leading and trailing whitespace.
+pub(crate) fn without_comments(entries: Vec<OsString>) -> Vec<OsString> {
+    entries.into_iter()
+        .filter_map(|entry| {
+            let trimmed = trim_whitespace(&entry);
+            let first = trimmed.encode_wide().next();
+            if first.is_none() {
+                debug!("skipping empty entry in the list of watched services");
+                None
+            } else if first == Some(u16::from(b'#')) || first == Some(u16::from(b';')) {
+                debug!("skipping commented-out entry {:?} in the list of watched services", entry);
+                None
+            } else {
+                Some(trimmed)
+            }
+        })
+        .collect()
+}
+
+/// Removes leading and trailing whitespace from a string that is not necessarily valid Unicode.
+fn trim_whitespace(s: &OsStr) -> OsString {
+    let wide: Vec<u16> = s.encode_wide().collect();
+    // unpaired surrogates are not whitespace
+    let is_whitespace = |unit: &u16| char::from_u32(u32::from(*unit))
+        .is_some_and(char::is_whitespace);
+    let start = match wide.iter().position(|u| !is_whitespace(u)) {
+        Some(s) => s,
+        None => return OsString::new(),
+    };
+    let end = wide.iter().rposition(|u| !is_whitespace(u)).unwrap() + 1;
+    OsString::from_wide(&wide[start..end])
+}
+
+
+/// The path of the configuration file of the given instance: `SERVICENAME.toml` in the directory
+/// of the executable.
+pub(crate) fn config_file_path(service_name: &OsStr) -> Option<PathBuf> {
+    let exe_path = std::env::current_exe().ok()?;
+    let mut file_name = service_name.to_os_string();
+    file_name.push(".toml");
+    Some(exe_path.with_file_name(file_name))
+}
+
+/// Drops repeated entries of a list of watched services, keeping the first occurrence of each.
+/// Entries are compared case-insensitively.
+///
+/// A warning listing the duplicates is logged the first time any are found; afterwards, they are
+/// only logged at debug level.
+pub(crate) fn without_duplicates(entries: Vec<OsString>) -> Vec<OsString> {
+    let mut unique: Vec<OsString> = Vec::with_capacity(entries.len());
+    let mut duplicates: Vec<OsString> = Vec::new();
+    for entry in entries {
+        if unique.iter().any(|u| u.eq_ignore_ascii_case(&entry)) {
+            duplicates.push(entry);
+        } else {
+            unique.push(entry);
+        }
+    }
+
+    if !duplicates.is_empty() {
+        if WARNED_ABOUT_DUPLICATES.swap(true, Ordering::SeqCst) {
+            debug!("skipping duplicate entries in the list of watched services: {:?}", duplicates);
+        } else {
+            warn!("the list of watched services contains duplicate entries, which are skipped: {:?}", duplicates);
+        }
+    }
+    unique
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let entries = names(&["First", "#Second", ";Third", "Fourth"]);
+        assert_eq!(without_comments(entries), names(&["First", "Fourth"]));
+    }
+
+    #[test]
+    fn comments_with_leading_whitespace_are_skipped() {
+        let entries = names(&["First", "  #Second", "\t; Third", "Fourth"]);
+        assert_eq!(without_comments(entries), names(&["First", "Fourth"]));
+    }
+
+    #[test]
+    fn empty_and_whitespace_only_entries_are_skipped() {
+        let entries = names(&["", "First", " ", "\t \t", "Second", "\u{3000}"]);
+        assert_eq!(without_comments(entries), names(&["First", "Second"]));
+    }
+
+    #[test]
+    fn entries_are_trimmed() {
+        let entries = names(&["  Foo ", "\tBar\t", "Two Words "]);
+        assert_eq!(without_comments(entries), names(&["Foo", "Bar", "Two Words"]));
+    }
+
+    #[test]
+    fn entries_that_are_not_unicode_are_trimmed() {
+        // " a", an unpaired high surrogate, "b "
+        let entry = OsString::from_wide(&[0x0020, 0x0061, 0xD800, 0x0062, 0x0020]);
+        let expected = OsString::from_wide(&[0x0061, 0xD800, 0x0062]);
+        assert_eq!(without_comments(vec![entry]), vec![expected]);
+    }
+
+    #[test]
+    fn hash_inside_an_entry_is_kept() {
+        let entries = names(&["C#Service", "Semi;Colon"]);
+        assert_eq!(without_comments(entries), names(&["C#Service", "Semi;Colon"]));
+    }
+
+    #[test]
+    fn duplicates_are_skipped_case_insensitively() {
+        let entries = names(&["Spooler", "EventLog", "SPOOLER", "spooler", "Dnscache"]);
+        assert_eq!(without_duplicates(entries), names(&["Spooler", "EventLog", "Dnscache"]));
+    }
+
+    #[test]
+    fn first_occurrence_of_a_duplicate_is_kept() {
+        let entries = names(&["dnscache", "Dnscache", "DNSCACHE"]);
+        assert_eq!(without_duplicates(entries), names(&["dnscache"]));
+    }
+
+    #[test]
+    fn services_to_check_without_watched_services() {
+        assert_eq!(Config::default().services_to_check(), None);
+    }
+
+    #[test]
+    fn services_to_check_skips_comments_and_duplicates() {
+        let config = Config {
+            watched_services: Some(names(&["  Spooler", "#EventLog", "", "spooler ", "Dnscache", "; Spooler"])),
+            ..Config::default()
+        };
+        assert_eq!(config.services_to_check(), Some(names(&["Spooler", "Dnscache"])));
+    }
+
+    #[test]
+    fn services_to_check_excludes_every_occurrence() {
+        let config = Config {
+            watched_services: Some(names(&["Spooler", "Dnscache", "SPOOLER", "EventLog"])),
+            excluded_services: Some(names(&["spooler"])),
+            ..Config::default()
+        };
+        assert_eq!(config.services_to_check(), Some(names(&["Dnscache", "EventLog"])));
+    }
+
+    #[test]
+    fn services_to_check_excludes_trimmed_names() {
+        let config = Config {
+            watched_services: Some(names(&[" Spooler ", "Dnscache"])),
+            excluded_services: Some(names(&["SPOOLER"])),
+            ..Config::default()
+        };
+        assert_eq!(config.services_to_check(), Some(names(&["Dnscache"])));
+    }
+
+    #[test]
+    fn services_to_check_may_exclude_everything() {
+        let config = Config {
+            watched_services: Some(names(&["Spooler", "spooler"])),
+            excluded_services: Some(names(&["Spooler"])),
+            ..Config::default()
+        };
+        assert_eq!(config.services_to_check(), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,351 @@
+use std::ffi::OsString;
+use std::fmt;
+
+use crate::registry::RegistryValue;
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ConfigFileError {
+    MissingEquals { line: usize },
+    EmptyKey { line: usize },
+    UnterminatedString { line: usize },
+    UnterminatedList { line: usize },
+    TrailingCharacters { line: usize },
+    InvalidEscape { line: usize, escape: char },
+}
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEquals { line } => write!(f, "line {}: expected key = value", line),
+            Self::EmptyKey { line } => write!(f, "line {}: key is empty", line),
+            Self::UnterminatedString { line } => write!(f, "line {}: string is missing its closing quote", line),
+            Self::UnterminatedList { line } => write!(f, "line {}: list is missing its closing bracket", line),
+            Self::TrailingCharacters { line } => write!(f, "line {}: unexpected characters after the value", line),
+            Self::InvalidEscape { line, escape } => write!(f, "line {}: unknown escape sequence \\{}", line, escape),
+        }
+    }
+}
+impl std::error::Error for ConfigFileError {
+}
+
+
+/// A value in a configuration file.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ConfigValue {
+    String(String),
+    Integer(u64),
+    List(Vec<String>),
+}
+impl ConfigValue {
+    fn kind_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+            Self::List(_) => "a list",
+        }
+    }
+}
+
+
+/// Parses a quoted string starting at the opening quote. Returns the string and the rest of the
+/// text after the closing quote.
+fn parse_quoted(text: &str, line: usize) -> Result<(String, &str), ConfigFileError> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[i+1..])),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some((_, e)) => e,
+                    None => break,
+                };
+                match escaped {
+                    '\\' => value.push('\\'),
+                    '"' => value.push('"'),
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    other => return Err(ConfigFileError::InvalidEscape { line, escape: other }),
+                }
+            },
+            other => value.push(other),
+        }
+    }
+    Err(ConfigFileError::UnterminatedString { line })
+}
+
+fn parse_list(text: &str, line: usize) -> Result<Vec<String>, ConfigFileError> {
+    let inner = text.strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or(ConfigFileError::UnterminatedList { line })?;
+
+    let mut items = Vec::new();
+    let mut rest = inner.trim_start();
+    while !rest.is_empty() {
+        let item;
+        if rest.starts_with('"') {
+            let (quoted, after) = parse_quoted(rest, line)?;
+            item = quoted;
+            rest = after.trim_start();
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            item = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+        }
+        items.push(item);
+
+        match rest.strip_prefix(',') {
+            Some(after_comma) => rest = after_comma.trim_start(),
+            None if rest.is_empty() => {},
+            None => return Err(ConfigFileError::TrailingCharacters { line }),
+        }
+    }
+    Ok(items)
+}
+
+fn parse_value(text: &str, line: usize) -> Result<ConfigValue, ConfigFileError> {
+    if text.starts_with('"') {
+        let (value, rest) = parse_quoted(text, line)?;
+        if !rest.trim().is_empty() {
+            return Err(ConfigFileError::TrailingCharacters { line });
+        }
+        Ok(ConfigValue::String(value))
+    } else if text.starts_with('[') {
+        Ok(ConfigValue::List(parse_list(text, line)?))
+    } else if let Ok(i) = text.parse() {
+        Ok(ConfigValue::Integer(i))
+    } else {
+        Ok(ConfigValue::String(text.to_owned()))
+    }
+}
+
+/// Parses a configuration file consisting of lines of the form `key = value`.
+///
+/// Values are integers, strings (optionally in double quotes, which allows leading and trailing
+/// whitespace and the escapes `\\`, `\"`, `\n` and `\t`) or lists of strings in square brackets,
+/// separated by commas. Empty lines and lines starting with `#` are ignored.
+pub(crate) fn parse(text: &str) -> Result<Vec<(String, ConfigValue)>, ConfigFileError> {
+    let mut entries = Vec::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let trimmed = raw_line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let (key, value_text) = trimmed.split_once('=')
+            .ok_or(ConfigFileError::MissingEquals { line })?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(ConfigFileError::EmptyKey { line });
+        }
+        let value = parse_value(value_text.trim(), line)?;
+        entries.push((key.to_owned(), value));
+    }
+    Ok(entries)
+}
+
+
+/// The type of a parameter that can be set from a configuration file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ParameterKind {
+    String,
+    Integer,
+    StringList,
+}
+
+/// The parameters that can be set from a configuration file.
+const KNOWN_PARAMETERS: [(&str, ParameterKind); 41] = [
+    ("ServicesExpectedRunning", ParameterKind::StringList),
+    ("ServicesExcluded", ParameterKind::StringList),
+    ("ServiceStartOrder", ParameterKind::StringList),
+    ("ObserveOnly", ParameterKind::StringList),
+    ("SleepDurationMilliseconds", ParameterKind::Integer),
+    ("SleepDuration", ParameterKind::String),
+    ("MinimumSleepDurationMilliseconds", ParameterKind::Integer),
+    ("SleepJitterMilliseconds", ParameterKind::Integer),
+    ("InitialSleepDurationMilliseconds", ParameterKind::Integer),
+    ("NotifyWebhookUrl", ParameterKind::String),
+    ("MaxRestartAttempts", ParameterKind::Integer),
+    ("RestartAttemptResetMilliseconds", ParameterKind::Integer),
+    ("HealthyStableMilliseconds", ParameterKind::Integer),
+    ("MaxConcurrentStarts", ParameterKind::Integer),
+    ("SmtpServer", ParameterKind::String),
+    ("SmtpFrom", ParameterKind::String),
+    ("SmtpTo", ParameterKind::StringList),
+    ("StatsFlushIntervalMilliseconds", ParameterKind::Integer),
+    ("ExitAfterIdleMilliseconds", ParameterKind::Integer),
+    ("HealthPort", ParameterKind::Integer),
+    ("MetricsPort", ParameterKind::Integer),
+    ("ScmLockedRetryCount", ParameterKind::Integer),
+    ("ScmLockedRetryDelayMilliseconds", ParameterKind::Integer),
+    ("MaintenanceMode", ParameterKind::Integer),
+    ("RestartWindows", ParameterKind::StringList),
+    ("PreRestartCommand", ParameterKind::String),
+    ("RestartCommand", ParameterKind::String),
+    ("CommandTimeoutMilliseconds", ParameterKind::Integer),
+    ("ProbeTimeoutMilliseconds", ParameterKind::Integer),
+    ("ProbeFailureThreshold", ParameterKind::Integer),
+    ("PowerSettleMilliseconds", ParameterKind::Integer),
+    ("WatchParameterChanges", ParameterKind::Integer),
+    ("WatchdogTimeoutMilliseconds", ParameterKind::Integer),
+    ("WatchdogExitOnStall", ParameterKind::Integer),
+    ("LogPath", ParameterKind::String),
+    ("LogLevel", ParameterKind::Integer),
+    ("LogMaxMessageLength", ParameterKind::Integer),
+    ("LogRotation", ParameterKind::String),
+    ("LogMaxFiles", ParameterKind::Integer),
+    ("LogTimeZone", ParameterKind::String),
+    ("LogSequenceNumbers", ParameterKind::Integer),
+];
+
+pub(crate) fn parameter_kind(name: &str) -> Option<ParameterKind> {
+    KNOWN_PARAMETERS.iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, k)| *k)
+}
+
+/// Converts a value from a configuration file into the registry value of a parameter of the given
+/// kind. A single string is accepted where a list is expected.
+pub(crate) fn to_registry_value(kind: ParameterKind, value: &ConfigValue) -> Result<RegistryValue, String> {
+    match (kind, value) {
+        (ParameterKind::String, ConfigValue::String(s)) => Ok(RegistryValue::String(OsString::from(s))),
+        (ParameterKind::Integer, ConfigValue::Integer(i)) => match u32::try_from(*i) {
+            Ok(dw) => Ok(RegistryValue::Dword(dw)),
+            Err(_) => Ok(RegistryValue::Qword(*i)),
+        },
+        (ParameterKind::StringList, ConfigValue::String(s)) => Ok(RegistryValue::MultiString(vec![OsString::from(s)])),
+        (ParameterKind::StringList, ConfigValue::List(items)) => {
+            if items.iter().any(|i| i.is_empty()) {
+                return Err("lists must not contain empty strings".to_owned());
+            }
+            Ok(RegistryValue::MultiString(items.iter().map(OsString::from).collect()))
+        },
+        (ParameterKind::String, other) => Err(format!("expected a string, got {}", other.kind_name())),
+        (ParameterKind::Integer, other) => Err(format!("expected an integer, got {}", other.kind_name())),
+        (ParameterKind::StringList, other) => Err(format!("expected a list of strings, got {}", other.kind_name())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_single(line: &str) -> Result<ConfigValue, ConfigFileError> {
+        let mut entries = parse(line)?;
+        assert_eq!(entries.len(), 1);
+        Ok(entries.remove(0).1)
+    }
+
+    fn strings(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn entries_and_comments() {
+        let text = "# a comment\n\n  Name = value with spaces  \r\nCount=42\n   # indented comment\nUrl = http://example.com/?a=b\n";
+        assert_eq!(
+            parse(text),
+            Ok(vec![
+                ("Name".to_owned(), ConfigValue::String("value with spaces".to_owned())),
+                ("Count".to_owned(), ConfigValue::Integer(42)),
+                ("Url".to_owned(), ConfigValue::String("http://example.com/?a=b".to_owned())),
+            ]),
+        );
+    }
+
+    #[test]
+    fn quoted_strings() {
+        assert_eq!(parse_single(r#"a = "  padded  ""#), Ok(ConfigValue::String("  padded  ".to_owned())));
+        assert_eq!(
+            parse_single(r#"a = "back\\slash \"quoted\"\n\t""#),
+            Ok(ConfigValue::String("back\\slash \"quoted\"\n\t".to_owned())),
+        );
+        // quoting keeps numbers as strings
+        assert_eq!(parse_single(r#"a = "42""#), Ok(ConfigValue::String("42".to_owned())));
+        assert_eq!(parse_single(r#"a = """#), Ok(ConfigValue::String(String::new())));
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse_single("a = []"), Ok(ConfigValue::List(Vec::new())));
+        assert_eq!(parse_single("a = [one, two ,three]"), Ok(ConfigValue::List(strings(&["one", "two", "three"]))));
+        assert_eq!(
+            parse_single(r#"a = ["with, comma", plain, "\"quoted\""]"#),
+            Ok(ConfigValue::List(strings(&["with, comma", "plain", "\"quoted\""]))),
+        );
+        assert_eq!(parse_single("a = [one,]"), Ok(ConfigValue::List(strings(&["one"]))));
+        assert_eq!(parse_single("a = [one, , two]"), Ok(ConfigValue::List(strings(&["one", "", "two"]))));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("# comment\n\nno value"), Err(ConfigFileError::MissingEquals { line: 3 }));
+        assert_eq!(parse("a = 1\n = 2"), Err(ConfigFileError::EmptyKey { line: 2 }));
+        assert_eq!(parse(r#"a = "open"#), Err(ConfigFileError::UnterminatedString { line: 1 }));
+        assert_eq!(parse(r#"a = "open\"#), Err(ConfigFileError::UnterminatedString { line: 1 }));
+        assert_eq!(parse(r#"a = ["open]"#), Err(ConfigFileError::UnterminatedString { line: 1 }));
+        assert_eq!(parse("a = [one, two"), Err(ConfigFileError::UnterminatedList { line: 1 }));
+        assert_eq!(parse("a = [one] two"), Err(ConfigFileError::UnterminatedList { line: 1 }));
+        assert_eq!(parse(r#"a = "one" two"#), Err(ConfigFileError::TrailingCharacters { line: 1 }));
+        assert_eq!(parse(r#"a = ["one" two]"#), Err(ConfigFileError::TrailingCharacters { line: 1 }));
+        assert_eq!(parse(r#"a = "\q""#), Err(ConfigFileError::InvalidEscape { line: 1, escape: 'q' }));
+    }
+
+    #[test]
+    fn known_parameters() {
+        assert_eq!(parameter_kind("SleepDuration"), Some(ParameterKind::String));
+        assert_eq!(parameter_kind("MaxRestartAttempts"), Some(ParameterKind::Integer));
+        assert_eq!(parameter_kind("ServicesExpectedRunning"), Some(ParameterKind::StringList));
+        assert_eq!(parameter_kind("NoSuchParameter"), None);
+    }
+
+    #[test]
+    fn registry_values() {
+        assert_eq!(
+            to_registry_value(ParameterKind::String, &ConfigValue::String("x".to_owned())),
+            Ok(RegistryValue::String(OsString::from("x"))),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::Integer, &ConfigValue::Integer(u32::MAX.into())),
+            Ok(RegistryValue::Dword(u32::MAX)),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::Integer, &ConfigValue::Integer(u64::from(u32::MAX) + 1)),
+            Ok(RegistryValue::Qword(u64::from(u32::MAX) + 1)),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::StringList, &ConfigValue::String("Single".to_owned())),
+            Ok(RegistryValue::MultiString(vec![OsString::from("Single")])),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::StringList, &ConfigValue::List(strings(&["One", "Two"]))),
+            Ok(RegistryValue::MultiString(vec![OsString::from("One"), OsString::from("Two")])),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::StringList, &ConfigValue::List(strings(&["One", "", "Two"]))),
+            Err("lists must not contain empty strings".to_owned()),
+        );
+    }
+
+    #[test]
+    fn registry_value_type_checks() {
+        assert_eq!(
+            to_registry_value(ParameterKind::String, &ConfigValue::Integer(1)),
+            Err("expected a string, got an integer".to_owned()),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::String, &ConfigValue::List(Vec::new())),
+            Err("expected a string, got a list".to_owned()),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::Integer, &ConfigValue::String("1".to_owned())),
+            Err("expected an integer, got a string".to_owned()),
+        );
+        assert_eq!(
+            to_registry_value(ParameterKind::StringList, &ConfigValue::Integer(1)),
+            Err("expected a list of strings, got an integer".to_owned()),
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use windows::core::Error;
+use windows::Win32::System::Console::{
+    CONSOLE_MODE, ENABLE_VIRTUAL_TERMINAL_PROCESSING, GetConsoleMode, GetStdHandle,
+    PHANDLER_ROUTINE, SetConsoleCtrlHandler, SetConsoleMode, STD_ERROR_HANDLE,
+};
+
+
+/// Adds a function to the list of handlers called when the console receives a control event
+/// (e.g. Ctrl+C or Ctrl+Break).
+pub(crate) fn add_console_ctrl_handler(handler_routine: PHANDLER_ROUTINE) -> Result<(), Error> {
+    let success = unsafe {
+        SetConsoleCtrlHandler(handler_routine, true)
+    }.as_bool();
+
+    if success {
+        Ok(())
+    } else {
+        Err(Error::from_win32())
+    }
+}
+
+
+/// Checks whether stderr is a console and, if so, makes it interpret ANSI escape sequences.
+/// Returns whether escape sequences can be used, i.e. `false` if stderr is redirected or the
+/// console is too old to support them.
+pub(crate) fn enable_stderr_escape_sequences() -> bool {
+    let handle = match unsafe { GetStdHandle(STD_ERROR_HANDLE) } {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+
+    // fails if the handle is not a console (e.g. a file or a pipe)
+    let mut mode = CONSOLE_MODE::default();
+    let is_console = unsafe {
+        GetConsoleMode(handle, &mut mode)
+    }.as_bool();
+    if !is_console {
+        return false;
+    }
+    if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING == ENABLE_VIRTUAL_TERMINAL_PROCESSING {
+        return true;
+    }
+    unsafe {
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING)
+    }.as_bool()
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::time::Duration;
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum DurationParseError {
+    Empty,
+    MissingNumber(String),
+    MissingUnit(String),
+    UnknownUnit(String),
+    Overflow,
+}
+impl fmt::Display for DurationParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "duration is empty"),
+            Self::MissingNumber(rest) => write!(f, "expected a number at {:?}", rest),
+            Self::MissingUnit(number) => write!(f, "number {:?} is missing a unit (ms, s, m, h or d)", number),
+            Self::UnknownUnit(unit) => write!(f, "unknown unit {:?} (expected ms, s, m, h or d)", unit),
+            Self::Overflow => write!(f, "duration is too long"),
+        }
+    }
+}
+impl std::error::Error for DurationParseError {
+}
+
+
+/// Parses a human-friendly duration such as `500ms`, `60s`, `5m`, `1h` or `1d`.
+///
+/// Multiple components may be concatenated, e.g. `1h30m`. Whitespace between components is
+/// permitted.
+pub(crate) fn parse_duration(text: &str) -> Result<Duration, DurationParseError> {
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return Err(DurationParseError::Empty);
+    }
+
+    let mut total_ms: u64 = 0;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if number_len == 0 {
+            return Err(DurationParseError::MissingNumber(rest.to_owned()));
+        }
+        let (number_str, after_number) = rest.split_at(number_len);
+        let number: u64 = number_str.parse()
+            .map_err(|_| DurationParseError::Overflow)?;
+
+        let unit_len = after_number.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after_number.len());
+        if unit_len == 0 {
+            return Err(DurationParseError::MissingUnit(number_str.to_owned()));
+        }
+        let (unit, after_unit) = after_number.split_at(unit_len);
+        let factor_ms: u64 = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            "d" => 24 * 60 * 60 * 1000,
+            other => return Err(DurationParseError::UnknownUnit(other.to_owned())),
+        };
+
+        total_ms = number.checked_mul(factor_ms)
+            .and_then(|ms| total_ms.checked_add(ms))
+            .ok_or(DurationParseError::Overflow)?;
+        rest = after_unit.trim_start();
+    }
+
+    Ok(Duration::from_millis(total_ms))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_components() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(5 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn concatenated_components() {
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration(" 1m 30s 250ms "), Ok(Duration::from_millis(90_250)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_duration(""), Err(DurationParseError::Empty));
+        assert_eq!(parse_duration("   "), Err(DurationParseError::Empty));
+        assert_eq!(parse_duration("s"), Err(DurationParseError::MissingNumber("s".to_owned())));
+        assert_eq!(parse_duration("1h m"), Err(DurationParseError::MissingNumber("m".to_owned())));
+        assert_eq!(parse_duration("60"), Err(DurationParseError::MissingUnit("60".to_owned())));
+        assert_eq!(parse_duration("1h30"), Err(DurationParseError::MissingUnit("30".to_owned())));
+        assert_eq!(parse_duration("5min"), Err(DurationParseError::UnknownUnit("min".to_owned())));
+        assert_eq!(parse_duration("5S"), Err(DurationParseError::UnknownUnit("S".to_owned())));
+    }
+
+    #[test]
+    fn overflow() {
+        // too large for u64 on its own
+        assert_eq!(parse_duration("99999999999999999999d"), Err(DurationParseError::Overflow));
+        // fits, but not once converted to milliseconds
+        assert_eq!(parse_duration("300000000000000d"), Err(DurationParseError::Overflow));
+        // fits, but not once added up
+        assert_eq!(parse_duration("18446744073709551615ms1ms"), Err(DurationParseError::Overflow));
+    }
+}
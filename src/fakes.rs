@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::rc::Rc;
+
+use windows::core::Error;
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SERVICE_DOES_NOT_EXIST};
+
+use crate::registry::{RegistryAccess, RegistryPermissions, RegistryValue};
+use crate::service_control::{ServiceAccess, ServiceControl, ServicePermissions, ServiceStartType, ServiceState};
+
+
+/// The values of each key of a [`FakeRegistry`], by lowercased path relative to its root.
+type FakeKeys = BTreeMap<String, BTreeMap<OsString, RegistryValue>>;
+
+fn key_path(parent: &str, subkey: &OsStr) -> String {
+    let subkey_lower = subkey.to_string_lossy().to_lowercase();
+    if parent.is_empty() {
+        subkey_lower
+    } else {
+        format!("{}\\{}", parent, subkey_lower)
+    }
+}
+
+
+/// An in-memory registry key. Clones and opened subkeys share the same tree.
+#[derive(Clone, Debug)]
+pub(crate) struct FakeRegistry {
+    keys: Rc<RefCell<FakeKeys>>,
+    path: String,
+}
+impl FakeRegistry {
+    /// Creates an empty tree and returns its root key.
+    pub fn new() -> Self {
+        let mut keys = FakeKeys::new();
+        keys.insert(String::new(), BTreeMap::new());
+        Self {
+            keys: Rc::new(RefCell::new(keys)),
+            path: String::new(),
+        }
+    }
+
+    pub fn with_value(self, name: &str, value: RegistryValue) -> Self {
+        self.write_value(Some(&OsString::from(name)), &value)
+            .expect("failed to write fake registry value");
+        self
+    }
+
+    /// The value with the given name in the given subkey, if both exist.
+    pub fn value_in(&self, subkey: &str, name: &str) -> Option<RegistryValue> {
+        self.keys.borrow()
+            .get(&key_path(&self.path, OsStr::new(subkey)))
+            .and_then(|values| values.get(OsStr::new(name)).cloned())
+    }
+}
+impl RegistryAccess for FakeRegistry {
+    fn open_subkey(&self, subkey: Option<&OsStr>, _permissions: RegistryPermissions) -> Result<Self, Error> {
+        let path = match subkey {
+            Some(sk) => key_path(&self.path, sk),
+            None => self.path.clone(),
+        };
+        if !self.keys.borrow().contains_key(&path) {
+            return Err(ERROR_FILE_NOT_FOUND.to_hresult().into());
+        }
+        Ok(Self {
+            keys: Rc::clone(&self.keys),
+            path,
+        })
+    }
+
+    fn create_subkey(&self, subkey: &OsStr, _permissions: RegistryPermissions) -> Result<Self, Error> {
+        let path = key_path(&self.path, subkey);
+        self.keys.borrow_mut()
+            .entry(path.clone())
+            .or_default();
+        Ok(Self {
+            keys: Rc::clone(&self.keys),
+            path,
+        })
+    }
+
+    fn delete_subkey(&self, subkey: &OsStr) -> Result<(), Error> {
+        let path = key_path(&self.path, subkey);
+        let mut keys = self.keys.borrow_mut();
+        if keys.remove(&path).is_none() {
+            return Err(ERROR_FILE_NOT_FOUND.to_hresult().into());
+        }
+        let prefix = format!("{}\\", path);
+        keys.retain(|p, _| !p.starts_with(&prefix));
+        Ok(())
+    }
+
+    fn read_value(&self, value_name: Option<&OsStr>) -> Result<RegistryValue, Error> {
+        let name = value_name.unwrap_or_default();
+        self.keys.borrow()
+            .get(&self.path)
+            .and_then(|values| values.get(name).cloned())
+            .ok_or_else(|| ERROR_FILE_NOT_FOUND.to_hresult().into())
+    }
+
+    fn write_value(&self, value_name: Option<&OsStr>, value: &RegistryValue) -> Result<(), Error> {
+        let name = value_name.unwrap_or_default().to_os_string();
+        match self.keys.borrow_mut().get_mut(&self.path) {
+            Some(values) => {
+                values.insert(name, value.clone());
+                Ok(())
+            },
+            None => Err(ERROR_FILE_NOT_FOUND.to_hresult().into()),
+        }
+    }
+}
+
+
+/// A service known to a [`FakeServiceControl`].
+#[derive(Clone, Copy, Debug)]
+struct FakeService {
+    state: ServiceState,
+    state_after_start: ServiceState,
+    start_type: ServiceStartType,
+    start_calls: u64,
+}
+
+
+/// An in-memory service control manager. Clones share the same services.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FakeServiceControl {
+    services: Rc<RefCell<HashMap<OsString, FakeService>>>,
+}
+impl FakeServiceControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a service in the given state that changes to `state_after_start` whenever it is
+    /// started.
+    pub fn add_service(&self, name: &str, state: ServiceState, state_after_start: ServiceState) {
+        let service = FakeService {
+            state,
+            state_after_start,
+            start_type: ServiceStartType::Auto,
+            start_calls: 0,
+        };
+        self.services.borrow_mut().insert(OsString::from(name), service);
+    }
+
+    pub fn set_state(&self, name: &str, state: ServiceState) {
+        self.services.borrow_mut()
+            .get_mut(OsStr::new(name))
+            .expect("unknown fake service")
+            .state = state;
+    }
+
+    /// How often the given service has been started.
+    pub fn start_calls(&self, name: &str) -> u64 {
+        self.services.borrow()
+            .get(OsStr::new(name))
+            .expect("unknown fake service")
+            .start_calls
+    }
+}
+impl ServiceControl for FakeServiceControl {
+    type Service = FakeServiceHandle;
+
+    fn open_service(&self, service_name: &OsStr, _desired_access: ServicePermissions) -> Result<Self::Service, Error> {
+        if !self.services.borrow().contains_key(service_name) {
+            return Err(ERROR_SERVICE_DOES_NOT_EXIST.to_hresult().into());
+        }
+        Ok(FakeServiceHandle {
+            services: Rc::clone(&self.services),
+            name: service_name.to_os_string(),
+        })
+    }
+
+    fn resolve_display_name(&self, _display_name: &OsStr) -> Result<OsString, Error> {
+        Err(ERROR_SERVICE_DOES_NOT_EXIST.to_hresult().into())
+    }
+
+    fn get_display_name(&self, key_name: &OsStr) -> Result<OsString, Error> {
+        Ok(key_name.to_os_string())
+    }
+}
+
+
+/// A service opened through a [`FakeServiceControl`].
+#[derive(Debug)]
+pub(crate) struct FakeServiceHandle {
+    services: Rc<RefCell<HashMap<OsString, FakeService>>>,
+    name: OsString,
+}
+impl FakeServiceHandle {
+    fn service(&self) -> FakeService {
+        *self.services.borrow()
+            .get(&self.name)
+            .expect("fake service vanished")
+    }
+}
+impl ServiceAccess for FakeServiceHandle {
+    fn start(&self, _args: Vec<&OsStr>) -> Result<(), Error> {
+        let mut services = self.services.borrow_mut();
+        let service = services.get_mut(&self.name)
+            .expect("fake service vanished");
+        service.start_calls += 1;
+        service.state = service.state_after_start;
+        Ok(())
+    }
+
+    fn get_state(&self) -> Result<ServiceState, Error> {
+        Ok(self.service().state)
+    }
+
+    fn get_process_id(&self) -> Result<Option<u32>, Error> {
+        Ok(None)
+    }
+
+    fn get_start_type(&self) -> Result<Option<ServiceStartType>, Error> {
+        Ok(Some(self.service().start_type))
+    }
+}
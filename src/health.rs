@@ -0,0 +1,24 @@
+use std::io;
+
+use crate::http_server::{HttpResponse, HttpServer};
+use crate::status::SharedStatus;
+
+
+/// Starts an HTTP server answering `GET /healthz` with 200 if all watched services are running and
+/// 503 otherwise.
+pub(crate) fn spawn_health_server(port: u16, status: SharedStatus) -> Result<HttpServer, io::Error> {
+    HttpServer::spawn("health endpoint", port, move |method, path| {
+        if path != "/healthz" {
+            return HttpResponse::not_found();
+        }
+        if method != "GET" && method != "HEAD" {
+            return HttpResponse::method_not_allowed();
+        }
+
+        if status.snapshot().is_healthy() {
+            HttpResponse::text(200, "OK", "ok\n".to_owned())
+        } else {
+            HttpResponse::text(503, "Service Unavailable", "not all watched services are running\n".to_owned())
+        }
+    })
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+
+/// The components of an `http://` URL.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct HttpUrl {
+    /// The host name or address; IPv6 addresses are stored without their brackets.
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+impl HttpUrl {
+    pub fn parse(url: &str) -> Result<Self, HttpError> {
+        let rest = match url.strip_prefix("http://") {
+            Some(r) => r,
+            None => return Err(HttpError::UnsupportedUrl(url.to_owned())),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(slash_index) => (&rest[..slash_index], &rest[slash_index..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return Err(HttpError::UnsupportedUrl(url.to_owned()));
+        }
+
+        // IPv6 literals are enclosed in brackets and contain colons themselves
+        let unsupported = || HttpError::UnsupportedUrl(url.to_owned());
+        let (host, port_str) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after_host) = bracketed.split_once(']')
+                    .ok_or_else(unsupported)?;
+                if after_host.is_empty() {
+                    (host, None)
+                } else {
+                    (host, Some(after_host.strip_prefix(':').ok_or_else(unsupported)?))
+                }
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port_str)) => (host, Some(port_str)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(unsupported());
+        }
+        let port: u16 = match port_str {
+            Some(ps) => ps.parse().map_err(|_| unsupported())?,
+            None => 80,
+        };
+
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == 80 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub(crate) enum HttpError {
+    UnsupportedUrl(String),
+    NoAddress(String),
+    Io(io::Error),
+    InvalidResponse(String),
+}
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedUrl(u) => write!(f, "unsupported URL {:?} (only plain http:// is supported)", u),
+            Self::NoAddress(h) => write!(f, "host {:?} did not resolve to any address", h),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::InvalidResponse(l) => write!(f, "invalid response status line {:?}", l),
+        }
+    }
+}
+impl std::error::Error for HttpError {
+}
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+
+
+/// Resolves a host name to its first address, giving up after `timeout`.
+///
+/// The system resolver cannot be interrupted, so the lookup runs on a separate thread, which is
+/// left to finish on its own if it takes too long.
+pub(crate) fn resolve(host: &str, port: u16, timeout: Duration) -> Result<SocketAddr, HttpError> {
+    // addresses need no lookup
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let lookup_host = host.to_owned();
+    std::thread::Builder::new()
+        .name("resolver".to_owned())
+        .spawn(move || {
+            let res = (lookup_host.as_str(), port).to_socket_addrs()
+                .map(|mut addrs| addrs.next());
+            // nobody might be waiting for the result anymore
+            let _ = sender.send(res);
+        })?;
+
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(Some(address))) => Ok(address),
+        Ok(Ok(None)) => Err(HttpError::NoAddress(host.to_owned())),
+        Ok(Err(e)) => Err(e.into()),
+        Err(RecvTimeoutError::Timeout) => Err(HttpError::Io(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("resolving host {:?} took longer than {} ms", host, timeout.as_millis()),
+        ))),
+        Err(RecvTimeoutError::Disconnected) => Err(HttpError::Io(io::Error::other(
+            format!("resolving host {:?} failed unexpectedly", host),
+        ))),
+    }
+}
+
+
+/// Performs a blocking HTTP/1.1 request and returns the response status code.
+///
+/// Every network operation is bounded by `timeout`. The response body is not read.
+pub(crate) fn request(
+    method: &str,
+    url: &HttpUrl,
+    content_type: Option<&str>,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<u16, HttpError> {
+    let address = resolve(&url.host, url.port, timeout)?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: servicerestarter\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method, url.path, url.host_header(), body.len(),
+    );
+    if let Some(ct) = content_type {
+        head.push_str("Content-Type: ");
+        head.push_str(ct);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    // we only care about the status line
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    let mut pieces = status_line.split(' ');
+    let version = pieces.next().unwrap_or("");
+    let code = pieces.next().unwrap_or("");
+    if !version.starts_with("HTTP/") {
+        return Err(HttpError::InvalidResponse(status_line));
+    }
+    code.parse()
+        .map_err(|_| HttpError::InvalidResponse(status_line.clone()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_strips_ipv6_brackets() {
+        let url = HttpUrl::parse("http://[::1]:8080/healthz").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/healthz");
+        assert_eq!(url.host_header(), "[::1]:8080");
+
+        let url = HttpUrl::parse("http://[fe80::1]").unwrap();
+        assert_eq!(url.host, "fe80::1");
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+        assert_eq!(url.host_header(), "[fe80::1]");
+
+        assert!(HttpUrl::parse("http://[::1/").is_err());
+        assert!(HttpUrl::parse("http://[::1]8080/").is_err());
+    }
+
+    #[test]
+    fn parse_host_and_port() {
+        let url = HttpUrl::parse("http://example.com:81/a/b").unwrap();
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, 81);
+        assert_eq!(url.path, "/a/b");
+        assert_eq!(url.host_header(), "example.com:81");
+
+        assert!(HttpUrl::parse("http://:81/").is_err());
+        assert!(HttpUrl::parse("https://example.com/").is_err());
+    }
+
+    #[test]
+    fn resolve_address_without_lookup() {
+        let address = resolve("::1", 8080, Duration::ZERO).unwrap();
+        assert_eq!(address, "[::1]:8080".parse().unwrap());
+    }
+}
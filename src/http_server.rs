@@ -0,0 +1,159 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error, warn};
+
+
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_HEAD_LINES: usize = 100;
+
+
+/// A response to be sent by [`HttpServer`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub reason: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+impl HttpResponse {
+    pub fn text(status: u16, reason: &'static str, body: String) -> Self {
+        Self {
+            status,
+            reason,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::text(404, "Not Found", "not found\n".to_owned())
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Self::text(405, "Method Not Allowed", "method not allowed\n".to_owned())
+    }
+}
+
+
+/// A very small HTTP/1.1 server answering one request per connection.
+///
+/// Requests are handled sequentially on a single background thread by calling the handler with
+/// the request method and path.
+pub(crate) struct HttpServer {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+impl HttpServer {
+    /// Binds to the given port on all IPv4 interfaces and starts serving on a background thread.
+    pub fn spawn<H>(name: &str, port: u16, handler: H) -> Result<Self, io::Error>
+        where H: Fn(&str, &str) -> HttpResponse + Send + 'static
+    {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        let address = listener.local_addr()?;
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thread_stopping = Arc::clone(&stopping);
+        let thread = std::thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || serve(listener, thread_stopping, handler))?;
+
+        Ok(Self {
+            address,
+            stopping,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops serving and waits for the serving thread to finish.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        let thread = match self.thread.take() {
+            Some(t) => t,
+            None => return,
+        };
+
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // the serving thread is blocked waiting for a connection; connect to wake it up
+        let wake_address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), self.address.port());
+        if let Err(e) = TcpStream::connect_timeout(&wake_address, CONNECTION_TIMEOUT) {
+            warn!("failed to connect to HTTP server on {} to stop it: {}", wake_address, e);
+        }
+
+        if thread.join().is_err() {
+            error!("HTTP server thread panicked");
+        }
+    }
+}
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+
+fn serve<H>(listener: TcpListener, stopping: Arc<AtomicBool>, handler: H)
+    where H: Fn(&str, &str) -> HttpResponse
+{
+    for stream_res in listener.incoming() {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let stream = match stream_res {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("failed to accept HTTP connection: {}", e);
+                continue;
+            },
+        };
+        if let Err(e) = handle_connection(stream, &handler) {
+            debug!("failed to handle HTTP connection: {}", e);
+        }
+    }
+}
+
+fn handle_connection<H>(mut stream: TcpStream, handler: &H) -> Result<(), io::Error>
+    where H: Fn(&str, &str) -> HttpResponse
+{
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // skip the headers; we don't care about them
+    for _ in 0..MAX_REQUEST_HEAD_LINES {
+        let mut header_line = String::new();
+        if reader.read_line(&mut header_line)? == 0 || header_line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut pieces = request_line.trim_end().split(' ');
+    let method = pieces.next().unwrap_or("");
+    let target = pieces.next().unwrap_or("");
+    let path = target.split('?').next().unwrap_or("");
+
+    let response = handler(method, path);
+    let send_body = method != "HEAD";
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, response.reason, response.content_type, response.body.len(),
+    );
+    stream.write_all(head.as_bytes())?;
+    if send_body {
+        stream.write_all(response.body.as_bytes())?;
+    }
+    stream.flush()
+}
@@ -0,0 +1,21 @@
+use std::fmt::Write;
+
+
+/// Appends `s` to `buf` as a quoted and escaped JSON string.
+pub(crate) fn push_string(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(buf, "\\u{:04x}", c as u32).unwrap();
+            },
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
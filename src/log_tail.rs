@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+
+/// How much of the file to read at once while looking for the start of the last lines.
+const CHUNK_SIZE: u64 = 4096;
+
+/// How often to check the file for appended content when following it.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+
+/// Reads the last `count` lines of the file, searching backwards from its end. Returns the bytes of
+/// the lines and the length of the file at the time.
+///
+/// A newline at the very end of the file does not count as the start of another line.
+pub(crate) fn last_lines(file: &mut File, count: usize) -> Result<(Vec<u8>, u64), io::Error> {
+    let file_length = file.seek(SeekFrom::End(0))?;
+
+    let mut tail: Vec<u8> = Vec::new();
+    let mut chunk_end = file_length;
+    let mut newlines_found = 0;
+    while chunk_end > 0 {
+        let chunk_start = chunk_end.saturating_sub(CHUNK_SIZE);
+        let mut chunk = vec![0u8; usize::try_from(chunk_end - chunk_start).unwrap()];
+        file.seek(SeekFrom::Start(chunk_start))?;
+        file.read_exact(&mut chunk)?;
+
+        for i in (0..chunk.len()).rev() {
+            if chunk[i] != b'\n' || chunk_start + (i as u64) == file_length - 1 {
+                continue;
+            }
+            newlines_found += 1;
+            if newlines_found == count {
+                // the lines start after this newline
+                chunk.drain(..=i);
+                chunk.append(&mut tail);
+                return Ok((chunk, file_length));
+            }
+        }
+
+        chunk.append(&mut tail);
+        tail = chunk;
+        chunk_end = chunk_start;
+    }
+
+    // the file has fewer lines
+    Ok((tail, file_length))
+}
+
+/// Writes the last `count` lines of the file to `output`. If `follow` is set, keeps writing
+/// whatever is appended to the file until the process is ended; if the file shrinks (e.g. because it
+/// has been replaced), it is written again from the start.
+pub(crate) fn tail<W: Write>(path: &Path, count: usize, follow: bool, output: &mut W) -> Result<(), io::Error> {
+    let mut file = File::open(path)?;
+    let (lines, mut position) = last_lines(&mut file, count)?;
+    output.write_all(&lines)?;
+    output.flush()?;
+    if !follow {
+        return Ok(());
+    }
+    drop(file);
+
+    let mut buffer = Vec::new();
+    loop {
+        sleep(FOLLOW_POLL_INTERVAL);
+
+        // open it anew each time to notice if it has been replaced
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let length = file.metadata()?.len();
+        if length < position {
+            position = 0;
+        }
+        if length == position {
+            continue;
+        }
+
+        file.seek(SeekFrom::Start(position))?;
+        buffer.clear();
+        position += file.read_to_end(&mut buffer)? as u64;
+        output.write_all(&buffer)?;
+        output.flush()?;
+    }
+}
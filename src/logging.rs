@@ -1,20 +1,154 @@
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::os::windows::io::AsRawHandle;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use chrono::Local;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Utc};
 use log::{Level, Log, Metadata, Record};
-use windows::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+use once_cell::sync::OnceCell;
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, HANDLE};
+use windows::Win32::Storage::FileSystem::{BY_HANDLE_FILE_INFORMATION, GetFileInformationByHandle};
 
+use crate::config::Config;
+use crate::console::enable_stderr_escape_sequences;
 use crate::log_panic;
-use crate::registry::{PredefinedKey, RegistryKeyHandle, RegistryPermissions, RegistryValue};
+use crate::ParameterError;
+use crate::registry::{
+    PredefinedKey, RegistryAccess, RegistryKeyHandle, RegistryPermissions, RegistryValue,
+    UnexpectedValueType,
+};
+
+
+/// Where log messages go and at which level, for the configuration summary.
+static LOG_TARGET: OnceCell<String> = OnceCell::new();
+
+
+/// The sequence number of the next log line if [`LogFormat::sequence_numbers`] is enabled; shared
+/// between all loggers.
+static LOG_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+
+/// How often to check whether the log file is still the one at its path.
+const LOG_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+
+/// Whether to color the level of messages logged to stderr.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) enum ColorMode {
+    /// Color if stderr is a console and the `NO_COLOR` environment variable is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+
+/// Which time zone the timestamps of log messages are given in.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) enum LogTimeZone {
+    #[default]
+    Local,
+    Utc,
+}
+impl LogTimeZone {
+    /// The current time in this time zone.
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        match self {
+            Self::Local => Local::now().into(),
+            Self::Utc => Utc::now().into(),
+        }
+    }
+
+    fn parse(value: &OsStr) -> Option<Self> {
+        if value.eq_ignore_ascii_case("local") {
+            Some(Self::Local)
+        } else if value.eq_ignore_ascii_case("utc") {
+            Some(Self::Utc)
+        } else {
+            None
+        }
+    }
+}
+
+
+/// How the beginning of each log line is formatted.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct LogFormat {
+    pub time_zone: LogTimeZone,
+
+    /// Whether to number the lines, which orders them even if their timestamps are the same.
+    pub sequence_numbers: bool,
+}
+impl LogFormat {
+    /// The sequence number of the next line followed by a space, or nothing if lines are not
+    /// numbered.
+    fn next_sequence_prefix(&self) -> String {
+        if self.sequence_numbers {
+            format!("#{} ", LOG_SEQUENCE.fetch_add(1, Ordering::Relaxed))
+        } else {
+            String::new()
+        }
+    }
+}
+
+
+/// How messages are split up between log files over time.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) enum LogRotation {
+    /// All messages are written to the file given by `LogPath`.
+    #[default]
+    None,
+
+    /// The messages of each day (in the time zone given by `LogTimeZone`) are written to their own
+    /// file, named like the one given by `LogPath` with the date inserted before the extension; see
+    /// [`daily_log_path`].
+    Daily,
+}
+
+
+/// The ANSI escape sequence that colors the given level.
+fn level_color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "\x1b[31m",
+        Level::Warn => "\x1b[33m",
+        Level::Info => "\x1b[32m",
+        Level::Debug => "\x1b[36m",
+        Level::Trace => "\x1b[35m",
+    }
+}
+
+const RESET_COLOR: &str = "\x1b[0m";
+
+
+/// Shortens the message to the given number of characters if it is longer, noting the original
+/// length.
+fn truncate_message(message: String, max_length: Option<usize>) -> String {
+    let max_length = match max_length {
+        Some(ml) => ml,
+        None => return message,
+    };
+    let char_count = message.chars().count();
+    if char_count <= max_length {
+        return message;
+    }
+    let cut_at = message.char_indices()
+        .nth(max_length)
+        .map(|(i, _)| i)
+        .unwrap_or(message.len());
+    format!("{}... (truncated from {} characters)", &message[..cut_at], char_count)
+}
 
 
 pub(crate) struct StderrLogger {
     pub level: Level,
+    pub color: bool,
+    pub max_message_length: Option<usize>,
+    pub format: LogFormat,
 }
 impl Log for StderrLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
@@ -23,8 +157,20 @@ impl Log for StderrLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let now = Local::now();
-            eprintln!("[{}] {:5} - {}", now.format("%Y-%m-%d %H:%M:%S%.3f %z"), record.level(), record.args());
+            let now = self.format.time_zone.now();
+            let sequence = self.format.next_sequence_prefix();
+            let message = truncate_message(record.args().to_string(), self.max_message_length);
+            if self.color {
+                eprintln!(
+                    "[{}] {}{}{:5}{} - {}",
+                    now.format("%Y-%m-%d %H:%M:%S%.3f %z"),
+                    sequence,
+                    level_color(record.level()), record.level(), RESET_COLOR,
+                    message,
+                );
+            } else {
+                eprintln!("[{}] {}{:5} - {}", now.format("%Y-%m-%d %H:%M:%S%.3f %z"), sequence, record.level(), message);
+            }
         }
     }
 
@@ -32,28 +178,254 @@ impl Log for StderrLogger {
 }
 
 
-pub(crate) struct WriterLogger<W: Send + Write> {
+/// Something log messages can be written to.
+pub(crate) trait LogWriter: Send + Write {
+    /// Called with the timestamp of each message before it is written, e.g. to switch to a
+    /// different file.
+    fn prepare(&mut self, _now: &DateTime<FixedOffset>) {
+    }
+}
+
+
+/// The name of the log file of the given day if daily rotation is enabled: the file name of
+/// `base_path` with the date inserted before the extension, e.g. `C:\logs\servicerestarter.log`
+/// becomes `C:\logs\servicerestarter-2024-06-01.log`.
+pub(crate) fn daily_log_path(base_path: &Path, date: NaiveDate) -> PathBuf {
+    let mut file_name = base_path.file_stem()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push(format!("-{}", date.format("%Y-%m-%d")));
+    if let Some(extension) = base_path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    base_path.with_file_name(file_name)
+}
+
+/// The date in the name of a daily log file derived from `base_path`, or `None` if `file_name` is
+/// not the name of such a file.
+fn daily_log_date(base_path: &Path, file_name: &OsStr) -> Option<NaiveDate> {
+    let stem = base_path.file_stem()?.to_str()?;
+    let suffix = match base_path.extension() {
+        Some(extension) => format!(".{}", extension.to_str()?),
+        None => String::new(),
+    };
+    let date_str = file_name.to_str()?
+        .strip_prefix(stem)?
+        .strip_prefix('-')?
+        .strip_suffix(suffix.as_str())?;
+    NaiveDate::parse_from_str(date_str, "%Y-%m-%d").ok()
+}
+
+/// Identifies the file independently of its path: its volume serial number and file index.
+fn file_identity(file: &File) -> Option<(u32, u32, u32)> {
+    let mut info = BY_HANDLE_FILE_INFORMATION::default();
+    let succeeded = unsafe {
+        GetFileInformationByHandle(HANDLE(file.as_raw_handle() as isize), &mut info)
+    }.as_bool();
+    if succeeded {
+        Some((info.dwVolumeSerialNumber, info.nFileIndexHigh, info.nFileIndexLow))
+    } else {
+        None
+    }
+}
+
+
+/// A log file that is reopened if it is deleted or moved away, e.g. by an external log rotation
+/// tool; otherwise, messages would keep going to a file that is no longer visible at its path.
+///
+/// Whether the file at the path is still the open one is checked every
+/// [`LOG_FILE_CHECK_INTERVAL`] and whenever writing fails. Once the file has been replaced, it is
+/// created anew if necessary.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    last_checked: Instant,
+}
+impl LogFile {
+    fn open(path: PathBuf, create: bool) -> Result<Self, io::Error> {
+        let file = File::options()
+            .append(true)
+            .create(create)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            last_checked: Instant::now(),
+        })
+    }
+
+    /// Whether the file at the path is no longer the open one.
+    fn is_replaced(&self) -> bool {
+        match File::open(&self.path) {
+            Ok(at_path) => {
+                let (open_identity, path_identity) = (file_identity(&self.file), file_identity(&at_path));
+                // if in doubt, keep the file
+                open_identity.is_some() && path_identity.is_some() && open_identity != path_identity
+            },
+            Err(e) => e.kind() == io::ErrorKind::NotFound,
+        }
+    }
+
+    /// Switches to the file at the path if it is no longer the open one.
+    ///
+    /// Problems are reported on stderr; logging them would try to write to this very file.
+    fn reopen_if_replaced(&mut self) {
+        self.last_checked = Instant::now();
+        if !self.is_replaced() {
+            return;
+        }
+        match Self::open(self.path.clone(), true) {
+            Ok(reopened) => *self = reopened,
+            Err(e) => eprintln!("failed to reopen log file {:?}; still writing to the previous one: {}", self.path, e),
+        }
+    }
+}
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        match self.file.write(buf) {
+            Ok(written) => Ok(written),
+            Err(e) => {
+                // the file might have been replaced in a way that invalidated our handle
+                self.reopen_if_replaced();
+                self.file.write(buf)
+                    .map_err(|_| e)
+            },
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.file.flush()
+    }
+}
+impl LogWriter for LogFile {
+    fn prepare(&mut self, _now: &DateTime<FixedOffset>) {
+        if self.last_checked.elapsed() >= LOG_FILE_CHECK_INTERVAL {
+            self.reopen_if_replaced();
+        }
+    }
+}
+
+
+/// A log file that is replaced by a new one every day; see [`LogRotation::Daily`].
+struct DailyLogFile {
+    base_path: PathBuf,
+
+    /// How many daily files to keep (including the current one); `None` to keep all of them.
+    max_files: Option<usize>,
+
+    date: NaiveDate,
+    file: LogFile,
+}
+impl DailyLogFile {
+    fn open(base_path: PathBuf, max_files: Option<usize>, date: NaiveDate) -> Result<Self, io::Error> {
+        let file = LogFile::open(daily_log_path(&base_path, date), true)?;
+        let daily_file = Self {
+            base_path,
+            max_files,
+            date,
+            file,
+        };
+        daily_file.prune();
+        Ok(daily_file)
+    }
+
+    /// Deletes the oldest daily files beyond `max_files`.
+    ///
+    /// Problems are reported on stderr; logging them would try to write to this very file.
+    fn prune(&self) {
+        let max_files = match self.max_files {
+            Some(mf) => mf,
+            None => return,
+        };
+        let directory = match self.base_path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        let entries = match std::fs::read_dir(directory) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("failed to list log directory {:?} to prune old log files: {}", directory, e);
+                return;
+            },
+        };
+
+        let mut dated_files: Vec<(NaiveDate, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let date = daily_log_date(&self.base_path, &entry.file_name())?;
+                Some((date, entry.path()))
+            })
+            .collect();
+        dated_files.sort_unstable_by_key(|(date, _)| std::cmp::Reverse(*date));
+        for (_, path) in dated_files.iter().skip(max_files) {
+            if let Err(e) = std::fs::remove_file(path) {
+                eprintln!("failed to delete old log file {:?}: {}", path, e);
+            }
+        }
+    }
+}
+impl Write for DailyLogFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.file.flush()
+    }
+}
+impl LogWriter for DailyLogFile {
+    fn prepare(&mut self, now: &DateTime<FixedOffset>) {
+        let today = now.date().naive_local();
+        if today == self.date {
+            self.file.prepare(now);
+            return;
+        }
+
+        // if the new file cannot be opened, keep using the old one and try again next time
+        let path = daily_log_path(&self.base_path, today);
+        match LogFile::open(path.clone(), true) {
+            Ok(file) => {
+                self.file = file;
+                self.date = today;
+                self.prune();
+            },
+            Err(e) => eprintln!("failed to open log file {:?}; still writing to the previous one: {}", path, e),
+        }
+    }
+}
+
+
+pub(crate) struct WriterLogger<W: LogWriter> {
     pub level: Level,
+    pub max_message_length: Option<usize>,
+    pub format: LogFormat,
     writer: Mutex<W>,
 }
-impl<W: Send + Write> WriterLogger<W> {
-    pub fn new(level: Level, writer: W) -> Self {
+impl<W: LogWriter> WriterLogger<W> {
+    pub fn new(level: Level, max_message_length: Option<usize>, format: LogFormat, writer: W) -> Self {
         Self {
             level,
+            max_message_length,
+            format,
             writer: Mutex::new(writer),
         }
     }
 }
-impl<W: Send + Write> Log for WriterLogger<W> {
+impl<W: LogWriter> Log for WriterLogger<W> {
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level() <= self.level
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let now = Local::now();
+            let now = self.format.time_zone.now();
             let mut writer_guard = self.writer.lock().expect("failed to lock file");
-            let write_res = writeln!(writer_guard, "[{}] {:5} - {}", now.format("%Y-%m-%d %H:%M:%S%.3f %z"), record.level(), record.args());
+            writer_guard.prepare(&now);
+            // numbered while holding the lock, so that the numbers appear in order in the file
+            let sequence = self.format.next_sequence_prefix();
+            let message = truncate_message(record.args().to_string(), self.max_message_length);
+            let write_res = writeln!(writer_guard, "[{}] {}{:5} - {}", now.format("%Y-%m-%d %H:%M:%S%.3f %z"), sequence, record.level(), message);
             if let Err(e) = write_res {
                 eprintln!("failed to write to log writer: {}", e);
             }
@@ -64,78 +436,266 @@ impl<W: Send + Write> Log for WriterLogger<W> {
 }
 
 
-pub(crate) fn enable_stderr(level: Level) {
+pub(crate) fn enable_stderr(level: Level, color_mode: ColorMode, format: LogFormat) {
+    let color = match color_mode {
+        ColorMode::Always => {
+            // best effort; the user has asked for it
+            enable_stderr_escape_sequences();
+            true
+        },
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            let no_color = std::env::var_os("NO_COLOR")
+                .map(|nc| !nc.is_empty())
+                .unwrap_or(false);
+            !no_color && enable_stderr_escape_sequences()
+        },
+    };
     let log_res = log::set_boxed_logger(Box::new(StderrLogger {
         level,
+        color,
+        max_message_length: None,
+        format,
     }));
-    if let Err(e) = log_res {
-        eprintln!("failed to set logger: {}", e);
+    match log_res {
+        Ok(()) => {
+            let _ = LOG_TARGET.set(format!("stderr at level {}", level));
+        },
+        Err(e) => eprintln!("failed to set logger: {}", e),
     }
 }
 
-pub(crate) fn enable_file(level: Level, path: &Path) {
-    let file = File::options()
-        .append(true)
-        .open(path)
-        .expect("failed to open log file");
-    let log_res = log::set_boxed_logger(Box::new(WriterLogger::new(
-        level,
-        file,
-    )));
-    if let Err(e) = log_res {
-        eprintln!("failed to set logger: {}", e);
+/// Sets up logging to the given file or, with [`LogRotation::Daily`], to the daily files derived
+/// from it, of which at most `max_files` are kept.
+///
+/// Without rotation, the file must already exist; daily files are created as needed.
+pub(crate) fn enable_file(level: Level, max_message_length: Option<usize>, format: LogFormat, path: &Path, rotation: LogRotation, max_files: Option<usize>) {
+    let (log_res, target) = match rotation {
+        LogRotation::None => {
+            let file = LogFile::open(path.to_path_buf(), false)
+                .expect("failed to open log file");
+            let res = log::set_boxed_logger(Box::new(WriterLogger::new(
+                level,
+                max_message_length,
+                format,
+                file,
+            )));
+            (res, format!("file {:?} at level {}", path, level))
+        },
+        LogRotation::Daily => {
+            let daily_file = DailyLogFile::open(path.to_path_buf(), max_files, format.time_zone.now().date().naive_local())
+                .expect("failed to open log file");
+            let res = log::set_boxed_logger(Box::new(WriterLogger::new(
+                level,
+                max_message_length,
+                format,
+                daily_file,
+            )));
+            (res, format!("daily files based on {:?} at level {}", path, level))
+        },
+    };
+    match log_res {
+        Ok(()) => {
+            let _ = LOG_TARGET.set(target);
+        },
+        Err(e) => eprintln!("failed to set logger: {}", e),
     }
 }
 
-pub(crate) fn enable_file_from_registry(top_key: PredefinedKey, sub_key: &OsStr) {
-    // open registry
+/// Describes where log messages are written, e.g. `file "C:\servicerestarter.log" at level INFO`.
+pub(crate) fn log_target() -> &'static str {
+    LOG_TARGET.get()
+        .map(|t| t.as_str())
+        .unwrap_or("nowhere")
+}
+
+/// Opens the registry key holding the logging parameters, or returns `None` if it does not exist.
+fn open_logging_registry(top_key: PredefinedKey, sub_key: &OsStr) -> Option<RegistryKeyHandle> {
     let registry_res = RegistryKeyHandle::open_predefined(
         top_key,
         Some(sub_key),
-        RegistryPermissions::QUERY_VALUE,
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
     );
-    let registry = match registry_res {
-        Ok(r) => r,
+    match registry_res {
+        Ok(r) => Some(r),
         Err(e) => {
-            if e.win32_error().map(|w| w == ERROR_FILE_NOT_FOUND).unwrap_or(false) {
-                // registry key does not exist
-                return;
+            if !e.win32_error().map(|w| w == ERROR_FILE_NOT_FOUND).unwrap_or(false) {
+                log_panic!("failed to open logging registry key: {}", e);
             }
-            log_panic!("failed to open logging registry key: {}", e);
+            // registry key does not exist
+            None
         },
-    };
+    }
+}
 
-    // read the path
-    let path_res = registry.read_value_optional(Some(&OsString::from("LogPath")));
+/// Reads `LogPath` from the configuration file if it sets it and from the registry otherwise.
+fn read_log_path(registry: Option<&RegistryKeyHandle>, file_config: Option<&Config>) -> Option<OsString> {
+    if let Some(fp) = file_config.and_then(|fc| fc.log_path.clone()) {
+        return Some(fp);
+    }
+
+    let path_res = registry?.read_value_optional(Some(&OsString::from("LogPath")));
     let path_val = match path_res {
         Ok(Some(p)) => p,
         Ok(None) => {
             // registry value does not exist
-            return;
+            return None;
         },
         Err(e) => log_panic!("failed to read LogPath value: {}", e),
     };
-    let path = match path_val {
-        RegistryValue::String(s) => s,
-        RegistryValue::ExpandString { unexpanded: _, expanded: s } => s,
-        other => log_panic!("LogPath has unexpected type: {:?}", other),
+    match path_val {
+        RegistryValue::String(s) => Some(s),
+        RegistryValue::ExpandString { unexpanded: _, expanded: s } => Some(s),
+        other => log_panic!("{}", UnexpectedValueType::new("LogPath", "REG_SZ or REG_EXPAND_SZ", &other)),
+    }
+}
+
+/// Reads `LogRotation` from the registry; without it, the log file is not rotated.
+pub(crate) fn try_read_log_rotation<R: RegistryAccess>(registry: &R) -> Result<LogRotation, ParameterError> {
+    let rotation_res = registry.read_value_optional(Some(&OsString::from("LogRotation")));
+    let rotation_str = match rotation_res {
+        Ok(None) => return Ok(LogRotation::None),
+        Ok(Some(RegistryValue::String(s))) => s,
+        Ok(Some(other)) => return Err(UnexpectedValueType::new("LogRotation", "REG_SZ", &other).into()),
+        Err(error) => return Err(ParameterError::Read { name: "LogRotation".to_owned(), error }),
     };
+    if rotation_str.eq_ignore_ascii_case("none") {
+        Ok(LogRotation::None)
+    } else if rotation_str.eq_ignore_ascii_case("daily") {
+        Ok(LogRotation::Daily)
+    } else {
+        Err(ParameterError::Invalid {
+            name: "LogRotation".to_owned(),
+            value: rotation_str.to_string_lossy().into_owned(),
+            message: "expected none or daily".to_owned(),
+        })
+    }
+}
 
-    // read the log level
-    let level_res = registry.read_value_optional(Some(&OsString::from("LogLevel")));
-    let level_val = match level_res {
-        Ok(Some(l)) => l,
-        Ok(None) => {
-            // registry value does not exist; use a default (Error)
-            RegistryValue::Dword((Level::Error as usize) as u32)
+fn read_log_rotation<R: RegistryAccess>(registry: Option<&R>) -> LogRotation {
+    match registry {
+        Some(r) => try_read_log_rotation(r)
+            .unwrap_or_else(|e| log_panic!("{}", e)),
+        None => LogRotation::None,
+    }
+}
+
+/// Reads `LogTimeZone` from the registry; without it, timestamps are in local time.
+fn read_log_time_zone<R: RegistryAccess>(registry: Option<&R>) -> LogTimeZone {
+    let registry = match registry {
+        Some(r) => r,
+        None => return LogTimeZone::Local,
+    };
+    let time_zone_res = registry.read_value_optional(Some(&OsString::from("LogTimeZone")));
+    let time_zone_str = match time_zone_res {
+        Ok(None) => return LogTimeZone::Local,
+        Ok(Some(RegistryValue::String(s))) => s,
+        Ok(Some(other)) => log_panic!("{}", UnexpectedValueType::new("LogTimeZone", "REG_SZ", &other)),
+        Err(e) => log_panic!("failed to read LogTimeZone value: {}", e),
+    };
+    match LogTimeZone::parse(&time_zone_str) {
+        Some(tz) => tz,
+        None => log_panic!("unknown LogTimeZone {:?}; expected local or utc", time_zone_str),
+    }
+}
+
+/// Reads `LogSequenceNumbers` from the registry; without it, lines are not numbered.
+fn read_log_sequence_numbers<R: RegistryAccess>(registry: Option<&R>) -> bool {
+    let registry = match registry {
+        Some(r) => r,
+        None => return false,
+    };
+    match registry.read_value_optional(Some(&OsString::from("LogSequenceNumbers"))) {
+        Ok(None) => false,
+        Ok(Some(RegistryValue::Dword(d))) => d != 0,
+        Ok(Some(RegistryValue::Qword(d))) => d != 0,
+        Ok(Some(other)) => log_panic!("{}", UnexpectedValueType::new("LogSequenceNumbers", "REG_DWORD or REG_QWORD", &other)),
+        Err(e) => log_panic!("failed to read LogSequenceNumbers value: {}", e),
+    }
+}
+
+/// The format given by `LogTimeZone` and `LogSequenceNumbers` in the registry, for logging to
+/// stderr.
+///
+/// Since this is read before any logging is set up, problems with the values are not reported;
+/// the defaults are used instead.
+pub(crate) fn configured_log_format(top_key: PredefinedKey, sub_key: &OsStr) -> LogFormat {
+    let registry_res = RegistryKeyHandle::open_predefined(
+        top_key,
+        Some(sub_key),
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
+    );
+    let registry = match registry_res {
+        Ok(r) => r,
+        Err(_) => return LogFormat::default(),
+    };
+    let read = |name: &str| registry.read_value_optional(Some(&OsString::from(name))).ok().flatten();
+    let time_zone = match read("LogTimeZone") {
+        Some(RegistryValue::String(s)) => LogTimeZone::parse(&s).unwrap_or_default(),
+        _ => LogTimeZone::Local,
+    };
+    let sequence_numbers = match read("LogSequenceNumbers") {
+        Some(RegistryValue::Dword(d)) => d != 0,
+        Some(RegistryValue::Qword(d)) => d != 0,
+        _ => false,
+    };
+    LogFormat {
+        time_zone,
+        sequence_numbers,
+    }
+}
+
+/// The file given by `LogPath` in the configuration file or the registry, if any. With daily
+/// rotation, this is the file of the current day.
+pub(crate) fn configured_log_path(top_key: PredefinedKey, sub_key: &OsStr, file_config: Option<&Config>) -> Option<OsString> {
+    let registry = open_logging_registry(top_key, sub_key);
+    let path = read_log_path(registry.as_ref(), file_config)?;
+    match read_log_rotation(registry.as_ref()) {
+        LogRotation::None => Some(path),
+        LogRotation::Daily => {
+            let today = read_log_time_zone(registry.as_ref()).now().date().naive_local();
+            Some(daily_log_path(Path::new(&path), today).into_os_string())
         },
-        Err(e) => log_panic!("failed to read LogLevel value: {}", e),
+    }
+}
+
+/// Sets up logging to the file given by `LogPath` at the level given by `LogLevel`.
+///
+/// The values are taken from the configuration file if it sets them and from the registry
+/// otherwise. If neither sets `LogPath`, logging stays disabled.
+pub(crate) fn enable_file_from_registry(top_key: PredefinedKey, sub_key: &OsStr, file_config: Option<&Config>) {
+    let file_level = file_config.and_then(|fc| fc.log_level);
+
+    // open registry
+    let registry = open_logging_registry(top_key, sub_key);
+
+    // read the path
+    let path = match read_log_path(registry.as_ref(), file_config) {
+        Some(p) => p,
+        None => return,
     };
-    let level_int = match level_val {
-        RegistryValue::Dword(d) => d.into(),
-        RegistryValue::DwordBigEndian(d) => d.into(),
-        RegistryValue::Qword(d) => d,
-        other => log_panic!("LogLevel has unexpected type: {:?}", other),
+
+    // read the log level
+    let level_int = match (file_level, &registry) {
+        (Some(fl), _) => fl,
+        (None, Some(registry)) => {
+            let level_res = registry.read_value_optional(Some(&OsString::from("LogLevel")));
+            let level_val = match level_res {
+                Ok(Some(l)) => l,
+                Ok(None) => {
+                    // registry value does not exist; use a default (Error)
+                    RegistryValue::Dword((Level::Error as usize) as u32)
+                },
+                Err(e) => log_panic!("failed to read LogLevel value: {}", e),
+            };
+            match level_val {
+                RegistryValue::Dword(d) => d.into(),
+                RegistryValue::DwordBigEndian(d) => d.into(),
+                RegistryValue::Qword(d) => d,
+                other => log_panic!("{}", UnexpectedValueType::new("LogLevel", "REG_DWORD or REG_QWORD", &other)),
+            }
+        },
+        (None, None) => Level::Error as u64,
     };
     let int_to_level: BTreeMap<usize, Level> = Level::iter()
         .map(|l| (l as usize, l))
@@ -148,6 +708,39 @@ pub(crate) fn enable_file_from_registry(top_key: PredefinedKey, sub_key: &OsStr)
         *int_to_level.get(max_level).unwrap()
     };
 
+    // read the maximum message length
+    let max_message_length = match &registry {
+        Some(registry) => match registry.read_value_optional(Some(&OsString::from("LogMaxMessageLength"))) {
+            Ok(None) => None,
+            Ok(Some(RegistryValue::Dword(0))) | Ok(Some(RegistryValue::Qword(0))) => None,
+            Ok(Some(RegistryValue::Dword(d))) => Some(d as usize),
+            Ok(Some(RegistryValue::Qword(d))) => Some(usize::try_from(d).unwrap_or(usize::MAX)),
+            Ok(Some(other)) => log_panic!("{}", UnexpectedValueType::new("LogMaxMessageLength", "REG_DWORD or REG_QWORD", &other)),
+            Err(e) => log_panic!("failed to read LogMaxMessageLength value: {}", e),
+        },
+        None => None,
+    };
+
+    // read how to format the beginning of each line
+    let format = LogFormat {
+        time_zone: read_log_time_zone(registry.as_ref()),
+        sequence_numbers: read_log_sequence_numbers(registry.as_ref()),
+    };
+
+    // read how to rotate the file and how many files to keep
+    let rotation = read_log_rotation(registry.as_ref());
+    let max_files = match &registry {
+        Some(registry) => match registry.read_value_optional(Some(&OsString::from("LogMaxFiles"))) {
+            Ok(None) => None,
+            Ok(Some(RegistryValue::Dword(0))) | Ok(Some(RegistryValue::Qword(0))) => None,
+            Ok(Some(RegistryValue::Dword(d))) => Some(d as usize),
+            Ok(Some(RegistryValue::Qword(d))) => Some(usize::try_from(d).unwrap_or(usize::MAX)),
+            Ok(Some(other)) => log_panic!("{}", UnexpectedValueType::new("LogMaxFiles", "REG_DWORD or REG_QWORD", &other)),
+            Err(e) => log_panic!("failed to read LogMaxFiles value: {}", e),
+        },
+        None => None,
+    };
+
     // set it up
-    enable_file(level, &PathBuf::from(path))
+    enable_file(level, max_message_length, format, &PathBuf::from(path), rotation, max_files)
 }
@@ -1,384 +1,3414 @@
 mod args;
+mod config;
+mod config_file;
+mod console;
+mod duration;
 mod extensions;
+#[cfg(test)]
+mod fakes;
+mod health;
+mod http;
+mod http_server;
+mod json;
+mod log_tail;
 mod logging;
+mod metrics;
+mod named_mutex;
+mod notify;
+mod power;
+mod privileges;
+mod probe;
+mod process;
+mod reg_file;
 mod registry;
+mod rng;
+mod schedule;
+mod self_test;
 mod service_control;
 mod service_running;
+mod stats;
+mod status;
+mod status_pipe;
+mod timestamp;
+mod tracking;
 mod wait_stopper;
+mod watchdog;
 mod windows_utils;
 
 
-use std::ffi::{OsStr, OsString};
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_void, OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use log::Level;
-use once_cell::sync::OnceCell;
-use windows::core::PWSTR;
-use windows::Win32::Foundation::NO_ERROR;
+use chrono::Local;
+use log::{debug, error, info, warn};
+use once_cell::sync::{Lazy, OnceCell};
+use windows::core::{Error, PWSTR};
+use windows::Win32::Foundation::{
+    BOOL, ERROR_ACCESS_DENIED, ERROR_CALL_NOT_IMPLEMENTED, ERROR_FILE_NOT_FOUND,
+    ERROR_SERVICE_DATABASE_LOCKED, ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SERVICE_MARKED_FOR_DELETE,
+    ERROR_SERVICE_REQUEST_TIMEOUT, NO_ERROR,
+};
+use windows::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
 use windows::Win32::System::Services::{
-    SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_STOP, SERVICE_RUNNING,
-    SERVICE_STATUS, SERVICE_STOPPED, SERVICE_WIN32_OWN_PROCESS,
+    SERVICE_ACCEPT_PARAMCHANGE, SERVICE_ACCEPT_POWEREVENT, SERVICE_ACCEPT_SESSIONCHANGE,
+    SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+    SERVICE_CONTROL_PARAMCHANGE, SERVICE_CONTROL_POWEREVENT, SERVICE_CONTROL_SESSIONCHANGE,
+    SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_STATUS,
+    SERVICE_START_PENDING, SERVICE_STATUS_CURRENT_STATE, SERVICE_STOPPED, SERVICE_WIN32_OWN_PROCESS, SERVICE_WIN32_SHARE_PROCESS,
+};
+use windows::Win32::System::SystemServices::SE_DEBUG_NAME;
+
+use crate::args::{Args, OperMode};
+use crate::config::{Config, config_file_path, without_comments, without_duplicates};
+use crate::config_file::{parameter_kind, to_registry_value};
+use crate::console::add_console_ctrl_handler;
+use crate::duration::parse_duration;
+use crate::extensions::ExpectExtension;
+use crate::health::spawn_health_server;
+use crate::http_server::HttpServer;
+use crate::log_tail::tail;
+use crate::logging::LogRotation;
+use crate::metrics::spawn_metrics_server;
+use crate::named_mutex::{NamedMutex, service_mutex_name};
+use crate::notify::{notify_mail, notify_webhook, RestartEvent, SmtpSettings};
+use crate::power::PowerState;
+use crate::privileges::ensure_privilege;
+use crate::probe::{Probe, ProbeOutcome};
+use crate::process::{CommandOutcome, ProcessHandle, quote_argument, run_command, split_command_line};
+use crate::reg_file::{keys_to_reg_file, to_reg_file};
+use crate::registry::{
+    PredefinedKey, RegistryAccess, RegistryChangeFilter, RegistryKeyHandle, RegistryPermissions,
+    RegistryValue, UnexpectedValueType,
+};
+use crate::rng::XorShiftRng;
+use crate::schedule::{is_within_windows, RestartWindow};
+use crate::self_test::self_test;
+use crate::service_control::{
+    LockedRetryPolicy, LockStatus, ServiceAccess, ServiceConfig, ServiceControl, ServiceControlManagerHandle, ServiceEntry,
+    ServiceControlManagerPermissions, ServiceErrorControl, ServiceInstallOptions, ServicePermissions, ServiceStartType,
+    ServiceState, ServiceType, validate_service_name,
+};
+use crate::service_running::{
+    register_service_control_handler_ex, ServiceStatusHandle, ServiceTableEntry,
+    start_service_dispatcher,
 };
+use crate::stats::RestartStatistics;
+use crate::status::{CheckSummary, PauseReason, SharedStatus};
+use crate::status_pipe::StatusPipe;
+use crate::tracking::RestartTracker;
+use crate::wait_stopper::{StopReason, WaitStopper};
+use crate::watchdog::Watchdog;
+use crate::windows_utils::WideString;
+
+
+struct ServiceInfo {
+    pub wait_stopper: WaitStopper,
+    /// The status handle; only set if running as a service.
+    pub service_status_handle: OnceCell<ServiceStatusHandle>,
+
+    /// Whether this service shares its process with other services.
+    pub shared_process: bool,
+
+    /// The checkpoint last reported while starting up.
+    checkpoint: AtomicU32,
+
+    /// Set once the system has announced that it is shutting down.
+    pub shutting_down: AtomicBool,
+
+    /// Suspend/resume and session changes announced by the system.
+    pub power_state: PowerState,
+}
+impl ServiceInfo {
+    pub fn new(shared_process: bool) -> Self {
+        Self {
+            wait_stopper: WaitStopper::new(),
+            service_status_handle: OnceCell::new(),
+            shared_process,
+            checkpoint: AtomicU32::new(0),
+            shutting_down: AtomicBool::new(false),
+            power_state: PowerState::new(),
+        }
+    }
+
+    /// Reports the given state to the service control manager. Failing to announce that we are
+    /// running is fatal; other failures are only logged.
+    pub fn set_state(&self, state: SERVICE_STATUS_CURRENT_STATE, controls_accepted: u32) {
+        self.checkpoint.store(0, Ordering::SeqCst);
+        if let Err(e) = self.report_status(state, controls_accepted, 0, Duration::ZERO) {
+            if state == SERVICE_RUNNING {
+                log_panic!("failed to set service status: {}", e);
+            }
+            error!("failed to set service status: {}", e);
+        }
+    }
+
+    /// Tells the service control manager that we are still starting up and that the next step
+    /// should take at most `wait_hint`. Each call advances the checkpoint.
+    pub fn report_start_pending(&self, wait_hint: Duration) {
+        let checkpoint = self.checkpoint.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Err(e) = self.report_status(SERVICE_START_PENDING, 0, checkpoint, wait_hint) {
+            error!("failed to report startup progress: {}", e);
+        }
+    }
+
+    fn report_status(
+        &self,
+        state: SERVICE_STATUS_CURRENT_STATE,
+        controls_accepted: u32,
+        checkpoint: u32,
+        wait_hint: Duration,
+    ) -> Result<(), Error> {
+        let service_type = if self.shared_process {
+            SERVICE_WIN32_SHARE_PROCESS
+        } else {
+            SERVICE_WIN32_OWN_PROCESS
+        };
+        let service_status = SERVICE_STATUS {
+            dwServiceType: service_type,
+            dwCurrentState: state,
+            dwControlsAccepted: controls_accepted,
+            dwWin32ExitCode: NO_ERROR.0,
+            dwServiceSpecificExitCode: NO_ERROR.0,
+            dwCheckPoint: checkpoint,
+            dwWaitHint: u32::try_from(wait_hint.as_millis()).unwrap_or(u32::MAX),
+        };
+        self.service_status_handle
+            .get().expect_log("not running as a service?!")
+            .set_status(service_status)
+    }
+}
+
+
+/// The services run by this process, keyed by their lowercased name.
+///
+/// Entries are never removed, which keeps the pointers passed as context to the service control
+/// handler valid.
+static SERVICE_INFO: Lazy<RwLock<HashMap<OsString, Arc<ServiceInfo>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Ensures that the warning about a ServicesExpectedRunning that is not a REG_MULTI_SZ is only
+/// output once instead of at every check.
+static WARNED_ABOUT_SERVICE_LIST_TYPE: AtomicBool = AtomicBool::new(false);
+
+/// Ensures that the warning about SmtpServer being set without SmtpFrom or SmtpTo is only output
+/// once instead of at every check.
+static WARNED_ABOUT_INCOMPLETE_SMTP: AtomicBool = AtomicBool::new(false);
+
+const DEFAULT_MINIMUM_SLEEP_DURATION: Duration = Duration::from_millis(1000);
+
+/// How long commands such as `RestartCommand` may run before they are terminated.
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// After how long persisted restart attempts are forgotten.
+const DEFAULT_RESTART_ATTEMPT_RESET: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long network liveness probes may take.
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long each step of starting up as a service may take before the service control manager
+/// assumes that we are stuck.
+const STARTUP_STEP_WAIT_HINT: Duration = Duration::from_secs(10);
+
+/// How long after a resume or session change restarts are held off.
+const DEFAULT_POWER_SETTLE_DURATION: Duration = Duration::from_secs(60);
+
+/// How many consecutive failed liveness probes it takes to consider a service hung.
+const DEFAULT_PROBE_FAILURE_THRESHOLD: u64 = 3;
+
+/// The exit code given to the processes of hung services when terminating them.
+const WEDGED_EXIT_CODE: u32 = 1;
+
+/// How long to wait for a terminated service to be considered stopped.
+const KILL_WAIT: Duration = Duration::from_secs(30);
+
+/// How often to check whether a terminated service is considered stopped.
+const KILL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long `delete` waits for a service to stop by default.
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often `delete` checks whether a service has stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long `install --start` waits for a service to be running.
+const START_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often `install --start` checks whether a service is running.
+const START_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long `reinstall` waits for a deleted service to disappear.
+const DELETE_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often `reinstall` checks whether a deleted service has disappeared.
+const DELETE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often `install` tries to create or update a service that is pending deletion.
+const MARKED_FOR_DELETE_ATTEMPTS: u32 = 5;
+
+/// How long `install` waits between attempts while a service is pending deletion.
+const MARKED_FOR_DELETE_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Exit code of `check` if at least one service was started and no errors occurred.
+const EXIT_CHECK_STARTED: i32 = 2;
+
+/// Exit code of `check` if errors occurred.
+const EXIT_CHECK_ERRORS: i32 = 3;
+
+/// Exit code of `validate` if problems were found.
+const EXIT_VALIDATE_PROBLEMS: i32 = 1;
+
+/// Exit code of `import-config` if nothing was imported due to problems.
+const EXIT_IMPORT_FAILED: i32 = 1;
+
+/// Exit code of `logs` if the log file cannot be found or read.
+const EXIT_LOGS_FAILED: i32 = 1;
+
+/// How many lines `logs` outputs by default.
+const DEFAULT_LOG_LINES: usize = 20;
+
+/// Exit code of `start`, `stop`, `install` and `delete` for errors not covered by a more specific
+/// code.
+const EXIT_CONTROL_FAILED: i32 = 1;
+
+/// Exit code of `start`, `stop`, `install` and `delete` if the service does not exist.
+const EXIT_SERVICE_NOT_FOUND: i32 = 2;
+
+/// Exit code of `start`, `stop`, `install` and `delete` if we are not allowed to control the
+/// service.
+const EXIT_ACCESS_DENIED: i32 = 3;
+
+/// Exit code of `start`, `stop`, `install` and `delete` if the service or the service control
+/// manager did not respond in time.
+const EXIT_TIMEOUT: i32 = 4;
+
+
+/// The key of a service in [`SERVICE_INFO`]; service names are case-insensitive.
+fn service_info_key(service_name: &OsStr) -> OsString {
+    OsString::from(service_name.to_string_lossy().to_lowercase())
+}
+
+/// Adds a service to be run by this process.
+fn register_service_info(service_name: &OsStr, shared_process: bool) {
+    SERVICE_INFO
+        .write().expect_log("SERVICE_INFO poisoned")
+        .insert(service_info_key(service_name), Arc::new(ServiceInfo::new(shared_process)));
+}
+
+/// Obtains the information about a service run by this process.
+fn service_info(service_name: &OsStr) -> Arc<ServiceInfo> {
+    let services = SERVICE_INFO
+        .read().expect_log("SERVICE_INFO poisoned");
+    match services.get(&service_info_key(service_name)) {
+        Some(si) => Arc::clone(si),
+        None => log_panic!("service {:?} is not run by this process", service_name),
+    }
+}
+
+/// Whether the system has announced that it is shutting down.
+fn is_shutting_down() -> bool {
+    SERVICE_INFO
+        .read().expect_log("SERVICE_INFO poisoned")
+        .values()
+        .any(|si| si.shutting_down.load(Ordering::SeqCst))
+}
+
+/// Whether the system is suspending or has resumed or switched sessions less than
+/// `settle_duration` ago.
+fn is_settling(settle_duration: Duration) -> bool {
+    SERVICE_INFO
+        .read().expect_log("SERVICE_INFO poisoned")
+        .values()
+        .any(|si| si.power_state.is_settling(settle_duration))
+}
+
+
+/// The service name and the key below `HKEY_LOCAL_MACHINE` given using `--params-key`, which
+/// replaces the default parameters key of that service (and only that one, even if the process
+/// hosts more than one service).
+static PARAMETERS_KEY_OVERRIDE: OnceCell<(OsString, OsString)> = OnceCell::new();
+
+
+/// The key given using `--params-key` for the given service, if any.
+fn parameters_key_override(service_name: &OsStr) -> Option<&'static OsString> {
+    PARAMETERS_KEY_OVERRIDE.get()
+        // service names are case-insensitive
+        .filter(|(name, _key)| name.to_string_lossy().eq_ignore_ascii_case(&service_name.to_string_lossy()))
+        .map(|(_name, key)| key)
+}
+
+/// The key below `HKEY_LOCAL_MACHINE` holding the parameters of the given service, unless
+/// overridden using `--params-key`.
+fn get_my_registry_path(service_name: &OsStr) -> OsString {
+    match parameters_key_override(service_name) {
+        Some(pko) => pko.clone(),
+        None => get_default_registry_path(service_name),
+    }
+}
+
+/// The key below `HKEY_LOCAL_MACHINE` where a service keeps its parameters by default.
+fn get_default_registry_path(service_name: &OsStr) -> OsString {
+    let mut mrp = OsString::new();
+    mrp.push("SYSTEM\\CurrentControlSet\\Services\\");
+    mrp.push(&service_name);
+    mrp.push("\\Parameters");
+    mrp
+}
+
+
+/// A service parameter that cannot be read or makes no sense.
+#[derive(Debug)]
+enum ParameterError {
+    Read { name: String, error: Error },
+    UnexpectedType(UnexpectedValueType),
+    NotUnicode { name: String, value: OsString },
+    Invalid { name: String, value: String, message: String },
+    Missing { name: String },
+}
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { name, error } => write!(f, "failed to read service parameter {}: {}", name, error),
+            Self::UnexpectedType(e) => write!(f, "{}", e),
+            Self::NotUnicode { name, value } => write!(f, "service parameter {} value {:?} is not valid Unicode", name, value),
+            Self::Invalid { name, value, message } => write!(f, "invalid service parameter {} value {:?}: {}", name, value, message),
+            Self::Missing { name } => write!(f, "{} is set neither in the configuration file nor in the registry", name),
+        }
+    }
+}
+impl std::error::Error for ParameterError {
+}
+impl From<UnexpectedValueType> for ParameterError {
+    fn from(e: UnexpectedValueType) -> Self { Self::UnexpectedType(e) }
+}
+
+fn try_read_optional_param<R: RegistryAccess>(registry: &R, name: &str) -> Result<Option<RegistryValue>, ParameterError> {
+    registry.read_value_optional(Some(&OsString::from(name)))
+        .map_err(|error| ParameterError::Read { name: name.to_owned(), error })
+}
+
+fn try_read_optional_string_param<R: RegistryAccess>(registry: &R, name: &str) -> Result<Option<OsString>, ParameterError> {
+    match try_read_optional_param(registry, name)? {
+        None => Ok(None),
+        Some(RegistryValue::String(s)) => Ok(Some(s)),
+        Some(RegistryValue::ExpandString { unexpanded: _, expanded }) => Ok(Some(expanded)),
+        Some(other) => Err(UnexpectedValueType::new(name, "REG_SZ or REG_EXPAND_SZ", &other).into()),
+    }
+}
+
+fn try_read_optional_integer_param<R: RegistryAccess>(registry: &R, name: &str) -> Result<Option<u64>, ParameterError> {
+    match try_read_optional_param(registry, name)? {
+        None => Ok(None),
+        Some(RegistryValue::Dword(dw)) => Ok(Some(dw.into())),
+        Some(RegistryValue::DwordBigEndian(dw)) => Ok(Some(dw.into())),
+        Some(RegistryValue::Qword(qw)) => Ok(Some(qw)),
+        Some(other) => Err(UnexpectedValueType::new(name, "REG_DWORD or REG_QWORD", &other).into()),
+    }
+}
+
+fn try_read_optional_string_list_param<R: RegistryAccess>(registry: &R, name: &str) -> Result<Option<Vec<OsString>>, ParameterError> {
+    match try_read_optional_param(registry, name)? {
+        None => Ok(None),
+        Some(RegistryValue::String(s)) => Ok(Some(vec![s])),
+        Some(RegistryValue::ExpandString { unexpanded: _, expanded }) => Ok(Some(vec![expanded])),
+        Some(RegistryValue::MultiString(ss)) => Ok(Some(ss)),
+        Some(other) => Err(UnexpectedValueType::new(name, "REG_SZ, REG_EXPAND_SZ or REG_MULTI_SZ", &other).into()),
+    }
+}
+
+fn try_os_string_param_to_string(name: &str, value: OsString) -> Result<String, ParameterError> {
+    value.into_string()
+        .map_err(|value| ParameterError::NotUnicode { name: name.to_owned(), value })
+}
+
+/// Reads where to send notification e-mails, or returns `None` if e-mails are not configured.
+///
+/// An incomplete configuration disables e-mails; this is warned about once instead of at every
+/// pass.
+fn read_smtp_settings<R: RegistryAccess>(registry: &R) -> Result<Option<SmtpSettings>, ParameterError> {
+    let server = match try_read_optional_string_param(registry, "SmtpServer")? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let from = try_read_optional_string_param(registry, "SmtpFrom")?;
+    let to = try_read_optional_string_list_param(registry, "SmtpTo")?
+        .filter(|t| !t.is_empty());
+    let (from, to) = match (from, to) {
+        (Some(f), Some(t)) => (f, t),
+        (from, _) => {
+            if !WARNED_ABOUT_INCOMPLETE_SMTP.swap(true, Ordering::SeqCst) {
+                let missing = if from.is_none() { "SmtpFrom" } else { "SmtpTo" };
+                warn!("service parameter SmtpServer is set but {} is not; not sending notification e-mails", missing);
+            }
+            return Ok(None);
+        },
+    };
+    Ok(Some(SmtpSettings {
+        server: try_os_string_param_to_string("SmtpServer", server)?,
+        from: try_os_string_param_to_string("SmtpFrom", from)?,
+        to: to.into_iter()
+            .map(|t| try_os_string_param_to_string("SmtpTo", t))
+            .collect::<Result<_, _>>()?,
+    }))
+}
+
+
+fn open_my_registry(my_registry_path: &OsStr, permissions: RegistryPermissions) -> RegistryKeyHandle {
+    let registry_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(my_registry_path),
+        permissions | RegistryPermissions::WOW64_64KEY,
+    );
+    match registry_res {
+        Ok(r) => r,
+        Err(e) => log_panic!("failed to open my registry path (HKLM subkey {:?}): {}", my_registry_path, e),
+    }
+}
+
+
+/// The parameters influencing a single pass over the watched services.
+struct PassSettings {
+    pub webhook_url: Option<OsString>,
+    pub smtp_settings: Option<SmtpSettings>,
+    pub max_restart_attempts: Option<u64>,
+    pub restart_attempt_reset: Duration,
+    pub healthy_stable_duration: Duration,
+    pub max_concurrent_starts: Option<u64>,
+    pub start_order: Option<Vec<OsString>>,
+    pub observe_only: Vec<OsString>,
+    pub maintenance_mode: bool,
+    pub restart_windows: Option<Vec<RestartWindow>>,
+    pub locked_retry_policy: LockedRetryPolicy,
+    pub pre_restart_command: Option<OsString>,
+    pub restart_command: Option<OsString>,
+    pub command_timeout: Duration,
+    pub probe_timeout: Duration,
+    pub probe_failure_threshold: u64,
+    pub power_settle_duration: Duration,
+}
+impl PassSettings {
+    pub fn read<R: RegistryAccess>(registry: &R) -> Result<Self, ParameterError> {
+        // query where to send restart notifications
+        let webhook_url = try_read_optional_string_param(registry, "NotifyWebhookUrl")?;
+        let smtp_settings = read_smtp_settings(registry)?;
+
+        // query how often we may try to restart a service
+        let max_restart_attempts = try_read_optional_integer_param(registry, "MaxRestartAttempts")?;
+        let restart_attempt_reset = try_read_optional_integer_param(registry, "RestartAttemptResetMilliseconds")?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_RESTART_ATTEMPT_RESET);
+
+        // query how long a service must keep running to count as recovered
+        let healthy_stable_duration = try_read_optional_integer_param(registry, "HealthyStableMilliseconds")?
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::ZERO);
+
+        // query how many services may be starting up at the same time
+        let max_concurrent_starts = try_read_optional_integer_param(registry, "MaxConcurrentStarts")?
+            .map(|mcs| mcs.max(1));
+
+        // query which services to take care of first
+        let start_order = try_read_optional_string_list_param(registry, "ServiceStartOrder")?;
+
+        // query which services we may only watch
+        let observe_only = try_read_optional_string_list_param(registry, "ObserveOnly")?
+            .unwrap_or_default();
+
+        // query whether we should stand down
+        let maintenance_mode = try_read_optional_integer_param(registry, "MaintenanceMode")?
+            .map(|mm| mm != 0)
+            .unwrap_or(false);
+
+        // query when we may restart services
+        let restart_windows = match try_read_optional_string_list_param(registry, "RestartWindows")? {
+            Some(windows) => {
+                let mut restart_windows = Vec::with_capacity(windows.len());
+                for w in windows {
+                    let w_str = try_os_string_param_to_string("RestartWindows", w)?;
+                    match RestartWindow::parse(&w_str) {
+                        Ok(rw) => restart_windows.push(rw),
+                        Err(e) => return Err(ParameterError::Invalid {
+                            name: "RestartWindows".to_owned(),
+                            value: w_str,
+                            message: e.to_string(),
+                        }),
+                    }
+                }
+                Some(restart_windows)
+            },
+            None => None,
+        };
+
+        // query how to deal with a locked service database
+        let locked_retry_policy = try_read_locked_retry_policy(registry)?;
+
+        // query what to run before and after restarting a service
+        let pre_restart_command = try_read_optional_string_param(registry, "PreRestartCommand")?;
+        let restart_command = try_read_optional_string_param(registry, "RestartCommand")?;
+        let command_timeout = try_read_optional_integer_param(registry, "CommandTimeoutMilliseconds")?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+
+        // query how patient to be with liveness probes
+        let probe_timeout = try_read_optional_integer_param(registry, "ProbeTimeoutMilliseconds")?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_PROBE_TIMEOUT);
+        let probe_failure_threshold = try_read_optional_integer_param(registry, "ProbeFailureThreshold")?
+            .unwrap_or(DEFAULT_PROBE_FAILURE_THRESHOLD)
+            .max(1);
+
+        // query how long to hold off after the system resumes or switches sessions
+        let power_settle_duration = try_read_optional_integer_param(registry, "PowerSettleMilliseconds")?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_POWER_SETTLE_DURATION);
+
+        Ok(Self {
+            webhook_url,
+            smtp_settings,
+            max_restart_attempts,
+            restart_attempt_reset,
+            healthy_stable_duration,
+            max_concurrent_starts,
+            start_order,
+            observe_only,
+            maintenance_mode,
+            restart_windows,
+            locked_retry_policy,
+            pre_restart_command,
+            restart_command,
+            command_timeout,
+            probe_timeout,
+            probe_failure_threshold,
+            power_settle_duration,
+        })
+    }
+}
+
+
+/// The parameters `run_with` needs around each pass besides those in the configuration.
+struct LoopSettings {
+    pub stats_flush_interval: Option<Duration>,
+    pub exit_after_idle: Option<Duration>,
+    pub locked_retry_policy: LockedRetryPolicy,
+    pub watched_services: Vec<OsString>,
+}
+impl LoopSettings {
+    pub fn read<R: RegistryAccess>(registry: &R, config: &Config) -> Result<Self, ParameterError> {
+        // query how often to write out statistics
+        let stats_flush_interval = try_read_optional_integer_param(registry, "StatsFlushIntervalMilliseconds")?
+            .map(Duration::from_millis);
+
+        // query how long to keep going with nothing to do
+        let exit_after_idle = try_read_optional_integer_param(registry, "ExitAfterIdleMilliseconds")?
+            .map(Duration::from_millis);
+
+        // query how to connect to the service control manager
+        let locked_retry_policy = try_read_locked_retry_policy(registry)?;
+
+        let watched_services = config.services_to_check()
+            .ok_or_else(|| ParameterError::Missing { name: "ServicesExpectedRunning".to_owned() })?;
+
+        Ok(Self {
+            stats_flush_interval,
+            exit_after_idle,
+            locked_retry_policy,
+            watched_services,
+        })
+    }
+}
+
+
+/// Runs a configured command with the service name appended as an argument.
+///
+/// Returns the exit code of the command, or `None` (after logging) if it could not be run or was
+/// terminated because it took too long.
+fn run_service_command(param_name: &str, command: &OsStr, service_name: &OsStr, timeout: Duration) -> Option<u32> {
+    let mut command_line = command.to_os_string();
+    command_line.push(" ");
+    command_line.push(quote_argument(service_name));
+
+    match run_command(&command_line, timeout) {
+        Ok(CommandOutcome::Exited(exit_code)) => {
+            info!("{} for service {:?} exited with code {}", param_name, service_name, exit_code);
+            Some(exit_code)
+        },
+        Ok(CommandOutcome::TimedOut) => {
+            warn!(
+                "{} for service {:?} did not finish within {} ms and was terminated",
+                param_name, service_name, timeout.as_millis(),
+            );
+            None
+        },
+        Err(e) => {
+            error!("failed to run {} {:?} for service {:?}: {}", param_name, command_line, service_name, e);
+            None
+        },
+    }
+}
+
+
+fn try_read_locked_retry_policy<R: RegistryAccess>(registry: &R) -> Result<LockedRetryPolicy, ParameterError> {
+    let default_policy = LockedRetryPolicy::default();
+    let retries = match try_read_optional_integer_param(registry, "ScmLockedRetryCount")? {
+        Some(r) => u32::try_from(r).unwrap_or(u32::MAX),
+        None => default_policy.retries,
+    };
+    let delay = try_read_optional_integer_param(registry, "ScmLockedRetryDelayMilliseconds")?
+        .map(Duration::from_millis)
+        .unwrap_or(default_policy.delay);
+    Ok(LockedRetryPolicy {
+        retries,
+        delay,
+    })
+}
+
+fn read_locked_retry_policy<R: RegistryAccess>(registry: &R) -> LockedRetryPolicy {
+    try_read_locked_retry_policy(registry)
+        .unwrap_or_else(|e| log_panic!("{}", e))
+}
+
+/// Reads the retry policy for command-line operations, falling back to the defaults if the
+/// service has no parameters.
+fn read_cli_locked_retry_policy(service_name: &OsStr) -> LockedRetryPolicy {
+    let my_registry_path = get_my_registry_path(service_name);
+    let registry_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(&my_registry_path),
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
+    );
+    match registry_res {
+        Ok(registry) => read_locked_retry_policy(&registry),
+        Err(_) => LockedRetryPolicy::default(),
+    }
+}
+
+
+/// State carried over from one pass over the watched services to the next.
+struct LoopState {
+    pub restart_tracking: RestartTracker,
+    pub probe_failures: HashMap<OsString, u64>,
+    pub restart_stats: RestartStatistics,
+    pub status: SharedStatus,
+
+    /// Service names already looked up by display name.
+    pub display_names: HashMap<OsString, OsString>,
+
+    /// Descriptions of services for log messages, by service name.
+    pub service_labels: HashMap<OsString, String>,
+
+    /// Named mutexes held open to let other instances know which services we are watching.
+    pub watch_markers: HashMap<OsString, NamedMutex>,
+
+    /// The state in which each service was found during the previous pass.
+    pub previous_states: HashMap<OsString, ServiceState>,
+
+    /// Since when each service has been found running (and healthy) in every pass.
+    pub running_since: HashMap<OsString, Instant>,
+
+    /// The services we have started that have not been found past their start-pending state since,
+    /// and when we started them.
+    pub starting: HashMap<OsString, Instant>,
+
+    /// The observe-only services that have been reported as down and not found running since.
+    pub observed_down: HashSet<OsString>,
+
+    /// The disabled services that we have warned about and not found enabled or running since.
+    pub disabled_warned: HashSet<OsString>,
+}
+impl LoopState {
+    pub fn new(status: SharedStatus) -> Self {
+        Self {
+            restart_tracking: RestartTracker::new(),
+            probe_failures: HashMap::new(),
+            restart_stats: RestartStatistics::new(),
+            status,
+            display_names: HashMap::new(),
+            service_labels: HashMap::new(),
+            watch_markers: HashMap::new(),
+            previous_states: HashMap::new(),
+            running_since: HashMap::new(),
+            starting: HashMap::new(),
+            observed_down: HashSet::new(),
+            disabled_warned: HashSet::new(),
+        }
+    }
+}
+
+
+/// Interprets the value of `ServicesExpectedRunning`.
+///
+/// Besides the canonical REG_MULTI_SZ, a REG_SZ is accepted as a list containing a single service
+/// and REG_NONE or an empty REG_SZ as an empty list. Returns `None` for any other type.
+fn watched_services_from_value(value: RegistryValue) -> Option<Vec<OsString>> {
+    match value {
+        RegistryValue::MultiString(names) => Some(names),
+        RegistryValue::String(name) if name.is_empty() => Some(Vec::new()),
+        RegistryValue::String(name) => Some(vec![name]),
+        RegistryValue::None(_) => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+fn read_watched_services<R: RegistryAccess>(registry: &R) -> Result<Option<Vec<OsString>>, ParameterError> {
+    let run_services = match try_read_optional_param(registry, "ServicesExpectedRunning")? {
+        Some(rs) => rs,
+        None => return Ok(None),
+    };
+    if !matches!(run_services, RegistryValue::MultiString(_)) && !WARNED_ABOUT_SERVICE_LIST_TYPE.swap(true, Ordering::SeqCst) {
+        warn!("service parameter ServicesExpectedRunning is a {}; a REG_MULTI_SZ is preferred", run_services.type_name());
+    }
+    match watched_services_from_value(run_services.clone()) {
+        Some(names) => Ok(Some(names)),
+        None => Err(UnexpectedValueType::new("ServicesExpectedRunning", "REG_MULTI_SZ", &run_services).into()),
+    }
+}
+
+/// Loads the configuration from the configuration file (if there is one), falling back to the
+/// registry for anything the file does not set.
+fn load_config<R: RegistryAccess>(config_path: Option<&Path>, registry: &R) -> Result<Config, ParameterError> {
+    let registry_config = Config::load_from_registry(registry)?;
+    let file_config = match config_path {
+        Some(cp) => match Config::from_file(cp) {
+            Ok(fc) => fc,
+            Err(e) => log_panic!("failed to read configuration file {:?}: {}", cp, e),
+        },
+        None => None,
+    };
+    Ok(match file_config {
+        Some(fc) => fc.or(registry_config),
+        None => registry_config,
+    })
+}
+
+/// Replaces entries of the form `display:Display Name` with the name of the service with that
+/// display name.
+///
+/// Successful lookups are cached. Entries that cannot be resolved are logged and dropped; their
+/// number is returned alongside the names.
+fn resolve_watched_services<S: ServiceControl>(
+    scm: &S,
+    entries: Vec<OsString>,
+    cache: &mut HashMap<OsString, OsString>,
+) -> (Vec<OsString>, usize) {
+    let mut names = Vec::with_capacity(entries.len());
+    let mut unresolved = 0;
+    for entry in entries {
+        let display_name = match entry.to_str().and_then(|e| e.strip_prefix("display:")) {
+            Some(dn) => OsString::from(dn),
+            None => {
+                names.push(entry);
+                continue;
+            },
+        };
+
+        if let Some(name) = cache.get(&display_name) {
+            names.push(name.clone());
+            continue;
+        }
+        match scm.resolve_display_name(&display_name) {
+            Ok(name) => {
+                debug!("service with display name {:?} is {:?}", display_name, name);
+                cache.insert(display_name, name.clone());
+                names.push(name);
+            },
+            Err(e) => {
+                error!("failed to find service with display name {:?}: {}", display_name, e);
+                unresolved += 1;
+            },
+        }
+    }
+    (names, unresolved)
+}
+
+/// Moves the services listed in `start_order` to the front, in that order; the others keep their
+/// relative order behind them. Service names are compared case-insensitively.
+fn apply_start_order(names: &mut [OsString], start_order: &[OsString]) {
+    // sort_by_key is stable
+    names.sort_by_key(|name| {
+        start_order.iter()
+            .position(|so| so.eq_ignore_ascii_case(name))
+            .unwrap_or(start_order.len())
+    });
+}
+
+/// Describes a service for log messages as `Display Name (name)`, or just by its name if the
+/// display name cannot be obtained.
+fn describe_service<S: ServiceControl>(scm: &S, name: &OsStr, cache: &mut HashMap<OsString, String>) -> String {
+    if let Some(label) = cache.get(name) {
+        return label.clone();
+    }
+    match scm.get_display_name(name) {
+        Ok(display_name) => {
+            let label = if display_name.is_empty() || display_name == name {
+                name.to_string_lossy().into_owned()
+            } else {
+                format!("{} ({})", display_name.to_string_lossy(), name.to_string_lossy())
+            };
+            cache.insert(name.to_os_string(), label.clone());
+            label
+        },
+        Err(e) => {
+            debug!("failed to obtain display name of service {:?}: {}", name, e);
+            name.to_string_lossy().into_owned()
+        },
+    }
+}
+
+/// Reads the liveness probes of those watched services that have one.
+///
+/// Probes are stored as values named after the service in the `Probe` subkey.
+fn read_probes<R: RegistryAccess>(registry: &R, names: &[OsString]) -> Result<HashMap<OsString, Probe>, ParameterError> {
+    let mut probes = HashMap::new();
+
+    let probe_key = match registry.open_subkey(Some(&OsString::from("Probe")), RegistryPermissions::QUERY_VALUE) {
+        Ok(pk) => pk,
+        Err(e) if e.win32_error() == Some(ERROR_FILE_NOT_FOUND) => return Ok(probes),
+        Err(error) => return Err(ParameterError::Read { name: "Probe".to_owned(), error }),
+    };
+
+    for name in names {
+        let spec = match try_read_optional_string_param(&probe_key, &name.to_string_lossy())? {
+            Some(s) => s,
+            None => continue,
+        };
+        let spec_str = try_os_string_param_to_string("Probe", spec)?;
+        match Probe::parse(&spec_str) {
+            Ok(p) => {
+                probes.insert(name.clone(), p);
+            },
+            Err(e) => return Err(ParameterError::Invalid {
+                name: format!("Probe\\{}", name.to_string_lossy()),
+                value: spec_str,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(probes)
+}
+
+/// Terminates the process hosting a hung service and waits for the service control manager to
+/// notice that the service has stopped.
+fn kill_service_process<A: ServiceAccess>(service: &A, name: &OsStr) -> Result<(), Error> {
+    let process_id = match service.get_process_id()? {
+        Some(pid) => pid,
+        None => return Ok(()),
+    };
+
+    warn!("terminating process {} of wedged service {:?}", process_id, name);
+    // services running as other accounts can only be terminated with this privilege
+    ensure_privilege(SE_DEBUG_NAME, "terminate the processes of hung services");
+    let process = ProcessHandle::open_for_termination(process_id)?;
+    process.terminate(WEDGED_EXIT_CODE)?;
+    process.wait(KILL_WAIT)?;
+
+    service.wait_for_state(ServiceState::Stopped, KILL_WAIT, KILL_POLL_INTERVAL)?;
+    Ok(())
+}
+
+
+/// Sends the event to the webhook URL given in `NotifyWebhookUrl`.
+fn send_webhook(url: &OsStr, event: &RestartEvent) {
+    match url.to_str() {
+        Some(u) => notify_webhook(u, event),
+        None => warn!("NotifyWebhookUrl {:?} is not valid Unicode; not notifying", url),
+    }
+}
+
+/// Logs that an observe-only service is down and notifies the webhook and the e-mail recipients,
+/// if configured.
+fn report_observe_only_down<S: ServiceControl>(
+    scm: &S,
+    settings: &PassSettings,
+    name: &OsStr,
+    service_state: ServiceState,
+    wedged: bool,
+    service_labels: &mut HashMap<OsString, String>,
+) {
+    let label = describe_service(scm, name, service_labels);
+    let condition = if wedged { "hung" } else { "stopped" };
+    warn!("service {} is {}, but it is observe-only; not restarting it", label, condition);
+
+    if let Some(url) = &settings.webhook_url {
+        let event = RestartEvent {
+            service_name: name.to_os_string(),
+            old_state: service_state,
+            success: false,
+            observe_only: true,
+        };
+        send_webhook(url, &event);
+    }
+    if let Some(smtp) = &settings.smtp_settings {
+        let subject = format!("service {} is {}", name.to_string_lossy(), condition);
+        let body = format!(
+            "The service {} is {}.\nIt is observe-only, so servicerestarter will not restart it.\n",
+            name.to_string_lossy(), condition,
+        );
+        notify_mail(smtp, &subject, &body);
+    }
+}
+
+/// Reads the parameters and checks each watched service once, starting those that are stopped.
+///
+/// Problems with individual services are logged and counted in the summary. If the parameters
+/// cannot be used, no service is checked and the problem is returned instead.
+fn check_once<R: RegistryAccess, S: ServiceControl>(
+    registry: &R,
+    scm: &S,
+    watched_services: Vec<OsString>,
+    state: &mut LoopState,
+) -> Result<CheckSummary, ParameterError> {
+    let settings = PassSettings::read(registry)?;
+    let (names, unresolved) = resolve_watched_services(scm, watched_services, &mut state.display_names);
+    // a display name may resolve to a service that is also listed by name
+    let mut names = without_duplicates(names);
+    if let Some(start_order) = &settings.start_order {
+        apply_start_order(&mut names, start_order);
+    }
+    let probes = read_probes(registry, &names)?;
+    let within_restart_window = match &settings.restart_windows {
+        Some(windows) => is_within_windows(windows, &Local::now()),
+        None => true,
+    };
+    let pause_reason = if is_shutting_down() {
+        Some(PauseReason::ShuttingDown)
+    } else if is_settling(settings.power_settle_duration) {
+        Some(PauseReason::PowerSettling)
+    } else if settings.maintenance_mode {
+        Some(PauseReason::MaintenanceMode)
+    } else if !within_restart_window {
+        Some(PauseReason::OutsideRestartWindow)
+    } else {
+        None
+    };
+    state.status.update(|s| s.pause_reason = pause_reason);
+
+    let mut summary = CheckSummary {
+        checked: unresolved,
+        errored: unresolved,
+        ..Default::default()
+    };
+    state.starting.retain(|n, _| names.contains(n));
+    for name in &names {
+        summary.checked += 1;
+
+        if !state.watch_markers.contains_key(name) {
+            match NamedMutex::open_or_create(&service_mutex_name("watch", name)) {
+                Ok((marker, already_existed)) => {
+                    if already_existed {
+                        warn!("another servicerestarter instance is also watching service {:?}; restarts are coordinated, but it should only be watched by one of them", name);
+                    }
+                    state.watch_markers.insert(name.clone(), marker);
+                },
+                Err(e) => debug!("failed to create watch marker for service {:?}: {}", name, e),
+            }
+        }
+
+        // open the service
+        let service_res = scm.open_service(
+            name,
+            ServicePermissions::QUERY_STATUS | ServicePermissions::QUERY_CONFIG | ServicePermissions::START
+                | ServicePermissions::STOP,
+        );
+        let service = match service_res {
+            Ok(s) => s,
+            Err(e) => {
+                error!("failed to open service {:?}: {}{}", name, e, ErrorHint(&e));
+                summary.errored += 1;
+                continue;
+            },
+        };
+
+        // query its state
+        let service_state = match service.get_state() {
+            Ok(ss) => ss,
+            Err(e) => {
+                error!("failed to get service {:?} state: {}", name, e);
+                summary.errored += 1;
+                continue;
+            },
+        };
+
+        // the first observation is not a transition
+        if let Some(previous_state) = state.previous_states.insert(name.clone(), service_state) {
+            if previous_state != service_state {
+                let label = describe_service(scm, name, &mut state.service_labels);
+                info!("service {} changed from {:?} to {:?}", label, previous_state, service_state);
+            }
+        }
+
+        if service_state != ServiceState::StartPending {
+            if let Some(started_at) = state.starting.remove(name) {
+                let elapsed = started_at.elapsed();
+                let label = describe_service(scm, name, &mut state.service_labels);
+                if service_state.is_running() {
+                    info!("service {} was found running {} ms after it was started", label, elapsed.as_millis());
+                    state.restart_stats.record_start_duration(name, elapsed);
+                } else {
+                    info!("service {} was found {:?} instead of running {} ms after it was started", label, service_state, elapsed.as_millis());
+                }
+            }
+        }
+
+        state.status.set_service_state(name, service_state);
+        state.restart_tracking.ensure_loaded(registry, name, settings.restart_attempt_reset);
+
+        // the service control manager might consider it running while it is actually hung
+        // (only act after multiple consecutive failures to ride out hiccups)
+        let wedged = match probes.get(name) {
+            Some(probe) if service_state.is_running() => {
+                match probe.check(name, settings.command_timeout, settings.probe_timeout) {
+                    ProbeOutcome::Healthy => {
+                        state.probe_failures.remove(name);
+                        false
+                    },
+                    ProbeOutcome::Unhealthy => {
+                        let failures = state.probe_failures.entry(name.clone()).or_default();
+                        *failures = failures.saturating_add(1);
+                        if *failures >= settings.probe_failure_threshold {
+                            true
+                        } else {
+                            info!(
+                                "service {:?} failed its probe ({} of {} consecutive failures)",
+                                name, failures, settings.probe_failure_threshold,
+                            );
+                            false
+                        }
+                    },
+                    ProbeOutcome::Failed => {
+                        summary.errored += 1;
+                        false
+                    },
+                }
+            },
+            _ => {
+                state.probe_failures.remove(name);
+                false
+            },
+        };
+
+        if service_state.is_running() && !wedged {
+            // it is fine again; once it has stayed that way, forget about previous attempts
+            let running_since = *state.running_since.entry(name.clone()).or_insert_with(Instant::now);
+            if running_since.elapsed() >= settings.healthy_stable_duration {
+                state.restart_tracking.clear(name);
+            }
+            state.observed_down.remove(name);
+            state.disabled_warned.remove(name);
+        } else {
+            state.running_since.remove(name);
+        }
+
+        let needs_start = if wedged || service_state.is_stopped() {
+            true
+        } else if service_state.is_running() {
+            false
+        } else if service_state == ServiceState::StopPending {
+            // it will be stopped (and started) at one of the next checks
+            debug!("service {:?} is stopping; checking on it again next time", name);
+            false
+        } else if service_state.is_transitioning() {
+            debug!("service {:?} is {:?}; waiting for it to get there", name, service_state);
+            false
+        } else {
+            // someone paused it on purpose; starting a paused service fails anyway
+            debug!("service {:?} is paused; leaving it alone", name);
+            false
+        };
+
+        if needs_start {
+            if is_shutting_down() {
+                // the system is taking the services down; don't fight it
+                debug!("system is shutting down; not starting service {:?}", name);
+                continue;
+            }
+            if is_settling(settings.power_settle_duration) {
+                info!("service {:?} is stopped, but the system is suspending, resuming or switching sessions", name);
+                continue;
+            }
+            if settings.observe_only.iter().any(|oo| oo.eq_ignore_ascii_case(name)) {
+                // a human has to decide what to do; tell them once per outage
+                if state.observed_down.insert(name.clone()) {
+                    report_observe_only_down(scm, &settings, name, service_state, wedged, &mut state.service_labels);
+                }
+                continue;
+            }
+            match service.get_start_type() {
+                Ok(Some(ServiceStartType::Disabled)) => {
+                    // someone disabled it on purpose; say so once instead of failing at every check
+                    if state.disabled_warned.insert(name.clone()) {
+                        warn!("service {:?} is disabled; not starting it until its start type is changed", name);
+                    }
+                    continue;
+                },
+                Ok(_) => {
+                    state.disabled_warned.remove(name);
+                },
+                Err(e) => debug!("failed to query start type of service {:?}; trying to start it anyway: {}", name, e),
+            }
+            if settings.maintenance_mode {
+                info!("service {:?} is stopped, but maintenance mode is active", name);
+                continue;
+            }
+            if !within_restart_window {
+                info!("service {:?} is stopped, but restarts are not allowed at this time", name);
+                continue;
+            }
+
+            let label = describe_service(scm, name, &mut state.service_labels);
+            let tracking = state.restart_tracking.get(name);
+            if let Some(max_attempts) = settings.max_restart_attempts {
+                if tracking.attempts >= max_attempts {
+                    if !tracking.gave_up {
+                        state.restart_tracking.give_up(name);
+                        error!(
+                            "service {} is still stopped after {} restart attempts; not trying again until it is running",
+                            label, tracking.attempts,
+                        );
+                        if let Some(smtp) = &settings.smtp_settings {
+                            let subject = format!("service {} keeps failing", name.to_string_lossy());
+                            let body = format!(
+                                "The service {} is still stopped after {} restart attempts.\nservicerestarter will not try to start it again until it has been observed running.\n",
+                                name.to_string_lossy(), tracking.attempts,
+                            );
+                            notify_mail(smtp, &subject, &body);
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            // don't start too many services at once (e.g. after an outage of a common dependency)
+            if let Some(max_starts) = settings.max_concurrent_starts {
+                let starting = u64::try_from(state.starting.len()).unwrap_or(u64::MAX);
+                if starting >= max_starts {
+                    info!("{} services are still starting up; not starting service {} until the next check", starting, label);
+                    continue;
+                }
+            }
+
+            // make sure no other instance is restarting it at the same time
+            let restart_mutex = match NamedMutex::open_or_create(&service_mutex_name("restart", name)) {
+                Ok((m, _)) => Some(m),
+                Err(e) => {
+                    warn!("failed to create restart mutex for service {}; restarting it without one: {}", label, e);
+                    None
+                },
+            };
+            let _restart_guard = match restart_mutex.as_ref().map(|m| m.try_lock(Duration::ZERO)) {
+                Some(Ok(Some(guard))) => Some(guard),
+                Some(Ok(None)) => {
+                    info!("another servicerestarter instance is restarting service {}; skipping it this time", label);
+                    continue;
+                },
+                Some(Err(e)) => {
+                    warn!("failed to acquire restart mutex for service {}; restarting it without one: {}", label, e);
+                    None
+                },
+                None => None,
+            };
+            if !wedged {
+                // another instance might have started it in the meantime
+                match service.get_state() {
+                    Ok(ServiceState::Stopped) => {},
+                    Ok(other) => {
+                        debug!("service {} is no longer stopped (now {:?}); not starting it", label, other);
+                        continue;
+                    },
+                    Err(e) => {
+                        error!("failed to get service {} state: {}", label, e);
+                        summary.errored += 1;
+                        continue;
+                    },
+                }
+            }
+
+            // ask whether we may start it now
+            if let Some(command) = &settings.pre_restart_command {
+                match run_service_command("PreRestartCommand", command, name, settings.command_timeout) {
+                    Some(0) => {},
+                    Some(_) => {
+                        info!("PreRestartCommand vetoed starting service {}; skipping it this time", label);
+                        continue;
+                    },
+                    None => {
+                        // the failure has already been logged; don't leave the service down because of it
+                        warn!("starting service {} even though PreRestartCommand did not succeed", label);
+                    },
+                }
+            }
+
+            state.restart_tracking.record_attempt(name);
+
+            // get rid of the hung instance first
+            if wedged {
+                state.probe_failures.remove(name);
+                if let Err(e) = kill_service_process(&service, name) {
+                    error!("failed to terminate process of wedged service {}: {}{}", label, e, ErrorHint(&e));
+                    summary.errored += 1;
+                    continue;
+                }
+            }
+
+            // start it
+            let start_res = settings.locked_retry_policy.run(
+                "start service",
+                || service.start(vec![]),
+            );
+            match &start_res {
+                Ok(_) => {
+                    info!("started service {}", label);
+                    summary.restarted += 1;
+                    state.starting.insert(name.clone(), Instant::now());
+                    state.restart_stats.record_restart(name);
+                    state.status.record_restart(name);
+
+                    if let Some(command) = &settings.restart_command {
+                        run_service_command("RestartCommand", command, name, settings.command_timeout);
+                    }
+                },
+                Err(e) => {
+                    error!("failed to start service {}: {}{}", label, e, ErrorHint(e));
+                    summary.errored += 1;
+                },
+            }
+
+            if let Some(url) = &settings.webhook_url {
+                let event = RestartEvent {
+                    service_name: name.clone(),
+                    old_state: service_state,
+                    success: start_res.is_ok(),
+                    observe_only: false,
+                };
+                send_webhook(url, &event);
+            }
+        }
+    }
+
+    state.probe_failures.retain(|name, _| names.contains(name));
+    state.watch_markers.retain(|name, _| names.contains(name));
+    state.observed_down.retain(|name| names.contains(name));
+    state.disabled_warned.retain(|name| names.contains(name));
+    state.status.finish_pass(&names, summary);
+    Ok(summary)
+}
+
+
+fn spawn_http_endpoint<F>(description: &str, port_param_name: &str, port: Option<u64>, spawn: F) -> Option<HttpServer>
+    where F: FnOnce(u16) -> Result<HttpServer, std::io::Error>
+{
+    let port = port?;
+    let port_u16 = match u16::try_from(port) {
+        Ok(p) => p,
+        Err(_) => {
+            error!("{} {} is not a valid port number; continuing without {}", port_param_name, port, description);
+            return None;
+        },
+    };
+    match spawn(port_u16) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            error!("failed to start {} on port {}; continuing without it: {}", description, port_u16, e);
+            None
+        },
+    }
+}
+
+/// Spawns a thread that cuts the current wait of the loop short whenever a value in the parameters
+/// key changes, so that changes take effect immediately instead of at the next check.
+fn spawn_change_watcher(my_registry_path: &OsStr, service_info: Arc<ServiceInfo>) {
+    let key_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(my_registry_path),
+        RegistryPermissions::NOTIFY | RegistryPermissions::WOW64_64KEY,
+    );
+    let key = match key_res {
+        Ok(k) => k,
+        Err(e) => {
+            warn!("failed to open my registry path for change notifications; continuing without them: {}", e);
+            return;
+        },
+    };
+
+    let spawn_res = std::thread::Builder::new()
+        .name("change watcher".to_owned())
+        .spawn(move || {
+            loop {
+                match key.wait_for_change(RegistryChangeFilter::LAST_SET, None) {
+                    Ok(true) => {
+                        debug!("service parameters have changed; checking again");
+                        service_info.wait_stopper.wake();
+                    },
+                    Ok(false) => {},
+                    Err(e) => {
+                        error!("failed to wait for changes to the service parameters; no longer watching for them: {}", e);
+                        return;
+                    },
+                }
+            }
+        });
+    if let Err(e) = spawn_res {
+        warn!("failed to spawn change watcher thread: {}", e);
+    }
+}
+
+
+fn run(service_name: OsString, max_runtime: Option<Duration>, max_iterations: Option<u64>) {
+    let deadline = max_runtime.map(|mr| Instant::now() + mr);
+    let my_registry_path = get_my_registry_path(&service_name);
+    let service_info = service_info(&service_name);
+
+    let status = SharedStatus::new();
+    let reported_state = service_info.service_status_handle.get()
+        .and_then(|ssh| ssh.last_status())
+        .and_then(|ls| ServiceState::try_from(ls.dwCurrentState).ok());
+    status.update(|s| s.reported_state = reported_state);
+    let status_pipe = match StatusPipe::spawn(&service_name, status.clone()) {
+        Ok(sp) => Some(sp),
+        Err(e) => {
+            warn!("failed to create status pipe {:?}; continuing without it: {}", StatusPipe::pipe_name(&service_name), e);
+            None
+        },
+    };
+
+
+    let (health_port, metrics_port, watch_changes, watchdog_timeout, watchdog_exit) = {
+        let registry = open_my_registry(&my_registry_path, RegistryPermissions::QUERY_VALUE);
+        // these are only read once; do without the feature instead of giving up
+        let read = |name: &str| match try_read_optional_integer_param(&registry, name) {
+            Ok(value) => value,
+            Err(e) => {
+                error!("{}; continuing as if it were not set", e);
+                None
+            },
+        };
+        (
+            read("HealthPort"),
+            read("MetricsPort"),
+            read("WatchParameterChanges").unwrap_or(0) != 0,
+            read("WatchdogTimeoutMilliseconds")
+                .map(Duration::from_millis),
+            read("WatchdogExitOnStall").unwrap_or(0) != 0,
+        )
+    };
+    let health_server = spawn_http_endpoint(
+        "health endpoint",
+        "HealthPort",
+        health_port,
+        |port| spawn_health_server(port, status.clone()),
+    );
+    let metrics_server = spawn_http_endpoint(
+        "metrics endpoint",
+        "MetricsPort",
+        metrics_port,
+        |port| spawn_metrics_server(port, status.clone()),
+    );
+    if watch_changes {
+        spawn_change_watcher(&my_registry_path, Arc::clone(&service_info));
+    }
+    let watchdog = watchdog_timeout.and_then(|timeout| {
+        match Watchdog::spawn(timeout, watchdog_exit, status.clone()) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                error!("failed to start watchdog; continuing without it: {}", e);
+                None
+            },
+        }
+    });
+
+    // exiting once there is nothing left to do only makes sense in the foreground
+    let running_as_service = service_info.service_status_handle.get().is_some();
+
+    let config_path = config_file_path(&service_name);
+    let stop_reason = run_with(
+        status.clone(),
+        deadline,
+        max_iterations,
+        !running_as_service,
+        config_path.as_deref(),
+        &service_info.wait_stopper,
+        watchdog.as_ref(),
+        |permissions| open_my_registry(&my_registry_path, permissions),
+        |retry_policy| {
+            retry_policy.run(
+                "connect to service control manager",
+                || ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT),
+            )
+                .expect_log("failed to connect to service control manager")
+        },
+    );
+
+    if let Some(wd) = watchdog {
+        wd.stop();
+    }
+    if let Some(ms) = metrics_server {
+        ms.stop();
+    }
+    if let Some(hs) = health_server {
+        hs.stop();
+    }
+    if let Some(sp) = status_pipe {
+        sp.stop();
+    }
+
+    info!("stopping because of {}", stop_reason);
+}
+
+/// Logs the configuration in effect at startup.
+fn log_effective_configuration<R: RegistryAccess>(registry: &R, config: &Config, config_path: Option<&Path>) {
+    let services = config.services_to_check().unwrap_or_default();
+    let settings_res = PassSettings::read(registry)
+        .and_then(|settings| Ok((settings, read_probes(registry, &services)?.len())));
+    let (settings, probe_count) = match settings_res {
+        Ok(sp) => sp,
+        Err(e) => {
+            // the check reports this as an error
+            warn!("not logging the effective configuration: {}", e);
+            return;
+        },
+    };
+    let config_file = match config_path {
+        Some(cp) if cp.is_file() => format!("{:?}", cp),
+        _ => "none".to_owned(),
+    };
+
+    info!(
+        "effective configuration: configuration file {}, sleep {} ms, initial sleep {} ms, services {:?}, log to {}, maintenance mode {}, max restart attempts {}, restart windows {}, pre-restart command {}, restart command {}, webhook {}, mail {}, probes {}",
+        config_file,
+        config.sleep_duration.unwrap_or_default().as_millis(),
+        config.initial_sleep_duration.unwrap_or_default().as_millis(),
+        services,
+        crate::logging::log_target(),
+        if settings.maintenance_mode { "on" } else { "off" },
+        settings.max_restart_attempts.map(|m| m.to_string()).unwrap_or_else(|| "unlimited".to_owned()),
+        settings.restart_windows.as_ref().map(|w| w.len().to_string()).unwrap_or_else(|| "none (always)".to_owned()),
+        if settings.pre_restart_command.is_some() { "set" } else { "unset" },
+        if settings.restart_command.is_some() { "set" } else { "unset" },
+        if settings.webhook_url.is_some() { "on" } else { "off" },
+        if settings.smtp_settings.is_some() { "on" } else { "off" },
+        probe_count,
+    );
+}
+
+/// Reads the regular sleep duration; the human-friendly variant wins over the milliseconds.
+fn read_optional_sleep_duration<R: RegistryAccess>(registry: &R) -> Result<Option<Duration>, ParameterError> {
+    match try_read_optional_string_param(registry, "SleepDuration")? {
+        Some(sd) => {
+            let sd_str = try_os_string_param_to_string("SleepDuration", sd)?;
+            match parse_duration(&sd_str) {
+                Ok(d) => Ok(Some(d)),
+                Err(e) => Err(ParameterError::Invalid {
+                    name: "SleepDuration".to_owned(),
+                    value: sd_str,
+                    message: e.to_string(),
+                }),
+            }
+        },
+        None => Ok(try_read_optional_integer_param(registry, "SleepDurationMilliseconds")?
+            .map(Duration::from_millis)),
+    }
+}
+
+/// Shortens a sleep so that it does not extend past the deadline, if any.
+///
+/// Returns the duration to sleep and whether the deadline is reached at the end of it.
+fn clamp_to_deadline(duration: Duration, deadline: Option<Instant>) -> (Duration, bool) {
+    match deadline {
+        Some(dl) => {
+            let remaining = dl.saturating_duration_since(Instant::now());
+            if remaining <= duration {
+                (remaining, true)
+            } else {
+                (duration, false)
+            }
+        },
+        None => (duration, false),
+    }
+}
+
+/// Writes out the statistics that have not been written yet before the loop stops.
+fn flush_remaining_stats<R, RF>(stats_flush_interval: Option<Duration>, state: &mut LoopState, open_registry: &mut RF)
+    where
+        R: RegistryAccess,
+        RF: FnMut(RegistryPermissions) -> R,
+{
+    if stats_flush_interval.is_some() && state.restart_stats.is_flush_due(Duration::ZERO) {
+        let stats_registry = open_registry(RegistryPermissions::QUERY_VALUE | RegistryPermissions::CREATE_SUB_KEY);
+        state.restart_stats.flush(&stats_registry);
+    }
+}
+
+/// Runs the loop until it is asked to stop, the deadline is reached or, if `max_iterations` is
+/// given, the services have been checked that many times.
+///
+/// If `exit_when_idle` is set and `ExitAfterIdleMilliseconds` is configured, the loop also stops
+/// once no service has been started (or been starting up) for that long.
+#[allow(clippy::too_many_arguments)]
+fn run_with<R, S, RF, SF>(
+    status: SharedStatus,
+    deadline: Option<Instant>,
+    max_iterations: Option<u64>,
+    exit_when_idle: bool,
+    config_path: Option<&Path>,
+    wait_stopper: &WaitStopper,
+    watchdog: Option<&Watchdog>,
+    mut open_registry: RF,
+    mut connect_scm: SF,
+) -> StopReason
+    where
+        R: RegistryAccess,
+        S: ServiceControl,
+        RF: FnMut(RegistryPermissions) -> R,
+        SF: FnMut(&LockedRetryPolicy) -> S,
+{
+    let mut is_first_loop: bool = true;
+    let mut state = LoopState::new(status);
+    let mut warned_about_short_sleep = false;
+    let mut rng = XorShiftRng::from_time();
+    let mut iterations: u64 = 0;
+    let mut idle_since = Instant::now();
+    let mut total_restarted: usize = 0;
+    loop {
+        // check our settings in the configuration file and the registry
+        let registry = open_registry(RegistryPermissions::QUERY_VALUE);
+        let config = match load_config(config_path, &registry) {
+            Ok(c) => c,
+            Err(e) => log_panic!("failed to load the configuration: {}", e),
+        };
+
+        if is_first_loop {
+            is_first_loop = false;
+
+            log_effective_configuration(&registry, &config, config_path);
+
+            // sleep initially
+            if let Some(initial_sleep_duration) = config.initial_sleep_duration {
+                let (sleep_duration, deadline_reached) = clamp_to_deadline(initial_sleep_duration, deadline);
+                if let Some(wd) = watchdog {
+                    wd.tick(sleep_duration);
+                }
+                let stop_result = wait_stopper.wait_until_stop_timeout(sleep_duration);
+                if let Some(reason) = stop_result.reason() {
+                    // get out
+                    return reason;
+                }
+                if deadline_reached {
+                    return StopReason::MaxRuntime;
+                }
+            }
+        }
+
+        // query the remaining parameters; if they are broken, this pass neither writes out
+        // statistics nor stops for being idle
+        let loop_settings = LoopSettings::read(&registry, &config);
+        let (stats_flush_interval, exit_after_idle) = match &loop_settings {
+            Ok(ls) => (ls.stats_flush_interval, ls.exit_after_idle),
+            Err(_) => (None, None),
+        };
+
+        // connect to service control manager and check on the services
+        let check_result = loop_settings.and_then(|ls| {
+            let scm = connect_scm(&ls.locked_retry_policy);
+            check_once(&registry, &scm, ls.watched_services, &mut state)
+        });
+        let summary = match check_result {
+            Ok(s) => s,
+            Err(e) => {
+                // someone might be in the middle of changing the parameters; try again next time
+                error!("not checking the services this time: {}", e);
+                let failed = CheckSummary { errored: 1, ..Default::default() };
+                state.status.update(|s| s.last_summary = failed);
+                failed
+            },
+        };
+        debug!(
+            "checked {} services: {} restarted, {} errors",
+            summary.checked, summary.restarted, summary.errored,
+        );
+
+        // services that are still coming up count as something to do
+        total_restarted += summary.restarted;
+        if summary.restarted > 0 || !state.starting.is_empty() {
+            idle_since = Instant::now();
+        }
+
+        // remember restart attempts across our own restarts
+        if state.restart_tracking.is_dirty() {
+            let state_registry = open_registry(RegistryPermissions::QUERY_VALUE | RegistryPermissions::CREATE_SUB_KEY);
+            state.restart_tracking.save(&state_registry);
+        }
+
+        // write out statistics if it's time
+        if let Some(interval) = stats_flush_interval {
+            if state.restart_stats.is_flush_due(interval) {
+                let stats_registry = open_registry(RegistryPermissions::QUERY_VALUE | RegistryPermissions::CREATE_SUB_KEY);
+                state.restart_stats.flush(&stats_registry);
+            }
+        }
+
+        // stop without sleeping once the requested number of iterations is reached
+        iterations += 1;
+        if max_iterations.is_some_and(|mi| iterations >= mi) {
+            flush_remaining_stats(stats_flush_interval, &mut state, &mut open_registry);
+            return StopReason::Iterations;
+        }
+
+        // stop once nothing has needed doing for long enough
+        if let Some(idle_period) = exit_after_idle.filter(|_| exit_when_idle) {
+            let idle_duration = idle_since.elapsed();
+            if idle_duration >= idle_period {
+                info!(
+                    "no services needed to be started for {} ms; {} services were started during this run",
+                    idle_duration.as_millis(), total_restarted,
+                );
+                flush_remaining_stats(stats_flush_interval, &mut state, &mut open_registry);
+                return StopReason::Idle;
+            }
+        }
+
+        // query regular sleep duration
+        let sleep_duration = config.sleep_duration
+            .unwrap_or_else(|| log_panic!("neither SleepDuration nor SleepDurationMilliseconds is set"));
+
+        // don't let a tiny interval turn into a busy loop
+        let minimum_sleep_duration = config.minimum_sleep_duration
+            .unwrap_or(DEFAULT_MINIMUM_SLEEP_DURATION);
+        let sleep_duration = if sleep_duration < minimum_sleep_duration {
+            if !warned_about_short_sleep {
+                warned_about_short_sleep = true;
+                warn!(
+                    "sleep duration of {} ms is shorter than the minimum of {} ms; using the minimum instead",
+                    sleep_duration.as_millis(), minimum_sleep_duration.as_millis(),
+                );
+            }
+            minimum_sleep_duration
+        } else {
+            sleep_duration
+        };
+
+        // spread out the checks of multiple instances
+        let sleep_jitter_ms = config.sleep_jitter
+            .map(|sj| u64::try_from(sj.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or(0);
+        let sleep_duration = if sleep_jitter_ms > 0 {
+            let offset_ms = rng.next_up_to(sleep_jitter_ms.saturating_mul(2));
+            let jittered = if offset_ms >= sleep_jitter_ms {
+                sleep_duration.saturating_add(Duration::from_millis(offset_ms - sleep_jitter_ms))
+            } else {
+                sleep_duration.saturating_sub(Duration::from_millis(sleep_jitter_ms - offset_ms))
+            };
+            jittered.max(minimum_sleep_duration)
+        } else {
+            sleep_duration
+        };
+
+        // sleep (but not past the maximum runtime); a change to our parameters may wake us early
+        let sleep_start = Instant::now();
+        let (sleep_duration, deadline_reached) = clamp_to_deadline(sleep_duration, deadline);
+        if let Some(wd) = watchdog {
+            wd.tick(sleep_duration);
+        }
+        let stop_result = wait_stopper.wait_until_stop_or_wake_timeout(sleep_duration);
+        let stop_reason = match stop_result.reason() {
+            Some(reason) => Some(reason),
+            None if deadline_reached && sleep_start.elapsed() >= sleep_duration => Some(StopReason::MaxRuntime),
+            None => None,
+        };
+        if let Some(reason) = stop_reason {
+            // write out remaining statistics
+            flush_remaining_stats(stats_flush_interval, &mut state, &mut open_registry);
+
+            // get out
+            return reason;
+        }
+    }
+}
+
+/// Extracts the executable path from a service command line, which may be quoted.
+fn executable_from_command_line(command_line: &str) -> &str {
+    if let Some(rest) = command_line.strip_prefix('"') {
+        match rest.find('"') {
+            Some(end) => &rest[..end],
+            None => rest,
+        }
+    } else {
+        command_line.split(' ').next().unwrap_or(command_line)
+    }
+}
+
+/// Logs a warning if the service is configured to run a different executable than this one, e.g.
+/// after the executable has been moved without reinstalling the service.
+fn warn_on_binary_path_drift(service_name: &OsStr) {
+    let my_path = match std::env::current_exe() {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("failed to obtain executable path to compare with the service configuration: {}", e);
+            return;
+        },
+    };
+
+    let config_res = ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT)
+        .and_then(|scm| scm.open_service(service_name, ServicePermissions::QUERY_CONFIG))
+        .and_then(|service| service.query_config());
+    let config = match config_res {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("failed to query configuration of service {:?}: {}", service_name, e);
+            return;
+        },
+    };
+
+    let binary_path = config.binary_path.to_string_lossy();
+    let configured_exe = executable_from_command_line(&binary_path);
+    let my_exe = my_path.to_string_lossy();
+    // paths are case-insensitive on Windows
+    if !configured_exe.eq_ignore_ascii_case(&my_exe) {
+        warn!(
+            "service {:?} is configured to run {:?}, but this is {:?}; reinstall the service to fix this",
+            service_name, configured_exe, my_exe,
+        );
+    }
+
+    // registry keys are case-insensitive too
+    let configured_params_key = params_key_from_command_line(&binary_path);
+    let my_params_key = parameters_key_override(service_name).map(|pko| pko.to_string_lossy());
+    let params_key_matches = match (&configured_params_key, &my_params_key) {
+        (Some(c), Some(m)) => c.eq_ignore_ascii_case(m),
+        (None, None) => true,
+        _ => false,
+    };
+    if !params_key_matches {
+        warn!(
+            "service {:?} is configured with the parameters key {:?}, but this instance uses {:?}",
+            service_name,
+            configured_params_key.unwrap_or_else(|| "(default)".to_owned()),
+            my_params_key.map(|m| m.into_owned()).unwrap_or_else(|| "(default)".to_owned()),
+        );
+    }
+}
+
+/// The value of `--params-key` in a service command line, if any.
+fn params_key_from_command_line(command_line: &str) -> Option<String> {
+    let mut arguments = split_command_line(command_line).into_iter();
+    while let Some(argument) = arguments.next() {
+        if argument == "--params-key" {
+            return arguments.next();
+        }
+        if let Some(value) = argument.strip_prefix("--params-key=") {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
+
+/// Whether the service looks like a servicerestarter instance: it runs an executable with the same
+/// file name as this one or its parameters name the services to watch. Returns the watched
+/// services if it does.
+///
+/// The parameters are read from the key given using `--params-key` in the service's command line
+/// (part of `config`, if it could be queried), if any.
+fn servicerestarter_instance(service: &ServiceEntry, config: Option<&ServiceConfig>, my_file_name: Option<&OsStr>) -> Option<Vec<OsString>> {
+    let binary_path = config
+        .map(|c| c.binary_path.to_string_lossy().into_owned());
+
+    let registry_path = binary_path.as_deref()
+        .and_then(params_key_from_command_line)
+        .map(OsString::from)
+        .unwrap_or_else(|| get_default_registry_path(&service.name));
+    let registry_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(&registry_path),
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
+    );
+    let watched = match registry_res {
+        Ok(registry) => read_watched_services(&registry).ok().flatten(),
+        Err(_) => None,
+    };
+    if watched.is_some() {
+        return watched;
+    }
+
+    let binary_path = binary_path?;
+    let file_name = Path::new(executable_from_command_line(&binary_path)).file_name()?;
+    // paths are case-insensitive on Windows
+    if my_file_name.map(|mfn| mfn.eq_ignore_ascii_case(file_name)).unwrap_or(false) {
+        Some(Vec::new())
+    } else {
+        None
+    }
+}
+
+/// Appends a servicerestarter service found by [`list`] to `buf` as a JSON object.
+fn push_list_entry_json(buf: &mut String, service: &ServiceEntry, start_type: Option<ServiceStartType>, watched: &[OsString]) {
+    buf.push_str("{\"name\":");
+    json::push_string(buf, &service.name.to_string_lossy());
+    buf.push_str(",\"display_name\":");
+    json::push_string(buf, &service.display_name.to_string_lossy());
+    buf.push_str(",\"state\":");
+    match service.state {
+        Some(s) => json::push_string(buf, &format!("{:?}", s)),
+        None => buf.push_str("null"),
+    }
+    buf.push_str(",\"process_id\":");
+    match service.process_id {
+        Some(pid) => buf.push_str(&pid.to_string()),
+        None => buf.push_str("null"),
+    }
+    buf.push_str(",\"start_type\":");
+    match start_type {
+        Some(st) => json::push_string(buf, &format!("{:?}", st)),
+        None => buf.push_str("null"),
+    }
+    buf.push_str(",\"watched_services\":[");
+    for (i, name) in watched.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        json::push_string(buf, &name.to_string_lossy());
+    }
+    buf.push_str("]}");
+}
+
+/// Lists the servicerestarter services installed on this machine, one per line: name, state,
+/// display name and watched services, separated by tabs.
+///
+/// With `as_json`, writes a single JSON array instead, whose objects also contain the process ID
+/// and the start type of each service.
+fn list(as_json: bool) -> Result<(), CliError> {
+    let my_path = std::env::current_exe();
+    let my_file_name = my_path.as_ref().ok()
+        .and_then(|p| p.file_name());
+
+    let scm_perms =
+        ServiceControlManagerPermissions::CONNECT
+        | ServiceControlManagerPermissions::ENUMERATE_SERVICE
+    ;
+    let scm = ServiceControlManagerHandle::open_local_active(scm_perms)
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
+    let services = scm.enumerate_services()
+        .map_err(|e| CliError::new("failed to enumerate services", &e))?;
+
+    let mut json_output = String::from("[");
+    let mut json_count = 0;
+    for service in &services {
+        let config = scm.open_service(&service.name, ServicePermissions::QUERY_CONFIG)
+            .and_then(|s| s.query_config())
+            .ok();
+        let watched = match servicerestarter_instance(service, config.as_ref(), my_file_name) {
+            Some(w) => w,
+            None => continue,
+        };
+        if as_json {
+            if json_count > 0 {
+                json_output.push(',');
+            }
+            let start_type = config.as_ref().and_then(|c| c.start_type);
+            push_list_entry_json(&mut json_output, service, start_type, &watched);
+            json_count += 1;
+            continue;
+        }
+
+        let state = match service.state {
+            Some(s) => format!("{:?}", s),
+            None => "Unknown".to_owned(),
+        };
+        let watched_strings: Vec<String> = watched.iter()
+            .map(|w| w.to_string_lossy().into_owned())
+            .collect();
+        println!(
+            "{}\t{}\t{}\t{}",
+            service.name.to_string_lossy(),
+            state,
+            service.display_name.to_string_lossy(),
+            watched_strings.join(", "),
+        );
+    }
+    if as_json {
+        json_output.push(']');
+        println!("{}", json_output);
+    }
+    Ok(())
+}
+
+/// Prints whether the service database is locked, by whom and for how long.
+fn scm_lock_status() -> Result<(), CliError> {
+    let scm = ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::QUERY_LOCK_STATUS)
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
+    let lock_status = scm.query_lock_status()
+        .map_err(|e| CliError::new("failed to query the lock status of the service database", &e))?;
+    match lock_status {
+        LockStatus::Unlocked => println!("the service database is not locked"),
+        LockStatus::Locked { owner, duration } => {
+            let owner_str = if owner.is_empty() {
+                "an unknown owner".to_owned()
+            } else {
+                format!("{:?}", owner)
+            };
+            println!("the service database has been locked by {} for {} s", owner_str, duration.as_secs());
+        },
+    }
+    Ok(())
+}
+
+/// Performs the given number of passes over the watched services, sleeping for the regular sleep
+/// duration in between, and returns the process exit code.
+///
+/// If the process is asked to stop while sleeping, the remaining passes are skipped.
+fn check(service_name: OsString, iterations: u64) -> i32 {
+    let my_registry_path = get_my_registry_path(&service_name);
+    let service_info = service_info(&service_name);
+    let registry = open_my_registry(&my_registry_path, RegistryPermissions::QUERY_VALUE);
+
+    let config_path = config_file_path(&service_name);
+    let config = match load_config(config_path.as_deref(), &registry) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            return EXIT_CHECK_ERRORS;
+        },
+    };
+    let loop_settings = match LoopSettings::read(&registry, &config) {
+        Ok(ls) => ls,
+        Err(e) => {
+            error!("{}", e);
+            return EXIT_CHECK_ERRORS;
+        },
+    };
+    let stats_enabled = loop_settings.stats_flush_interval.is_some();
+    let watched_services = loop_settings.watched_services;
+
+    let scm_res = loop_settings.locked_retry_policy.run(
+        "connect to service control manager",
+        || ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT),
+    );
+    let scm = match scm_res {
+        Ok(s) => s,
+        Err(e) => {
+            error!("failed to connect to service control manager: {}", e);
+            return EXIT_CHECK_ERRORS;
+        },
+    };
+    let sleep_duration = config.sleep_duration
+        .unwrap_or_default()
+        .max(config.minimum_sleep_duration.unwrap_or(DEFAULT_MINIMUM_SLEEP_DURATION));
+
+    let mut state = LoopState::new(SharedStatus::new());
+    let mut summary = CheckSummary::default();
+    for iteration in 1..=iterations {
+        if iteration > 1 {
+            let stop_result = service_info.wait_stopper.wait_until_stop_timeout(sleep_duration);
+            if let Some(reason) = stop_result.reason() {
+                info!("skipping the remaining {} passes because of {}", iterations - iteration + 1, reason);
+                break;
+            }
+        }
+        let pass_summary = match check_once(&registry, &scm, watched_services.clone(), &mut state) {
+            Ok(ps) => ps,
+            Err(e) => {
+                error!("not checking the services: {}", e);
+                summary.errored += 1;
+                break;
+            },
+        };
+        info!(
+            "checked {} services: {} restarted, {} errors",
+            pass_summary.checked, pass_summary.restarted, pass_summary.errored,
+        );
+        summary.checked += pass_summary.checked;
+        summary.restarted += pass_summary.restarted;
+        summary.errored += pass_summary.errored;
+    }
+
+    if state.restart_tracking.is_dirty() {
+        let state_registry = open_my_registry(&my_registry_path, RegistryPermissions::QUERY_VALUE | RegistryPermissions::CREATE_SUB_KEY);
+        state.restart_tracking.save(&state_registry);
+    }
+
+    if stats_enabled && state.restart_stats.is_flush_due(Duration::ZERO) {
+        let stats_registry = open_my_registry(&my_registry_path, RegistryPermissions::QUERY_VALUE | RegistryPermissions::CREATE_SUB_KEY);
+        state.restart_stats.flush(&stats_registry);
+    }
+
+    if summary.errored > 0 {
+        EXIT_CHECK_ERRORS
+    } else if summary.restarted > 0 {
+        EXIT_CHECK_STARTED
+    } else {
+        0
+    }
+}
+
+/// Writes the parameters from the given configuration file into the registry and returns the
+/// process exit code.
+///
+/// The whole file is checked first; if it contains any errors, nothing is written.
+fn import_config(service_name: OsString, config_path: &Path) -> i32 {
+    let text = match std::fs::read_to_string(config_path) {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to read configuration file {:?}: {}", config_path, e);
+            return EXIT_IMPORT_FAILED;
+        },
+    };
+    let entries = match crate::config_file::parse(&text) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("failed to parse configuration file {:?}: {}", config_path, e);
+            return EXIT_IMPORT_FAILED;
+        },
+    };
+
+    let mut values = Vec::with_capacity(entries.len());
+    let mut problems: usize = 0;
+    for (name, config_value) in &entries {
+        let kind = match parameter_kind(name) {
+            Some(k) => k,
+            None => {
+                warn!("unknown parameter {:?}; ignoring it", name);
+                continue;
+            },
+        };
+        match to_registry_value(kind, config_value) {
+            Ok(v) => values.push((name, v)),
+            Err(e) => {
+                error!("invalid value for parameter {}: {}", name, e);
+                problems += 1;
+            },
+        }
+    }
+    if problems > 0 {
+        error!("{} problems found; not importing anything", problems);
+        return EXIT_IMPORT_FAILED;
+    }
+
+    let my_registry_path = get_my_registry_path(&service_name);
+    let registry_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        None,
+        RegistryPermissions::CREATE_SUB_KEY | RegistryPermissions::WOW64_64KEY,
+    )
+        .and_then(|hklm| hklm.create_subkey(&my_registry_path, RegistryPermissions::SET_VALUE));
+    let registry = match registry_res {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to open parameters (HKLM subkey {:?}) for writing: {}", my_registry_path, e);
+            return EXIT_IMPORT_FAILED;
+        },
+    };
+
+    for (name, value) in &values {
+        if let Err(e) = registry.write_value(Some(&OsString::from(name)), value) {
+            error!("failed to write parameter {}: {}", name, e);
+            return EXIT_IMPORT_FAILED;
+        }
+        info!("set {} to {}", name, value.preview());
+    }
+    0
+}
+
+/// Checks the configuration without starting any services and returns the process exit code.
+fn validate(service_name: OsString) -> i32 {
+    let my_registry_path = get_my_registry_path(&service_name);
+    let mut problems: usize = 0;
+
+    let registry_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(&my_registry_path),
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
+    );
+    let registry = match registry_res {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to open parameters (HKLM subkey {:?}): {}", my_registry_path, e);
+            return EXIT_VALIDATE_PROBLEMS;
+        },
+    };
+
+    // the configuration file, if any
+    let file_config = match config_file_path(&service_name) {
+        Some(cp) => match Config::from_file(&cp) {
+            Ok(Some(fc)) => {
+                info!("configuration file {:?} is valid", cp);
+                fc
+            },
+            Ok(None) => Config::default(),
+            Err(e) => {
+                error!("failed to read configuration file {:?}: {}", cp, e);
+                problems += 1;
+                Config::default()
+            },
+        },
+        None => Config::default(),
+    };
+
+    // the list of services to watch
+    let registry_names = match registry.read_value(Some(&OsString::from("ServicesExpectedRunning"))) {
+        Ok(RegistryValue::MultiString(names)) => {
+            info!("ServicesExpectedRunning lists {} services", names.len());
+            names
+        },
+        Ok(other) => match watched_services_from_value(other.clone()) {
+            Some(names) => {
+                warn!("ServicesExpectedRunning is a {}; a REG_MULTI_SZ is preferred", other.type_name());
+                info!("ServicesExpectedRunning lists {} services", names.len());
+                names
+            },
+            None => {
+                error!("{}", UnexpectedValueType::new("ServicesExpectedRunning", "REG_MULTI_SZ", &other));
+                problems += 1;
+                Vec::new()
+            },
+        },
+        Err(e) if e.win32_error() == Some(ERROR_FILE_NOT_FOUND) && file_config.watched_services.is_some() => {
+            Vec::new()
+        },
+        Err(e) => {
+            error!("failed to read ServicesExpectedRunning: {}", e);
+            problems += 1;
+            Vec::new()
+        },
+    };
+    let names = match &file_config.watched_services {
+        Some(file_names) => {
+            info!("the configuration file lists {} services, which take precedence", file_names.len());
+            file_names.clone()
+        },
+        None => registry_names,
+    };
+    let names = without_duplicates(without_comments(names));
+
+    // the remaining parameters
+    let parameters_res = Config::load_from_registry(&registry)
+        .and_then(|registry_config| {
+            PassSettings::read(&registry)?;
+            read_probes(&registry, &names)?;
+            let log_rotation = crate::logging::try_read_log_rotation(&registry)?;
+            Ok((file_config.clone().or(registry_config), log_rotation))
+        });
+    let log_path = match parameters_res {
+        Ok((config, log_rotation)) => {
+            if config.sleep_duration.is_some() {
+                info!("service parameters are valid");
+            } else {
+                error!("neither SleepDuration nor SleepDurationMilliseconds is set");
+                problems += 1;
+            }
+            config.log_path.map(|lp| (lp, log_rotation))
+        },
+        Err(e) => {
+            error!("{}", e);
+            problems += 1;
+            None
+        },
+    };
+
+    match log_path {
+        // the log file must be writable (it is not created automatically)
+        Some((lp, LogRotation::None)) => match File::options().append(true).open(&lp) {
+            Ok(_) => info!("log file {:?} is writable", lp),
+            Err(e) => {
+                error!("failed to open log file {:?} for writing: {}", lp, e);
+                problems += 1;
+            },
+        },
+        // daily files are created as needed, but their directory must exist
+        Some((lp, LogRotation::Daily)) => {
+            let directory = Path::new(&lp).parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            if directory.is_dir() {
+                info!("log directory {:?} exists", directory);
+            } else {
+                error!("log directory {:?} does not exist", directory);
+                problems += 1;
+            }
+        },
+        None => {},
+    }
+
+    // the watched services
+    match ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT) {
+        Ok(scm) => {
+            let (names, unresolved) = resolve_watched_services(&scm, names, &mut HashMap::new());
+            problems += unresolved;
+            for name in &names {
+                match scm.service_exists(name) {
+                    Ok(true) => {},
+                    Ok(false) => {
+                        error!("service {:?} is not installed", name);
+                        problems += 1;
+                        continue;
+                    },
+                    Err(e) => {
+                        error!("failed to check whether service {:?} exists: {}", name, e);
+                        problems += 1;
+                        continue;
+                    },
+                }
+
+                let state_res = scm.open_service(name, ServicePermissions::QUERY_STATUS)
+                    .and_then(|service| service.get_state());
+                match state_res {
+                    Ok(state) => info!("service {:?} can be queried; it is {:?}", name, state),
+                    Err(e) => {
+                        error!("failed to query service {:?}: {}", name, e);
+                        problems += 1;
+                    },
+                }
+            }
+        },
+        Err(e) => {
+            error!("failed to connect to service control manager: {}", e);
+            problems += 1;
+        },
+    }
+
+    if problems > 0 {
+        error!("found {} problems", problems);
+        EXIT_VALIDATE_PROBLEMS
+    } else {
+        info!("no problems found");
+        0
+    }
+}
+
+/// Handles control messages for a service. `context` points to the service's [`ServiceInfo`].
+unsafe extern "system" fn service_control(
+    control_value: u32,
+    event_type: u32,
+    _event_data: *mut c_void,
+    context: *mut c_void,
+) -> u32 {
+    // the ServiceInfo is created before the handler is registered, so the context is always valid
+    let service_info = &*(context as *const ServiceInfo);
+    match control_value {
+        SERVICE_CONTROL_INTERROGATE => {
+            // do nothing
+        },
+        SERVICE_CONTROL_STOP => {
+            // signal stop
+            service_info.wait_stopper.stop(StopReason::ControlStop);
+        },
+        SERVICE_CONTROL_PARAMCHANGE => {
+            // check again with the new parameters
+            service_info.wait_stopper.wake();
+        },
+        SERVICE_CONTROL_POWEREVENT => {
+            // hold off restarts around suspend and resume
+            service_info.power_state.power_event(event_type);
+        },
+        SERVICE_CONTROL_SESSIONCHANGE => {
+            // hold off restarts while users switch
+            service_info.power_state.session_change(event_type);
+        },
+        SERVICE_CONTROL_SHUTDOWN => {
+            // stop restarting services, then signal stop
+            service_info.shutting_down.store(true, Ordering::SeqCst);
+            service_info.wait_stopper.stop(StopReason::Shutdown);
+        },
+        other => {
+            debug!("ignoring service control {} (event type {})", other, event_type);
+            return ERROR_CALL_NOT_IMPLEMENTED.0;
+        },
+    }
+    NO_ERROR.0
+}
+
+unsafe extern "system" fn console_control(control_type: u32) -> BOOL {
+    match control_type {
+        CTRL_C_EVENT|CTRL_BREAK_EVENT => {
+            // signal stop
+            let services = SERVICE_INFO
+                .read().expect_log("SERVICE_INFO poisoned");
+            for service_info in services.values() {
+                service_info.wait_stopper.stop(StopReason::ConsoleCtrl);
+            }
+            true.into()
+        },
+        _ => {
+            // let the next handler take care of it
+            false.into()
+        },
+    }
+}
+
+extern "system" fn run_service(num_args: u32, args: *mut PWSTR) {
+    if num_args < 1 {
+        log_panic!("no arguments passed to run_service!");
+    }
+
+    let service_name_pwstr = unsafe { *args };
+    let service_name_ws = WideString::from(service_name_pwstr.0);
+    let service_name = service_name_ws.to_os_string();
+
+    // register our signalling procedure with the event pumping thread; the entry in SERVICE_INFO
+    // outlives the service, so the pointer remains valid
+    let service_info = service_info(&service_name);
+    let context = Arc::as_ptr(&service_info) as *const c_void;
+    let service_status_handle = register_service_control_handler_ex(&service_name, Some(service_control), context)
+        .expect_log("failed to register service control handler");
+    if service_info.service_status_handle.set(service_status_handle).is_err() {
+        log_panic!("service {:?} started twice?!", service_name);
+    }
+
+    // keep the service control manager from giving up on us while we prepare
+    service_info.report_start_pending(STARTUP_STEP_WAIT_HINT);
+    warn_on_binary_path_drift(&service_name);
+
+    // announce that we are running
+    let controls_accepted =
+        SERVICE_ACCEPT_STOP
+        | SERVICE_ACCEPT_SHUTDOWN
+        | SERVICE_ACCEPT_PARAMCHANGE
+        | SERVICE_ACCEPT_POWEREVENT
+        | SERVICE_ACCEPT_SESSIONCHANGE
+    ;
+    service_info.set_state(SERVICE_RUNNING, controls_accepted);
+
+    run(service_name, None, None);
+
+    // announce that we are stopped
+    service_info.set_state(SERVICE_STOPPED, 0);
+}
+
+
+/// An error that ends a command-line mode.
+#[derive(Debug)]
+struct CliError {
+    message: String,
+    exit_code: i32,
+}
+impl CliError {
+    /// Describes a failed operation; the exit code is derived from the error.
+    fn new(context: &str, error: &Error) -> Self {
+        Self {
+            message: format!("{}: {}{}", context, error, ErrorHint(error)),
+            exit_code: exit_code_for_error(error),
+        }
+    }
+
+    /// Prints the message to stderr and exits with the exit code.
+    fn exit(self) -> ! {
+        eprintln!("error: {}", self.message);
+        std::process::exit(self.exit_code);
+    }
+}
 
-use crate::args::{Args, OperMode};
-use crate::extensions::ExpectExtension;
-use crate::registry::{PredefinedKey, RegistryKeyHandle, RegistryPermissions, RegistryValue};
-use crate::service_control::{
-    ServiceControlManagerHandle, ServiceControlManagerPermissions, ServiceErrorControl,
-    ServicePermissions, ServiceStartType, ServiceState, ServiceType,
-};
-use crate::service_running::{
-    register_service_control_handler, ServiceStatusHandle, ServiceTableEntry,
-    start_service_dispatcher,
-};
-use crate::wait_stopper::WaitStopper;
-use crate::windows_utils::WideString;
+/// Advice on how to deal with some errors that are common when controlling services. Displays as
+/// nothing for other errors, so that it can be appended to any error message.
+struct ErrorHint<'a>(&'a Error);
+impl fmt::Display for ErrorHint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.win32_error() {
+            Some(ERROR_ACCESS_DENIED) => write!(
+                f,
+                "; the account servicerestarter runs as needs the right to query, start and stop the service (see its security descriptor, e.g. using `sc sdshow`)",
+            ),
+            Some(ERROR_SERVICE_MARKED_FOR_DELETE) => write!(
+                f,
+                "; service pending deletion; close the Services console and other programs that have it open, or reboot",
+            ),
+            _ => Ok(()),
+        }
+    }
+}
 
+/// The exit code of a command-line mode for the given error.
+fn exit_code_for_error(error: &Error) -> i32 {
+    match error.win32_error() {
+        Some(ERROR_SERVICE_DOES_NOT_EXIST) => EXIT_SERVICE_NOT_FOUND,
+        Some(ERROR_ACCESS_DENIED) => EXIT_ACCESS_DENIED,
+        Some(ERROR_SERVICE_REQUEST_TIMEOUT) | Some(ERROR_SERVICE_DATABASE_LOCKED) => EXIT_TIMEOUT,
+        _ => EXIT_CONTROL_FAILED,
+    }
+}
 
-struct ServiceInfo {
-    pub wait_stopper: WaitStopper,
-    pub service_status_handle: ServiceStatusHandle,
+/// Rejects service names that the service control manager would not accept, with a clearer error
+/// than it would give.
+fn check_service_name(service_name: &OsStr) -> Result<(), CliError> {
+    validate_service_name(service_name)
+        .map_err(|e| CliError {
+            message: format!("invalid service name {:?}: {}", service_name, e),
+            exit_code: EXIT_CONTROL_FAILED,
+        })
 }
 
+/// Fails if a parameters key is given along with more than one service: the services would share
+/// one command line and therefore one parameters key.
+fn check_params_key(service_names: &[OsString], params_key: Option<&OsStr>) -> Result<(), CliError> {
+    if params_key.is_some() && service_names.len() > 1 {
+        return Err(CliError {
+            message: "a parameters key can only be given when installing a single service".to_owned(),
+            exit_code: EXIT_CONTROL_FAILED,
+        });
+    }
+    Ok(())
+}
 
-static SERVICE_INFO: OnceCell<Option<ServiceInfo>> = OnceCell::new();
+/// Writes the last `line_count` lines of the log file of the given service to stdout, then keeps
+/// writing appended lines if `follow` is set.
+fn logs(service_name: &OsStr, line_count: usize, follow: bool) -> Result<(), CliError> {
+    let file_config = match config_file_path(service_name) {
+        Some(cp) => Config::from_file(&cp)
+            .map_err(|e| CliError {
+                message: format!("failed to read configuration file {:?}: {}", cp, e),
+                exit_code: EXIT_LOGS_FAILED,
+            })?,
+        None => None,
+    };
+    let my_registry_path = get_my_registry_path(service_name);
+    let log_path = crate::logging::configured_log_path(PredefinedKey::LocalMachine, &my_registry_path, file_config.as_ref())
+        .ok_or_else(|| CliError {
+            message: "LogPath is set neither in the configuration file nor in the registry".to_owned(),
+            exit_code: EXIT_LOGS_FAILED,
+        })?;
 
+    let stdout = std::io::stdout();
+    tail(Path::new(&log_path), line_count, follow, &mut stdout.lock())
+        .map_err(|e| CliError {
+            message: format!("failed to read log file {:?}: {}", log_path, e),
+            exit_code: EXIT_LOGS_FAILED,
+        })
+}
 
-fn get_my_registry_path(service_name: &OsStr) -> OsString {
-    let mut mrp = OsString::new();
-    mrp.push("SYSTEM\\CurrentControlSet\\Services\\");
-    mrp.push(&service_name);
-    mrp.push("\\Parameters");
-    mrp
+/// Prints the values of the parameters key in the format of a `.reg` file.
+fn export_config(service_name: &OsStr) -> Result<(), CliError> {
+    let my_registry_path = get_my_registry_path(service_name);
+    let registry = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(&my_registry_path),
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
+    )
+        .map_err(|e| CliError::new(&format!("failed to open parameters (HKLM subkey {:?})", my_registry_path), &e))?;
+    let values = registry.enumerate_values()
+        .map_err(|e| CliError::new("failed to enumerate service parameters", &e))?;
+    let key_path = format!("HKEY_LOCAL_MACHINE\\{}", my_registry_path.to_string_lossy());
+    print!("{}", to_reg_file(&key_path, &values));
+    Ok(())
 }
 
+/// Starts the given service, retrying while the service database is locked.
+fn start(service_name: &OsStr) -> Result<(), CliError> {
+    check_service_name(service_name)?;
 
-fn run(service_name: OsString) {
-    let my_registry_path = get_my_registry_path(&service_name);
+    let retry_policy = read_cli_locked_retry_policy(service_name);
 
-    let mut is_first_loop: bool = true;
-    loop {
-        // check our settings in the registry
-        let registry_res = RegistryKeyHandle::open_predefined(
-            PredefinedKey::LocalMachine,
-            Some(&my_registry_path),
-            RegistryPermissions::QUERY_VALUE,
-        );
-        let registry = match registry_res {
-            Ok(r) => r,
-            Err(e) => log_panic!("failed to open my registry path (HKLM subkey {:?}): {}", my_registry_path, e),
-        };
+    // open connection to SCM
+    let scm_conn = retry_policy.run(
+        "connect to service control manager",
+        || ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT),
+    )
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
 
-        if is_first_loop {
-            is_first_loop = false;
+    // open service
+    let service = scm_conn.open_service(
+        service_name,
+        ServicePermissions::START,
+    )
+        .map_err(|e| CliError::new("failed to open service", &e))?;
 
-            // query initial sleep duration
-            let initial_sleep_duration_ms_value = registry.read_value_optional(Some(&OsString::from("InitialSleepDurationMilliseconds")))
-                .expect_log("failed to read service parameter InitialSleepDurationMilliseconds");
-            if let Some(isdv) = initial_sleep_duration_ms_value {
-                let milliseconds: u64 = match isdv {
-                    RegistryValue::Dword(dw) => dw.into(),
-                    RegistryValue::DwordBigEndian(dw) => dw.into(),
-                    RegistryValue::Qword(qw) => qw,
-                    other => log_panic!("unexpected service parameter InitialSleepDurationMilliseconds value {:?}", other),
-                };
+    // start service
+    retry_policy.run("start service", || service.start(vec![service_name]))
+        .map_err(|e| CliError::new("failed to start service", &e))
+}
 
-                // sleep
-                let wait_stopper = SERVICE_INFO
-                    .get().expect_log("SERVICE_INFO not set")
-                    .as_ref().map(|si| &si.wait_stopper);
-                let stop_result = WaitStopper::wait_until_stop_timeout_opt(wait_stopper, Duration::from_millis(milliseconds));
-                if stop_result.wants_to_stop() {
-                    // get out
-                    return;
-                }
+/// Waits up to `timeout` for the given service to be running.
+fn wait_until_running(service_name: &OsStr, timeout: Duration) -> Result<(), CliError> {
+    let scm_conn = ServiceControlManagerHandle::open_local_active(
+        ServiceControlManagerPermissions::CONNECT,
+    )
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
+    let service = scm_conn.open_service(
+        service_name,
+        ServicePermissions::QUERY_STATUS,
+    )
+        .map_err(|e| CliError::new("failed to open service", &e))?;
+
+    let wait_start = Instant::now();
+    let running = service.wait_for_state(ServiceState::Running, timeout, START_POLL_INTERVAL)
+        .map_err(|e| CliError::new("failed to obtain service state", &e))?;
+    let elapsed = wait_start.elapsed();
+    if !running {
+        let state = service.get_state()
+            .map_err(|e| CliError::new("failed to obtain service state", &e))?;
+        info!("gave up waiting for service {:?} to be running after {} ms", service_name, elapsed.as_millis());
+        return Err(CliError {
+            message: format!("service {:?} is {:?} and not running after {} s", service_name, state, timeout.as_secs()),
+            exit_code: EXIT_TIMEOUT,
+        });
+    }
+    info!("service {:?} was running {} ms after it was started", service_name, elapsed.as_millis());
+    Ok(())
+}
+
+/// Asks the given service to stop, retrying while the service database is locked. Returns the
+/// state the service reports afterwards.
+fn stop(service_name: &OsStr) -> Result<ServiceState, CliError> {
+    check_service_name(service_name)?;
+
+    let retry_policy = read_cli_locked_retry_policy(service_name);
+
+    // open connection to SCM
+    let scm_conn = retry_policy.run(
+        "connect to service control manager",
+        || ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT),
+    )
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
+
+    // open service
+    let service = scm_conn.open_service(
+        service_name,
+        ServicePermissions::STOP,
+    )
+        .map_err(|e| CliError::new("failed to open service", &e))?;
+
+    // stop service
+    retry_policy.run("stop service", || service.stop())
+        .map_err(|e| CliError::new("failed to stop service", &e))
+}
+
+/// Installs this executable as the given services or, if a service already exists, updates its
+/// configuration to point at this executable.
+///
+/// If multiple services are given, they are set up to share a single process.
+fn install(service_names: &[OsString], params_key: Option<&OsStr>) -> Result<(), CliError> {
+    for service_name in service_names {
+        check_service_name(service_name)?;
+    }
+    check_params_key(service_names, params_key)?;
+
+    let my_path = std::env::current_exe()
+        .map_err(|e| CliError {
+            message: format!("failed to obtain executable path: {}", e),
+            exit_code: EXIT_CONTROL_FAILED,
+        })?;
+    let my_path_os = my_path.as_os_str();
+    let mut my_path_quoted_os = if my_path_os.to_string_lossy().contains(' ') {
+        let mut pqos = OsString::with_capacity(my_path_os.len() + 2);
+        pqos.push("\"");
+        pqos.push(my_path_os);
+        pqos.push("\"");
+        pqos
+    } else {
+        my_path_os.to_os_string()
+    };
+    if let Some(pk) = params_key {
+        my_path_quoted_os.push(" --params-key ");
+        my_path_quoted_os.push(quote_argument(pk));
+    }
+    my_path_quoted_os.push(" service");
+    for service_name in service_names {
+        my_path_quoted_os.push(" ");
+        my_path_quoted_os.push(service_name);
+    }
+
+    let service_type = if service_names.len() > 1 {
+        ServiceType::WIN32_SHARE_PROCESS
+    } else {
+        ServiceType::WIN32_OWN_PROCESS
+    };
+
+    // open connection to SCM
+    let scm_perms =
+        ServiceControlManagerPermissions::CONNECT
+        | ServiceControlManagerPermissions::CREATE_SERVICE
+    ;
+    let scm_conn = ServiceControlManagerHandle::open_local_active(scm_perms)
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
+
+    for service_name in service_names {
+        // a service that is pending deletion vanishes once the last handle to it is closed
+        let mut attempt = 1;
+        loop {
+            match install_one(&scm_conn, service_name, service_type, &my_path_quoted_os) {
+                Ok(()) => break,
+                Err((_, e)) if e.win32_error() == Some(ERROR_SERVICE_MARKED_FOR_DELETE) && attempt < MARKED_FOR_DELETE_ATTEMPTS => {
+                    warn!(
+                        "service {:?} is pending deletion; trying again in {} s (attempt {}/{})",
+                        service_name, MARKED_FOR_DELETE_RETRY_DELAY.as_secs(), attempt, MARKED_FOR_DELETE_ATTEMPTS,
+                    );
+                    sleep(MARKED_FOR_DELETE_RETRY_DELAY);
+                    attempt += 1;
+                },
+                Err((context, e)) => return Err(CliError::new(context, &e)),
             }
         }
+    }
+    Ok(())
+}
 
-        // query services that need to be running
-        let run_services = registry.read_value(Some(&OsString::from("ServicesExpectedRunning")))
-            .expect_log("failed to read service parameter ServicesExpectedRunning");
-        if let RegistryValue::MultiString(names) = run_services {
-            // connect to service control manager
-            let scm = ServiceControlManagerHandle::open_local_active(
-                ServiceControlManagerPermissions::CONNECT,
-            )
-                .expect_log("failed to connect to service control manager");
+/// Creates the given service or, if it already exists, updates its configuration. On failure,
+/// returns a description of the failed step along with the error.
+fn install_one(
+    scm_conn: &ServiceControlManagerHandle,
+    service_name: &OsStr,
+    service_type: ServiceType,
+    command_line: &OsStr,
+) -> Result<(), (&'static str, Error)> {
+    let options = ServiceInstallOptions::new(service_name, command_line)
+        .service_type(service_type)
+        .start_type(ServiceStartType::Demand)
+        .error_control(ServiceErrorControl::Normal);
 
-            for name in &names {
-                // open the service
-                let service_res = scm.open_service(
-                    name,
-                    ServicePermissions::QUERY_STATUS | ServicePermissions::STOP,
-                );
-                let service = match service_res {
-                    Ok(s) => s,
-                    Err(e) => {
-                        log_panic!("failed to open service {:?}: {}", name, e);
-                    },
-                };
+    let service_exists = scm_conn.service_exists(service_name)
+        .map_err(|e| ("failed to check whether the service exists", e))?;
+    if service_exists {
+        // update the existing service instead (e.g. the executable has moved)
+        let service = scm_conn.open_service(
+            service_name,
+            ServicePermissions::CHANGE_CONFIG,
+        )
+            .map_err(|e| ("failed to open existing service", e))?;
+        service.change_config(
+            Some(options.service_type),
+            Some(options.start_type),
+            Some(options.error_control),
+            Some(&options.path_and_args),
+            Some(options.display_name.as_deref().unwrap_or(service_name)),
+        )
+            .map_err(|e| ("failed to update existing service", e))?;
+        info!("existing service {:?} updated", service_name);
+    } else {
+        // create service
+        scm_conn.create_service_with(&options)
+            .map_err(|e| ("failed to create service", e))?;
+        info!("service {:?} installed", service_name);
+    }
+    Ok(())
+}
 
-                // query its state
-                let service_state = match service.get_state() {
-                    Ok(ss) => ss,
-                    Err(e) => {
-                        log_panic!("failed to get service {:?} state: {}", name, e);
-                    },
-                };
+/// Deletes the given service, stopping it first if necessary and waiting up to `stop_timeout` for
+/// it to stop.
+fn delete(service_name: &OsStr, stop_timeout: Duration) -> Result<(), CliError> {
+    check_service_name(service_name)?;
 
-                if service_state == ServiceState::Stopped {
-                    // start it
-                    if let Err(e) = service.start(vec![]) {
-                        log_panic!("failed to start service {:?}: {}", name, e);
-                    }
-                }
+    // open connection to SCM
+    let scm_conn = ServiceControlManagerHandle::open_local_active(
+        ServiceControlManagerPermissions::CONNECT,
+    )
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
+
+    // open service
+    let service = scm_conn.open_service(
+        service_name,
+        ServicePermissions::QUERY_STATUS | ServicePermissions::STOP | ServicePermissions::DELETE,
+    )
+        .map_err(|e| CliError::new("failed to open service", &e))?;
+
+    // check if the service is stopped
+    let service_state = service.get_state()
+        .map_err(|e| CliError::new("failed to obtain service state", &e))?;
+    if !service_state.is_stopped() {
+        // stop the service
+        let service_state = service.stop()
+            .map_err(|e| CliError::new("failed to stop service", &e))?;
+        info!("service is now {:?}", service_state);
+
+        // deleting a service that is still stopping fails
+        if !service_state.is_stopped() {
+            info!("waiting up to {} s for the service to stop", stop_timeout.as_secs());
+            let stopped = service.wait_for_state(ServiceState::Stopped, stop_timeout, STOP_POLL_INTERVAL)
+                .map_err(|e| CliError::new("failed to obtain service state", &e))?;
+            if !stopped {
+                return Err(CliError {
+                    message: format!("service did not stop within {} s; not deleting it", stop_timeout.as_secs()),
+                    exit_code: EXIT_TIMEOUT,
+                });
             }
-        } else {
-            log_panic!("unexpected service parameter ServicesExpectedRunning value {:?}", run_services);
+            info!("service is now Stopped");
         }
+    }
 
-        // query regular sleep duration
-        let sleep_duration_ms_value = registry.read_value(Some(&OsString::from("SleepDurationMilliseconds")))
-            .expect_log("failed to read service parameter SleepDurationMilliseconds");
-        let milliseconds: u64 = match sleep_duration_ms_value {
-            RegistryValue::Dword(dw) => dw.into(),
-            RegistryValue::DwordBigEndian(dw) => dw.into(),
-            RegistryValue::Qword(qw) => qw,
-            other => log_panic!("unexpected service parameter SleepDurationMilliseconds value {:?}", other),
-        };
+    // remove the service
+    service.delete()
+        .map_err(|e| CliError::new("failed to delete service", &e))
+}
 
-        // sleep
-        let wait_stopper = SERVICE_INFO
-            .get().expect_log("SERVICE_INFO not set")
-            .as_ref().map(|si| &si.wait_stopper);
-        let stop_result = WaitStopper::wait_until_stop_timeout_opt(wait_stopper, Duration::from_millis(milliseconds));
-        if stop_result.wants_to_stop() {
-            // get out
-            return;
+/// Waits up to `timeout` for the given service to disappear after it has been deleted. Returns
+/// whether it did.
+fn wait_until_deleted(scm_conn: &ServiceControlManagerHandle, service_name: &OsStr, timeout: Duration) -> Result<bool, CliError> {
+    let start_time = Instant::now();
+    loop {
+        let exists = scm_conn.service_exists(service_name)
+            .map_err(|e| CliError::new("failed to check whether the service exists", &e))?;
+        if !exists {
+            return Ok(true);
         }
+        let elapsed = start_time.elapsed();
+        if elapsed >= timeout {
+            return Ok(false);
+        }
+        sleep(DELETE_POLL_INTERVAL.min(timeout - elapsed));
     }
 }
 
-extern "system" fn service_control(control_value: u32) {
-    match control_value {
-        SERVICE_CONTROL_INTERROGATE => {
-            // do nothing
-            return;
-        },
-        SERVICE_CONTROL_STOP => {
-            // signal stop
-            SERVICE_INFO
-                .get().expect_log("SERVICE_INFO not set")
-                .as_ref().expect_log("SERVICE_INFO empty")
-                .wait_stopper.stop();
-        },
-        _ => {},
-    }
+/// The values and subkeys of a registry key, saved so that they can be written back.
+struct SavedKey {
+    values: Vec<(OsString, RegistryValue)>,
+    subkeys: Vec<(OsString, SavedKey)>,
 }
+impl SavedKey {
+    /// Reads the values of the given key and, recursively, of its subkeys. The key must have been
+    /// opened with `QUERY_VALUE` and `ENUMERATE_SUB_KEYS`.
+    fn read(key: &RegistryKeyHandle) -> Result<Self, Error> {
+        let values = key.enumerate_values()?;
+        let mut subkeys = Vec::new();
+        for name in key.enumerate_subkeys()? {
+            let subkey = key.open_subkey(
+                Some(&name),
+                RegistryPermissions::QUERY_VALUE | RegistryPermissions::ENUMERATE_SUB_KEYS,
+            )?;
+            subkeys.push((name, Self::read(&subkey)?));
+        }
+        Ok(Self { values, subkeys })
+    }
 
-extern "system" fn run_service(num_args: u32, args: *mut PWSTR) {
-    if num_args < 1 {
-        log_panic!("no arguments passed to run_service!");
+    /// Writes the values into the given key and recreates the subkeys below it. The key must have
+    /// been opened with `SET_VALUE` and `CREATE_SUB_KEY`.
+    fn write(&self, key: &RegistryKeyHandle) -> Result<(), Error> {
+        for (name, value) in &self.values {
+            key.write_value(Some(name), value)?;
+        }
+        for (name, saved_subkey) in &self.subkeys {
+            let subkey = key.create_subkey(
+                name,
+                RegistryPermissions::SET_VALUE | RegistryPermissions::CREATE_SUB_KEY,
+            )?;
+            saved_subkey.write(&subkey)?;
+        }
+        Ok(())
     }
 
-    let service_name_pwstr = unsafe { *args };
-    let service_name_ws = WideString::from(service_name_pwstr.0);
-    let service_name = service_name_ws.to_os_string();
+    /// The number of values in this key and all its subkeys.
+    fn value_count(&self) -> usize {
+        self.values.len()
+            + self.subkeys.iter().map(|(_name, sk)| sk.value_count()).sum::<usize>()
+    }
 
-    // register our signalling procedure with the event pumping thread
-    let service_status_handle = register_service_control_handler(&service_name, Some(service_control))
-        .expect_log("failed to register service control handler");
+    /// Serializes this key (at the given path) and its subkeys in the format of `.reg` files.
+    fn to_reg_file(&self, key_path: &str) -> String {
+        let mut keys = Vec::new();
+        self.collect_keys(key_path.to_owned(), &mut keys);
+        keys_to_reg_file(&keys)
+    }
+
+    fn collect_keys<'a>(&'a self, key_path: String, keys: &mut Vec<(String, &'a [(OsString, RegistryValue)])>) {
+        keys.push((key_path.clone(), &self.values));
+        for (name, saved_subkey) in &self.subkeys {
+            let subkey_path = format!("{}\\{}", key_path, name.to_string_lossy());
+            saved_subkey.collect_keys(subkey_path, keys);
+        }
+    }
+}
 
-    let service_info = ServiceInfo {
-        wait_stopper: WaitStopper::new(),
-        service_status_handle,
+/// Reads the default parameters key of the given service including its subkeys (probes, restart
+/// state, statistics), all of which are deleted along with the service. Returns `None` if the key
+/// does not exist.
+fn read_default_parameters(service_name: &OsStr) -> Result<Option<SavedKey>, CliError> {
+    let registry_path = get_default_registry_path(service_name);
+    let registry_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        Some(&registry_path),
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::ENUMERATE_SUB_KEYS | RegistryPermissions::WOW64_64KEY,
+    );
+    let registry = match registry_res {
+        Ok(r) => r,
+        Err(e) if e.win32_error() == Some(ERROR_FILE_NOT_FOUND) => return Ok(None),
+        Err(e) => return Err(CliError::new("failed to open the service parameters", &e)),
     };
+    SavedKey::read(&registry)
+        .map(Some)
+        .map_err(|e| CliError::new("failed to read the service parameters", &e))
+}
+
+/// Writes the given values and subkeys into the default parameters key of the given service.
+fn write_default_parameters(service_name: &OsStr, saved: &SavedKey) -> Result<(), CliError> {
+    let registry_path = get_default_registry_path(service_name);
+    let registry = RegistryKeyHandle::open_predefined(
+        PredefinedKey::LocalMachine,
+        None,
+        RegistryPermissions::CREATE_SUB_KEY | RegistryPermissions::WOW64_64KEY,
+    )
+        .and_then(|hklm| hklm.create_subkey(
+            &registry_path,
+            RegistryPermissions::SET_VALUE | RegistryPermissions::CREATE_SUB_KEY,
+        ))
+        .map_err(|e| CliError::new("failed to open the service parameters for writing", &e))?;
+    saved.write(&registry)
+        .map_err(|e| CliError::new("failed to restore the service parameters", &e))
+}
 
-    // don't care either way
-    match SERVICE_INFO.set(Some(service_info)) {
-        Ok(_) => {},
-        Err(_) => {},
+/// Deletes the given services if they exist, waits for them to disappear and installs them again.
+///
+/// Unless `discard_parameters` is set, the values and subkeys of their default parameters keys,
+/// which go away with the services, are restored afterwards. A parameters key that did not exist
+/// before is not created.
+fn reinstall(
+    service_names: &[OsString],
+    params_key: Option<&OsStr>,
+    stop_timeout: Duration,
+    discard_parameters: bool,
+) -> Result<(), CliError> {
+    for service_name in service_names {
+        check_service_name(service_name)?;
     }
+    // before anything is deleted
+    check_params_key(service_names, params_key)?;
 
-    // announce that we are running
-    let service_status = SERVICE_STATUS {
-        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
-        dwCurrentState: SERVICE_RUNNING,
-        dwControlsAccepted: SERVICE_ACCEPT_STOP,
-        dwWin32ExitCode: NO_ERROR.0,
-        dwServiceSpecificExitCode: NO_ERROR.0,
-        dwCheckPoint: 0,
-        dwWaitHint: 0,
-    };
-    SERVICE_INFO
-        .get().expect_log("SERVICE_INFO not set?!")
-        .as_ref().expect_log("SERVICE_INFO empty?!")
-        .service_status_handle
-        .set_status(service_status).expect_log("failed to set service status");
+    let scm_conn = ServiceControlManagerHandle::open_local_active(
+        ServiceControlManagerPermissions::CONNECT,
+    )
+        .map_err(|e| CliError::new("failed to connect to service control manager", &e))?;
 
-    run(service_name);
+    let mut saved_parameters = Vec::new();
+    for service_name in service_names {
+        let service_exists = scm_conn.service_exists(service_name)
+            .map_err(|e| CliError::new("failed to check whether the service exists", &e))?;
+        if !service_exists {
+            info!("service {:?} does not exist yet", service_name);
+            continue;
+        }
 
-    // announce that we are stopped
-    let service_status = SERVICE_STATUS {
-        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
-        dwCurrentState: SERVICE_STOPPED,
-        dwControlsAccepted: 0,
-        dwWin32ExitCode: NO_ERROR.0,
-        dwServiceSpecificExitCode: NO_ERROR.0,
-        dwCheckPoint: 0,
-        dwWaitHint: 0,
-    };
-    SERVICE_INFO
-        .get().expect_log("SERVICE_INFO not set?!")
-        .as_ref().expect_log("SERVICE_INFO empty?!")
-        .service_status_handle
-        .set_status(service_status).expect_log("failed to set service status");
+        if !discard_parameters {
+            match read_default_parameters(service_name)? {
+                Some(saved) => {
+                    info!("saved {} parameters of service {:?}", saved.value_count(), service_name);
+                    saved_parameters.push((service_name, saved));
+                },
+                None => info!("service {:?} has no parameters key", service_name),
+            }
+        }
+
+        delete(service_name, stop_timeout)?;
+        if !wait_until_deleted(&scm_conn, service_name, DELETE_WAIT_TIMEOUT)? {
+            return Err(CliError {
+                message: format!(
+                    "service {:?} still exists {} s after deleting it; it is pending deletion; close the Services console and other programs that have it open, or reboot",
+                    service_name, DELETE_WAIT_TIMEOUT.as_secs(),
+                ),
+                exit_code: EXIT_TIMEOUT,
+            });
+        }
+        info!("service {:?} deleted", service_name);
+    }
+
+    let installed = install(service_names, params_key);
+    if installed.is_err() {
+        // the parameters are gone from the registry; at least keep them from vanishing entirely
+        for (service_name, saved) in &saved_parameters {
+            let key_path = format!("HKEY_LOCAL_MACHINE\\{}", get_default_registry_path(service_name).to_string_lossy());
+            error!("the saved parameters of service {:?} were:\n{}", service_name, saved.to_reg_file(&key_path));
+        }
+    }
+    installed?;
+
+    for (service_name, saved) in &saved_parameters {
+        write_default_parameters(service_name, saved)?;
+        info!("restored {} parameters of service {:?}", saved.value_count(), service_name);
+    }
+    Ok(())
 }
 
+/// Starts each of the given services and waits for it to be running.
+fn start_and_wait(service_names: &[OsString]) -> Result<(), CliError> {
+    for service_name in service_names {
+        start(service_name)?;
+        wait_until_running(service_name, START_WAIT_TIMEOUT)?;
+        info!("service {:?} is running", service_name);
+    }
+    Ok(())
+}
 
 fn main() {
     let arguments = Args::parse();
+    if let Some(pk) = &arguments.params_key {
+        PARAMETERS_KEY_OVERRIDE.set((arguments.service_name.clone(), pk.clone()))
+            .expect_log("parameters key already set?!");
+    }
+
+    // only used when logging to stderr; file logging reads it along with its other settings
+    let log_format = crate::logging::configured_log_format(
+        PredefinedKey::LocalMachine,
+        &get_my_registry_path(&arguments.service_name),
+    );
 
     match arguments.mode {
         OperMode::Run => {
             // run in foreground
-            crate::logging::enable_stderr(Level::Info);
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            register_service_info(&arguments.service_name, false);
+
+            // stop cleanly on Ctrl+C and Ctrl+Break
+            add_console_ctrl_handler(Some(console_control))
+                .expect_log("failed to register console control handler");
+
+            run(arguments.service_name, arguments.max_runtime, arguments.iterations);
+        },
+        OperMode::Check => {
+            // perform a single pass (or a few) in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            register_service_info(&arguments.service_name, false);
+
+            // cut the wait between passes short on Ctrl+C and Ctrl+Break
+            add_console_ctrl_handler(Some(console_control))
+                .expect_log("failed to register console control handler");
+
+            let exit_code = check(arguments.service_name, arguments.iterations.unwrap_or(1));
+            std::process::exit(exit_code);
+        },
+        OperMode::Validate => {
+            // check the configuration in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            let exit_code = validate(arguments.service_name);
+            std::process::exit(exit_code);
+        },
+        OperMode::ExportConfig => {
+            // dump the configuration in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
 
-            match SERVICE_INFO.set(None) {
-                Ok(_) => {},
-                Err(_) => {},
+            if let Err(e) = export_config(&arguments.service_name) {
+                e.exit();
             }
+        },
+        OperMode::ImportConfig => {
+            // write the configuration in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            let config_file = arguments.config_file
+                .expect_log("no configuration file given");
+            let exit_code = import_config(arguments.service_name, Path::new(&config_file));
+            std::process::exit(exit_code);
+        },
+        OperMode::List => {
+            // list our services in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            if let Err(e) = list(arguments.json) {
+                e.exit();
+            }
+        },
+        OperMode::Logs => {
+            // show the log file in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            let line_count = arguments.lines
+                .map(|l| usize::try_from(l).unwrap_or(usize::MAX))
+                .unwrap_or(DEFAULT_LOG_LINES);
+            if let Err(e) = logs(&arguments.service_name, line_count, arguments.follow) {
+                e.exit();
+            }
+        },
+        OperMode::ScmLockStatus => {
+            // query the lock status in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            if let Err(e) = scm_lock_status() {
+                e.exit();
+            }
+        },
+        OperMode::SelfTest => {
+            // exercise the registry in the foreground
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
 
-            run(arguments.service_name);
+            let exit_code = self_test();
+            std::process::exit(exit_code);
         },
         OperMode::Service => {
-            // run as service
+            // run as service; logging is configured by the first service
             let my_registry_path = get_my_registry_path(&arguments.service_name);
-            crate::logging::enable_file_from_registry(PredefinedKey::LocalMachine, &my_registry_path);
+            let file_config = config_file_path(&arguments.service_name)
+                .and_then(|cp| match Config::from_file(&cp) {
+                    Ok(fc) => fc,
+                    Err(e) => {
+                        // logging is not set up yet
+                        eprintln!("failed to read configuration file {:?}: {}", cp, e);
+                        None
+                    },
+                });
+            crate::logging::enable_file_from_registry(PredefinedKey::LocalMachine, &my_registry_path, file_config.as_ref());
 
-            let service_table = [
-                ServiceTableEntry {
-                    name: arguments.service_name.clone(),
-                    main_func: Some(run_service),
-                },
-            ];
+            let shared_process = arguments.service_names.len() > 1;
+            let service_table: Vec<ServiceTableEntry> = arguments.service_names.iter()
+                .map(|name| {
+                    register_service_info(name, shared_process);
+                    ServiceTableEntry {
+                        name: name.clone(),
+                        main_func: Some(run_service),
+                    }
+                })
+                .collect();
             start_service_dispatcher(&service_table)
                 .expect_log("failed to start service dispatcher");
         },
         OperMode::Start => {
             // start service
-            crate::logging::enable_stderr(Level::Info);
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
 
-            // open connection to SCM
-            let scm_conn = ServiceControlManagerHandle::open_local_active(
-                ServiceControlManagerPermissions::CONNECT,
-            )
-                .expect_log("failed to connect to service control manager");
-
-            // open service
-            let service = scm_conn.open_service(
-                &arguments.service_name,
-                ServicePermissions::START,
-            )
-                .expect_log("failed to open service");
-
-            // start service
-            service.start(vec![&arguments.service_name])
-                .expect_log("failed to start service");
+            if let Err(e) = start(&arguments.service_name) {
+                e.exit();
+            }
         },
         OperMode::Stop => {
             // stop service
-            crate::logging::enable_stderr(Level::Info);
-
-            // open connection to SCM
-            let scm_conn = ServiceControlManagerHandle::open_local_active(
-                ServiceControlManagerPermissions::CONNECT,
-            )
-                .expect_log("failed to connect to service control manager");
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
 
-            // open service
-            let service = scm_conn.open_service(
-                &arguments.service_name,
-                ServicePermissions::STOP,
-            )
-                .expect_log("failed to open service");
-
-            // stop service
-            service.stop()
-                .expect_log("failed to stop service");
+            match stop(&arguments.service_name) {
+                Ok(service_state) => info!("service is now {:?}", service_state),
+                Err(e) => e.exit(),
+            }
         },
         OperMode::Install => {
             // install service
-            crate::logging::enable_stderr(Level::Info);
-
-            let my_path = std::env::current_exe()
-                .expect_log("failed to obtain executable path");
-            let my_path_os = my_path.as_os_str();
-            let mut my_path_quoted_os = if my_path_os.to_string_lossy().contains(' ') {
-                let mut pqos = OsString::with_capacity(my_path_os.len() + 2);
-                pqos.push("\"");
-                pqos.push(my_path_os);
-                pqos.push("\"");
-                pqos
-            } else {
-                my_path_os.to_os_string()
-            };
-            my_path_quoted_os.push(" service ");
-            my_path_quoted_os.push(&arguments.service_name);
-
-            // open connection to SCM
-            let scm_perms =
-                ServiceControlManagerPermissions::CONNECT
-                | ServiceControlManagerPermissions::CREATE_SERVICE
-            ;
-            let scm_conn = ServiceControlManagerHandle::open_local_active(scm_perms)
-                .expect_log("failed to connect to service control manager");
-
-            // create service
-            scm_conn.create_service(
-                &arguments.service_name,
-                None,
-                ServicePermissions::empty(),
-                ServiceType::WIN32_OWN_PROCESS,
-                ServiceStartType::Demand,
-                ServiceErrorControl::Normal,
-                &my_path_quoted_os,
-                None,
-                Vec::new(),
-                None,
-                None,
-            )
-                .expect_log("failed to create service");
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
+
+            if let Err(e) = install(&arguments.service_names, arguments.params_key.as_deref()) {
+                e.exit();
+            }
+
+            if arguments.start_after_install {
+                if let Err(e) = start_and_wait(&arguments.service_names) {
+                    e.exit();
+                }
+            }
         },
         OperMode::Delete => {
             // delete service after stopping it if necessary
-            crate::logging::enable_stderr(Level::Info);
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
 
-            // open connection to SCM
-            let scm_conn = ServiceControlManagerHandle::open_local_active(
-                ServiceControlManagerPermissions::CONNECT,
-            )
-                .expect_log("failed to connect to service control manager");
-
-            // open service
-            let service = scm_conn.open_service(
-                &arguments.service_name,
-                ServicePermissions::QUERY_STATUS | ServicePermissions::STOP | ServicePermissions::DELETE,
-            )
-                .expect_log("failed to open service");
+            let stop_timeout = arguments.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT);
+            if let Err(e) = delete(&arguments.service_name, stop_timeout) {
+                e.exit();
+            }
+        },
+        OperMode::Reinstall => {
+            // delete and install again
+            crate::logging::enable_stderr(arguments.stderr_level, arguments.color, log_format);
 
-            // check if the service is stopped
-            let service_state = service.get_state()
-                .expect_log("failed to obtain service state");
-            if service_state != ServiceState::Stopped {
-                // stop the service
-                service.stop()
-                    .expect_log("failed to stop service");
+            let stop_timeout = arguments.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT);
+            let reinstalled = reinstall(
+                &arguments.service_names,
+                arguments.params_key.as_deref(),
+                stop_timeout,
+                arguments.discard_parameters,
+            );
+            if let Err(e) = reinstalled {
+                e.exit();
             }
 
-            // remove the service
-            service.delete()
-                .expect_log("failed to delete service");
+            if arguments.start_after_install {
+                if let Err(e) = start_and_wait(&arguments.service_names) {
+                    e.exit();
+                }
+            }
         },
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{FakeRegistry, FakeServiceControl};
+
+    fn names(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    fn run_iterations(registry: &FakeRegistry, scm: &FakeServiceControl, iterations: u64) -> StopReason {
+        let wait_stopper = WaitStopper::new();
+        run_with(
+            SharedStatus::new(),
+            None,
+            Some(iterations),
+            false,
+            None,
+            &wait_stopper,
+            None,
+            |_permissions| registry.clone(),
+            |_retry_policy| scm.clone(),
+        )
+    }
+
+    #[test]
+    fn start_order_moves_listed_services_to_the_front() {
+        let mut watched = names(&["App", "Cache", "Database", "Web"]);
+        apply_start_order(&mut watched, &names(&["Database", "Cache"]));
+        assert_eq!(watched, names(&["Database", "Cache", "App", "Web"]));
+    }
+
+    #[test]
+    fn start_order_is_case_insensitive() {
+        let mut watched = names(&["App", "Database"]);
+        apply_start_order(&mut watched, &names(&["DATABASE", "app"]));
+        assert_eq!(watched, names(&["Database", "App"]));
+    }
+
+    #[test]
+    fn start_order_keeps_unlisted_services_in_order() {
+        let mut watched = names(&["Zeta", "Alpha", "Database", "Mu", "Beta"]);
+        apply_start_order(&mut watched, &names(&["Database"]));
+        assert_eq!(watched, names(&["Database", "Zeta", "Alpha", "Mu", "Beta"]));
+
+        let mut watched = names(&["Zeta", "Alpha", "Mu"]);
+        apply_start_order(&mut watched, &[]);
+        assert_eq!(watched, names(&["Zeta", "Alpha", "Mu"]));
+    }
+
+    #[test]
+    fn start_order_ignores_services_that_are_not_watched() {
+        let mut watched = names(&["App", "Database"]);
+        apply_start_order(&mut watched, &names(&["Unwatched", "Database", "AlsoUnwatched"]));
+        assert_eq!(watched, names(&["Database", "App"]));
+    }
+
+    #[test]
+    fn check_once_starts_stopped_service() {
+        let registry = FakeRegistry::new();
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeStopped", ServiceState::Stopped, ServiceState::Running);
+        scm.add_service("FakeRunning", ServiceState::Running, ServiceState::Running);
+        let mut state = LoopState::new(SharedStatus::new());
+
+        let summary = check_once(&registry, &scm, names(&["FakeStopped", "FakeRunning"]), &mut state).unwrap();
+        assert_eq!(summary, CheckSummary { checked: 2, restarted: 1, errored: 0 });
+        assert_eq!(scm.start_calls("FakeStopped"), 1);
+        assert_eq!(scm.start_calls("FakeRunning"), 0);
+
+        // it is running now
+        let summary = check_once(&registry, &scm, names(&["FakeStopped", "FakeRunning"]), &mut state).unwrap();
+        assert_eq!(summary, CheckSummary { checked: 2, restarted: 0, errored: 0 });
+        assert_eq!(scm.start_calls("FakeStopped"), 1);
+    }
+
+    #[test]
+    fn check_once_leaves_paused_and_stopping_services_alone() {
+        let registry = FakeRegistry::new();
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakePaused", ServiceState::Paused, ServiceState::Running);
+        scm.add_service("FakeStopping", ServiceState::StopPending, ServiceState::Running);
+        let watched = names(&["FakePaused", "FakeStopping"]);
+        let mut state = LoopState::new(SharedStatus::new());
+
+        let summary = check_once(&registry, &scm, watched.clone(), &mut state).unwrap();
+        assert_eq!(summary, CheckSummary { checked: 2, restarted: 0, errored: 0 });
+        assert_eq!(scm.start_calls("FakePaused"), 0);
+        assert_eq!(scm.start_calls("FakeStopping"), 0);
+
+        // once it has finished stopping, it is started again; the paused one stays paused
+        scm.set_state("FakeStopping", ServiceState::Stopped);
+        let summary = check_once(&registry, &scm, watched, &mut state).unwrap();
+        assert_eq!(summary, CheckSummary { checked: 2, restarted: 1, errored: 0 });
+        assert_eq!(scm.start_calls("FakePaused"), 0);
+        assert_eq!(scm.start_calls("FakeStopping"), 1);
+    }
+
+    #[test]
+    fn check_once_backs_off_while_services_are_starting() {
+        let registry = FakeRegistry::new()
+            .with_value("MaxConcurrentStarts", RegistryValue::Dword(1));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeSlowFirst", ServiceState::Stopped, ServiceState::StartPending);
+        scm.add_service("FakeSlowSecond", ServiceState::Stopped, ServiceState::StartPending);
+        let watched = names(&["FakeSlowFirst", "FakeSlowSecond"]);
+        let mut state = LoopState::new(SharedStatus::new());
+
+        // only one may be starting up at a time
+        let summary = check_once(&registry, &scm, watched.clone(), &mut state).unwrap();
+        assert_eq!(summary.restarted, 1);
+        assert_eq!(scm.start_calls("FakeSlowFirst"), 1);
+        assert_eq!(scm.start_calls("FakeSlowSecond"), 0);
+
+        // the first one is still coming up; neither is started (again)
+        let summary = check_once(&registry, &scm, watched.clone(), &mut state).unwrap();
+        assert_eq!(summary.restarted, 0);
+        assert_eq!(scm.start_calls("FakeSlowFirst"), 1);
+        assert_eq!(scm.start_calls("FakeSlowSecond"), 0);
+
+        // once it is up, it is the second one's turn
+        scm.set_state("FakeSlowFirst", ServiceState::Running);
+        let summary = check_once(&registry, &scm, watched, &mut state).unwrap();
+        assert_eq!(summary.restarted, 1);
+        assert_eq!(scm.start_calls("FakeSlowFirst"), 1);
+        assert_eq!(scm.start_calls("FakeSlowSecond"), 1);
+    }
+
+    #[test]
+    fn check_once_gives_up_after_max_restart_attempts() {
+        let registry = FakeRegistry::new()
+            .with_value("MaxRestartAttempts", RegistryValue::Dword(2));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeCrashing", ServiceState::Stopped, ServiceState::Stopped);
+        let mut state = LoopState::new(SharedStatus::new());
+
+        for _ in 0..4 {
+            check_once(&registry, &scm, names(&["FakeCrashing"]), &mut state).unwrap();
+        }
+        assert_eq!(scm.start_calls("FakeCrashing"), 2);
+        let tracking = state.restart_tracking.get(OsStr::new("FakeCrashing"));
+        assert_eq!(tracking.attempts, 2);
+        assert!(tracking.gave_up);
+
+        // once it has been seen running, it gets a fresh set of attempts
+        scm.set_state("FakeCrashing", ServiceState::Running);
+        check_once(&registry, &scm, names(&["FakeCrashing"]), &mut state).unwrap();
+        scm.set_state("FakeCrashing", ServiceState::Stopped);
+        check_once(&registry, &scm, names(&["FakeCrashing"]), &mut state).unwrap();
+        assert_eq!(scm.start_calls("FakeCrashing"), 3);
+    }
+
+    #[test]
+    fn check_once_reports_maintenance_mode_as_paused() {
+        let registry = FakeRegistry::new()
+            .with_value("MaintenanceMode", RegistryValue::Dword(1));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeMaintained", ServiceState::Stopped, ServiceState::Running);
+        let status = SharedStatus::new();
+        let mut state = LoopState::new(status.clone());
+
+        let summary = check_once(&registry, &scm, names(&["FakeMaintained"]), &mut state).unwrap();
+        assert_eq!(summary.restarted, 0);
+        assert_eq!(scm.start_calls("FakeMaintained"), 0);
+        assert_eq!(status.snapshot().pause_reason, Some(PauseReason::MaintenanceMode));
+        assert!(status.snapshot().to_json().contains("\"paused\":true,\"pause_reason\":\"maintenance_mode\""));
+
+        let registry = registry.with_value("MaintenanceMode", RegistryValue::Dword(0));
+        check_once(&registry, &scm, names(&["FakeMaintained"]), &mut state).unwrap();
+        assert_eq!(scm.start_calls("FakeMaintained"), 1);
+        assert_eq!(status.snapshot().pause_reason, None);
+    }
+
+    #[test]
+    fn check_once_returns_invalid_parameters() {
+        let registry = FakeRegistry::new()
+            .with_value("MaxRestartAttempts", RegistryValue::String(OsString::from("three")));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeMisconfigured", ServiceState::Stopped, ServiceState::Running);
+        let mut state = LoopState::new(SharedStatus::new());
+
+        let res = check_once(&registry, &scm, names(&["FakeMisconfigured"]), &mut state);
+        assert!(matches!(res, Err(ParameterError::UnexpectedType(_))));
+        assert_eq!(scm.start_calls("FakeMisconfigured"), 0);
+
+        let registry = registry.with_value("RestartWindows", RegistryValue::String(OsString::from("whenever")))
+            .with_value("MaxRestartAttempts", RegistryValue::Dword(3));
+        let res = check_once(&registry, &scm, names(&["FakeMisconfigured"]), &mut state);
+        assert!(matches!(res, Err(ParameterError::Invalid { .. })));
+        assert_eq!(scm.start_calls("FakeMisconfigured"), 0);
+    }
+
+    #[test]
+    fn run_with_skips_excluded_services() {
+        let registry = FakeRegistry::new()
+            .with_value("ServicesExpectedRunning", RegistryValue::MultiString(names(&["FakeIncluded", "FakeExcluded"])))
+            .with_value("ServicesExcluded", RegistryValue::MultiString(names(&["fakeexcluded"])));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeIncluded", ServiceState::Stopped, ServiceState::Running);
+        scm.add_service("FakeExcluded", ServiceState::Stopped, ServiceState::Running);
+
+        let stop_reason = run_iterations(&registry, &scm, 1);
+        assert_eq!(stop_reason, StopReason::Iterations);
+        assert_eq!(scm.start_calls("FakeIncluded"), 1);
+        assert_eq!(scm.start_calls("FakeExcluded"), 0);
+
+        // the attempt is remembered across restarts of servicerestarter
+        assert_eq!(
+            registry.value_in("State\\FakeIncluded", "RestartAttempts"),
+            Some(RegistryValue::Qword(1)),
+        );
+        assert_eq!(registry.value_in("State\\FakeExcluded", "RestartAttempts"), None);
+    }
+
+    #[test]
+    fn run_with_stops_restarting_after_max_restart_attempts() {
+        let registry = FakeRegistry::new()
+            .with_value("ServicesExpectedRunning", RegistryValue::MultiString(names(&["FakeDead"])))
+            .with_value("MaxRestartAttempts", RegistryValue::Dword(1))
+            .with_value("SleepDurationMilliseconds", RegistryValue::Dword(1))
+            .with_value("MinimumSleepDurationMilliseconds", RegistryValue::Dword(1));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeDead", ServiceState::Stopped, ServiceState::Stopped);
+
+        let stop_reason = run_iterations(&registry, &scm, 3);
+        assert_eq!(stop_reason, StopReason::Iterations);
+        assert_eq!(scm.start_calls("FakeDead"), 1);
+        assert_eq!(
+            registry.value_in("State\\FakeDead", "RestartAttempts"),
+            Some(RegistryValue::Qword(1)),
+        );
+    }
+
+    #[test]
+    fn run_with_survives_invalid_loop_parameters() {
+        let registry = FakeRegistry::new()
+            .with_value("ServicesExpectedRunning", RegistryValue::MultiString(names(&["FakeStopped"])))
+            .with_value("StatsFlushIntervalMilliseconds", RegistryValue::String(OsString::from("often")));
+        let scm = FakeServiceControl::new();
+        scm.add_service("FakeStopped", ServiceState::Stopped, ServiceState::Running);
+
+        // the pass is skipped instead of taking down the service
+        assert_eq!(run_iterations(&registry, &scm, 1), StopReason::Iterations);
+        assert_eq!(scm.start_calls("FakeStopped"), 0);
+
+        // fixing the value takes effect at the next pass
+        let registry = registry.with_value("StatsFlushIntervalMilliseconds", RegistryValue::Dword(1000));
+        assert_eq!(run_iterations(&registry, &scm, 1), StopReason::Iterations);
+        assert_eq!(scm.start_calls("FakeStopped"), 1);
+    }
+
+    #[test]
+    fn run_with_survives_missing_watched_services() {
+        let registry = FakeRegistry::new();
+        let scm = FakeServiceControl::new();
+        assert_eq!(run_iterations(&registry, &scm, 1), StopReason::Iterations);
+    }
+
+    #[test]
+    fn params_key_requires_a_single_service() {
+        let key = OsStr::new("SOFTWARE\\servicerestarter-test");
+        assert!(check_params_key(&names(&["One"]), Some(key)).is_ok());
+        assert!(check_params_key(&names(&["One", "Two"]), None).is_ok());
+        assert!(check_params_key(&names(&["One", "Two"]), Some(key)).is_err());
+    }
+
+    #[test]
+    fn params_key_is_read_from_command_line() {
+        assert_eq!(
+            params_key_from_command_line(r#""C:\Program Files\servicerestarter.exe" service Watched --params-key "SOFTWARE\My Key""#),
+            Some(String::from("SOFTWARE\\My Key")),
+        );
+        assert_eq!(
+            params_key_from_command_line(r#""C:\Program Files\servicerestarter.exe" --params-key SOFTWARE\Key service Watched"#),
+            Some(String::from("SOFTWARE\\Key")),
+        );
+        assert_eq!(
+            params_key_from_command_line(r#"C:\servicerestarter.exe service Watched "--params-key=SOFTWARE\My Key\\""#),
+            Some(String::from("SOFTWARE\\My Key\\")),
+        );
+    }
+
+    #[test]
+    fn params_key_written_by_install_is_read_back() {
+        let key = OsStr::new("SOFTWARE\\my dir\\");
+        let mut command_line = OsString::from("\"C:\\Program Files\\servicerestarter.exe\" --params-key ");
+        command_line.push(quote_argument(key));
+        command_line.push(" service Watched");
+        assert_eq!(
+            params_key_from_command_line(&command_line.to_string_lossy()),
+            Some(String::from("SOFTWARE\\my dir\\")),
+        );
+    }
+
+    #[test]
+    fn params_key_missing_from_command_line() {
+        assert_eq!(params_key_from_command_line(r#""C:\Program Files\servicerestarter.exe" service Watched"#), None);
+        assert_eq!(params_key_from_command_line(r#"C:\servicerestarter.exe service Watched --params-key"#), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt::Write;
+use std::io;
+
+use crate::http_server::{HttpResponse, HttpServer};
+use crate::service_control::ServiceState;
+use crate::status::{LoopStatus, SharedStatus};
+
+
+const ALL_STATES: [(ServiceState, &str); 7] = [
+    (ServiceState::Stopped, "stopped"),
+    (ServiceState::StartPending, "start_pending"),
+    (ServiceState::StopPending, "stop_pending"),
+    (ServiceState::Running, "running"),
+    (ServiceState::ContinuePending, "continue_pending"),
+    (ServiceState::PausePending, "pause_pending"),
+    (ServiceState::Paused, "paused"),
+];
+
+
+/// Starts an HTTP server answering `GET /metrics` with the loop status in the Prometheus text
+/// exposition format.
+pub(crate) fn spawn_metrics_server(port: u16, status: SharedStatus) -> Result<HttpServer, io::Error> {
+    HttpServer::spawn("metrics endpoint", port, move |method, path| {
+        if path != "/metrics" {
+            return HttpResponse::not_found();
+        }
+        if method != "GET" && method != "HEAD" {
+            return HttpResponse::method_not_allowed();
+        }
+
+        HttpResponse {
+            status: 200,
+            reason: "OK",
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: status.inspect(format_metrics),
+        }
+    })
+}
+
+fn format_metrics(status: &LoopStatus) -> String {
+    // roughly: the fixed text plus a line per service and state
+    let mut ret = String::with_capacity(512 + status.services.len() * 96 * (ALL_STATES.len() + 1));
+
+    ret.push_str("# HELP servicerestarter_loop_iterations_total Number of completed passes over the watched services.\n");
+    ret.push_str("# TYPE servicerestarter_loop_iterations_total counter\n");
+    writeln!(ret, "servicerestarter_loop_iterations_total {}", status.completed_passes).unwrap();
+
+    ret.push_str("# HELP servicerestarter_restarts_total Number of times a watched service has been started successfully.\n");
+    ret.push_str("# TYPE servicerestarter_restarts_total counter\n");
+    for (name, service_status) in &status.services {
+        ret.push_str("servicerestarter_restarts_total{service=\"");
+        push_label_value(&mut ret, &name.to_string_lossy());
+        writeln!(ret, "\"}} {}", service_status.restart_count).unwrap();
+    }
+
+    ret.push_str("# HELP servicerestarter_service_state Whether a watched service was in the given state when last checked.\n");
+    ret.push_str("# TYPE servicerestarter_service_state gauge\n");
+    for (name, service_status) in &status.services {
+        let lossy_name = name.to_string_lossy();
+        for (state, state_label) in ALL_STATES {
+            let value = if service_status.last_state == Some(state) { 1 } else { 0 };
+            ret.push_str("servicerestarter_service_state{service=\"");
+            push_label_value(&mut ret, &lossy_name);
+            writeln!(ret, "\",state=\"{}\"}} {}", state_label, value).unwrap();
+        }
+    }
+
+    ret
+}
+
+fn push_label_value(buf: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => buf.push_str("\\\\"),
+            '"' => buf.push_str("\\\""),
+            '\n' => buf.push_str("\\n"),
+            c => buf.push(c),
+        }
+    }
+}
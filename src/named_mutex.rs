@@ -0,0 +1,87 @@
+use std::ffi::{OsStr, OsString};
+use std::ptr::null;
+use std::time::Duration;
+
+use windows::core::Error;
+use windows::Win32::Foundation::{CloseHandle, ERROR_ALREADY_EXISTS, GetLastError, HANDLE, WAIT_TIMEOUT};
+use windows::Win32::System::Threading::{
+    CreateMutexW, ReleaseMutex, WAIT_ABANDONED, WAIT_OBJECT_0, WaitForSingleObject,
+};
+
+use crate::windows_utils::WideString;
+
+
+/// The name of a mutex shared by all servicerestarter instances on this machine for the given
+/// purpose and service, e.g. `Global\servicerestarter-restart-spooler`.
+///
+/// Service names are case-insensitive, so the name is lowercased.
+pub(crate) fn service_mutex_name(purpose: &str, service_name: &OsStr) -> OsString {
+    let mut name = OsString::from("Global\\servicerestarter-");
+    name.push(purpose);
+    name.push("-");
+    name.push(service_name.to_string_lossy().to_lowercase());
+    name
+}
+
+
+/// An owned handle to a named mutex, closed when dropped.
+#[derive(Debug)]
+pub(crate) struct NamedMutex(HANDLE);
+impl NamedMutex {
+    /// Opens the mutex with the given name, creating it if necessary. Also returns whether it
+    /// already existed, i.e. whether another process has it open.
+    pub fn open_or_create(name: &OsStr) -> Result<(Self, bool), Error> {
+        let name_ws = WideString::from(name);
+        let handle = unsafe {
+            CreateMutexW(null(), false, name_ws.as_pcwstr())
+        }?;
+        let already_existed = unsafe { GetLastError() } == ERROR_ALREADY_EXISTS;
+        Ok((Self(handle), already_existed))
+    }
+
+    /// Waits up to `timeout` to acquire the mutex. Returns `None` if it is still held by someone
+    /// else at that point.
+    ///
+    /// A mutex whose previous owner exited without releasing it is acquired normally.
+    pub fn try_lock(&self, timeout: Duration) -> Result<Option<NamedMutexGuard<'_>>, Error> {
+        // INFINITE is u32::MAX; stay just below it
+        let timeout_ms = u32::try_from(timeout.as_millis())
+            .unwrap_or(u32::MAX - 1)
+            .min(u32::MAX - 1);
+        let wait_result = unsafe {
+            WaitForSingleObject(self.0, timeout_ms)
+        };
+        if wait_result == WAIT_OBJECT_0 || wait_result == WAIT_ABANDONED {
+            Ok(Some(NamedMutexGuard(self)))
+        } else if wait_result == WAIT_TIMEOUT.0 {
+            Ok(None)
+        } else {
+            Err(Error::from_win32())
+        }
+    }
+}
+impl Drop for NamedMutex {
+    fn drop(&mut self) {
+        let success = unsafe {
+            CloseHandle(self.0)
+        }.as_bool();
+        if !success {
+            eprintln!("failed to close mutex handle: {}", Error::from_win32());
+        }
+    }
+}
+
+
+/// Ownership of a [`NamedMutex`], released when dropped.
+#[derive(Debug)]
+pub(crate) struct NamedMutexGuard<'a>(&'a NamedMutex);
+impl<'a> Drop for NamedMutexGuard<'a> {
+    fn drop(&mut self) {
+        let success = unsafe {
+            ReleaseMutex((self.0).0)
+        }.as_bool();
+        if !success {
+            eprintln!("failed to release mutex: {}", Error::from_win32());
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use chrono::Local;
+use log::warn;
+
+use crate::http::{self, HttpError, HttpUrl};
+use crate::json;
+use crate::service_control::ServiceState;
+
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Information about an attempt to restart a service.
+#[derive(Clone, Debug)]
+pub(crate) struct RestartEvent {
+    pub service_name: OsString,
+    pub old_state: ServiceState,
+    pub success: bool,
+
+    /// The service is only observed, so no attempt was made to start it.
+    pub observe_only: bool,
+}
+impl RestartEvent {
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\"service\":");
+        json::push_string(&mut json, &self.service_name.to_string_lossy());
+        json.push_str(",\"old_state\":");
+        json::push_string(&mut json, &format!("{:?}", self.old_state));
+        json.push_str(",\"timestamp\":");
+        json::push_string(&mut json, &Local::now().to_rfc3339());
+        json.push_str(",\"success\":");
+        json.push_str(if self.success { "true" } else { "false" });
+        json.push_str(",\"observe_only\":");
+        json.push_str(if self.observe_only { "true" } else { "false" });
+        json.push('}');
+        json
+    }
+}
+
+
+/// Sends the event to the given webhook URL on a background thread.
+///
+/// Delivery is best-effort: failures are logged as warnings and otherwise ignored.
+pub(crate) fn notify_webhook(url: &str, event: &RestartEvent) {
+    let parsed_url = match HttpUrl::parse(url) {
+        Ok(u) => u,
+        Err(e) => {
+            warn!("not sending restart webhook: {}", e);
+            return;
+        },
+    };
+    let body = event.to_json();
+
+    let spawn_res = std::thread::Builder::new()
+        .name("webhook".to_owned())
+        .spawn(move || {
+            let res = http::request(
+                "POST",
+                &parsed_url,
+                Some("application/json"),
+                body.as_bytes(),
+                WEBHOOK_TIMEOUT,
+            );
+            match res {
+                Ok(code) if (200..300).contains(&code) => {},
+                Ok(code) => warn!("restart webhook returned status code {}", code),
+                Err(e) => warn!("failed to deliver restart webhook: {}", e),
+            }
+        });
+    if let Err(e) = spawn_res {
+        warn!("failed to spawn restart webhook thread: {}", e);
+    }
+}
+
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SMTP_DEFAULT_PORT: u16 = 25;
+
+
+/// Where and how to send e-mail notifications.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct SmtpSettings {
+    /// The SMTP server as `host` or `host:port`; IPv6 addresses may be enclosed in brackets.
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+
+/// Sends a plaintext e-mail through the given SMTP server on a background thread.
+///
+/// Delivery is best-effort: failures are logged as warnings and otherwise ignored.
+pub(crate) fn notify_mail(settings: &SmtpSettings, subject: &str, body: &str) {
+    let settings = settings.clone();
+    let subject = subject.to_owned();
+    let body = body.to_owned();
+
+    let spawn_res = std::thread::Builder::new()
+        .name("smtp".to_owned())
+        .spawn(move || {
+            if let Err(e) = send_mail(&settings, &subject, &body) {
+                warn!("failed to send notification e-mail via {:?}: {}", settings.server, e);
+            }
+        });
+    if let Err(e) = spawn_res {
+        warn!("failed to spawn notification e-mail thread: {}", e);
+    }
+}
+
+/// Splits an SMTP server given as `host`, `host:port`, `[address]` or `[address]:port` into host
+/// and port. An IPv6 address without brackets is taken as a host on the default port.
+fn split_server(server: &str) -> Result<(&str, u16), SmtpError> {
+    let invalid = || SmtpError::InvalidServer(server.to_owned());
+    let (host, port_str) = match server.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after_host) = bracketed.split_once(']')
+                .ok_or_else(invalid)?;
+            if after_host.is_empty() {
+                (host, None)
+            } else {
+                (host, Some(after_host.strip_prefix(':').ok_or_else(invalid)?))
+            }
+        },
+        None => match server.rsplit_once(':') {
+            Some((host, port_str)) if !host.contains(':') => (host, Some(port_str)),
+            _ => (server, None),
+        },
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = match port_str {
+        Some(ps) => ps.parse().map_err(|_| invalid())?,
+        None => SMTP_DEFAULT_PORT,
+    };
+    Ok((host, port))
+}
+
+fn send_mail(settings: &SmtpSettings, subject: &str, body: &str) -> Result<(), SmtpError> {
+    let (host, port) = split_server(&settings.server)?;
+    let address = http::resolve(host, port, SMTP_TIMEOUT)
+        .map_err(SmtpError::Resolve)?;
+
+    let stream = TcpStream::connect_timeout(&address, SMTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+    let mut conn = SmtpConnection {
+        reader: BufReader::new(stream.try_clone()?),
+        writer: stream,
+    };
+
+    let hostname = std::env::var("COMPUTERNAME")
+        .unwrap_or_else(|_| "localhost".to_owned());
+
+    conn.expect_reply(&[220])?;
+    conn.command(&format!("EHLO {}", hostname), &[250])?;
+    conn.command(&format!("MAIL FROM:<{}>", settings.from), &[250])?;
+    for to in &settings.to {
+        // 251: the server will forward it
+        conn.command(&format!("RCPT TO:<{}>", to), &[250, 251])?;
+    }
+    conn.command("DATA", &[354])?;
+
+    let mut message = String::new();
+    message.push_str(&format!("From: <{}>\r\n", settings.from));
+    let to_list: Vec<String> = settings.to.iter()
+        .map(|t| format!("<{}>", t))
+        .collect();
+    message.push_str(&format!("To: {}\r\n", to_list.join(", ")));
+    message.push_str(&format!("Subject: {}\r\n", subject));
+    message.push_str(&format!("Date: {}\r\n", Local::now().to_rfc2822()));
+    message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    message.push_str("\r\n");
+    for line in body.lines() {
+        // dot-stuffing
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+    conn.command(&message, &[250])?;
+
+    conn.command("QUIT", &[221])?;
+    Ok(())
+}
+
+
+struct SmtpConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+impl SmtpConnection {
+    fn command(&mut self, command: &str, expected_codes: &[u16]) -> Result<(), SmtpError> {
+        self.writer.write_all(command.as_bytes())?;
+        self.writer.write_all(b"\r\n")?;
+        self.writer.flush()?;
+        self.expect_reply(expected_codes)
+    }
+
+    fn expect_reply(&mut self, expected_codes: &[u16]) -> Result<(), SmtpError> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(SmtpError::UnexpectedReply("(connection closed)".to_owned()));
+            }
+            let line = line.trim_end();
+
+            // multi-line replies have a dash after the code on all lines but the last
+            let code: u16 = match line.get(0..3).and_then(|c| c.parse().ok()) {
+                Some(c) => c,
+                None => return Err(SmtpError::UnexpectedReply(line.to_owned())),
+            };
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+
+            if expected_codes.contains(&code) {
+                return Ok(());
+            } else {
+                return Err(SmtpError::UnexpectedReply(line.to_owned()));
+            }
+        }
+    }
+}
+
+
+#[derive(Debug)]
+enum SmtpError {
+    InvalidServer(String),
+    Resolve(HttpError),
+    Io(io::Error),
+    UnexpectedReply(String),
+}
+impl fmt::Display for SmtpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidServer(s) => write!(f, "invalid server {:?} (expected host or host:port)", s),
+            Self::Resolve(e) => write!(f, "failed to resolve server: {}", e),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::UnexpectedReply(r) => write!(f, "unexpected server reply {:?}", r),
+        }
+    }
+}
+impl From<io::Error> for SmtpError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_server_variants() {
+        assert_eq!(split_server("mail.example.com").unwrap(), ("mail.example.com", 25));
+        assert_eq!(split_server("mail.example.com:587").unwrap(), ("mail.example.com", 587));
+        assert_eq!(split_server("192.0.2.1:2525").unwrap(), ("192.0.2.1", 2525));
+        assert_eq!(split_server("2001:db8::25").unwrap(), ("2001:db8::25", 25));
+        assert_eq!(split_server("[2001:db8::25]").unwrap(), ("2001:db8::25", 25));
+        assert_eq!(split_server("[2001:db8::25]:587").unwrap(), ("2001:db8::25", 587));
+        assert!(split_server("mail.example.com:smtp").is_err());
+        assert!(split_server("[2001:db8::25").is_err());
+        assert!(split_server(":25").is_err());
+    }
+}
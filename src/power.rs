@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use windows::Win32::UI::WindowsAndMessaging::{
+    PBT_APMRESUMEAUTOMATIC, PBT_APMRESUMESUSPEND, PBT_APMSUSPEND, WTS_CONSOLE_CONNECT,
+    WTS_CONSOLE_DISCONNECT, WTS_REMOTE_CONNECT, WTS_REMOTE_DISCONNECT, WTS_SESSION_LOGOFF,
+    WTS_SESSION_LOGON,
+};
+
+use crate::extensions::ExpectExtension;
+
+
+/// Keeps track of suspend/resume and session changes, while which services are expected to come
+/// and go.
+#[derive(Debug, Default)]
+pub(crate) struct PowerState {
+    suspended: AtomicBool,
+    last_transition: Mutex<Option<Instant>>,
+}
+impl PowerState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes note of a power event (`SERVICE_CONTROL_POWEREVENT`) of the given type.
+    pub fn power_event(&self, event_type: u32) {
+        match event_type {
+            PBT_APMSUSPEND => {
+                self.suspended.store(true, Ordering::SeqCst);
+                self.mark_transition();
+            },
+            PBT_APMRESUMEAUTOMATIC|PBT_APMRESUMESUSPEND => {
+                self.suspended.store(false, Ordering::SeqCst);
+                self.mark_transition();
+            },
+            _ => {},
+        }
+    }
+
+    /// Takes note of a session change (`SERVICE_CONTROL_SESSIONCHANGE`) of the given type.
+    pub fn session_change(&self, event_type: u32) {
+        match event_type {
+            WTS_CONSOLE_CONNECT|WTS_CONSOLE_DISCONNECT|WTS_REMOTE_CONNECT|WTS_REMOTE_DISCONNECT
+                    |WTS_SESSION_LOGON|WTS_SESSION_LOGOFF => {
+                self.mark_transition();
+            },
+            _ => {},
+        }
+    }
+
+    fn mark_transition(&self) {
+        let mut last_transition = self.last_transition
+            .lock().expect_log("power state lock poisoned");
+        *last_transition = Some(Instant::now());
+    }
+
+    /// Whether the system is suspending or has resumed or switched sessions less than
+    /// `settle_duration` ago.
+    pub fn is_settling(&self, settle_duration: Duration) -> bool {
+        if self.suspended.load(Ordering::SeqCst) {
+            return true;
+        }
+        let last_transition = self.last_transition
+            .lock().expect_log("power state lock poisoned");
+        match *last_transition {
+            Some(lt) => lt.elapsed() < settle_duration,
+            None => false,
+        }
+    }
+}
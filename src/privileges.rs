@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use windows::core::{Error, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, ERROR_NOT_ALL_ASSIGNED, GetLastError, HANDLE, LUID};
+use windows::Win32::Security::{
+    AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED,
+    TOKEN_ACCESS_MASK, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+};
+use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+use crate::extensions::ExpectExtension;
+use crate::windows_utils::WideString;
+
+
+/// The privileges that [`ensure_privilege`] has already tried to enable, and whether it succeeded.
+static ENSURED_PRIVILEGES: Lazy<Mutex<HashMap<&'static str, bool>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// An owned handle to an access token, closed when dropped.
+struct TokenHandle(HANDLE);
+impl TokenHandle {
+    fn open_own(desired_access: TOKEN_ACCESS_MASK) -> Result<Self, Error> {
+        let mut handle = HANDLE::default();
+        let opened = unsafe {
+            OpenProcessToken(GetCurrentProcess(), desired_access, &mut handle)
+        }.as_bool();
+        if !opened {
+            return Err(Error::from_win32());
+        }
+        Ok(Self(handle))
+    }
+}
+impl Drop for TokenHandle {
+    fn drop(&mut self) {
+        let handle_closed = unsafe { CloseHandle(self.0) }.as_bool();
+        if !handle_closed {
+            eprintln!("failed to close token handle: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+
+/// Enables the privilege with the given name (e.g. `SeDebugPrivilege`) in the access token of this
+/// process.
+///
+/// Returns `Ok(false)` if the account this process runs as does not hold the privilege, in which
+/// case it cannot be enabled.
+pub(crate) fn enable_privilege(name: &str) -> Result<bool, Error> {
+    let name_ws = WideString::from(OsStr::new(name));
+    let mut luid = LUID::default();
+    let looked_up = unsafe {
+        LookupPrivilegeValueW(PCWSTR::default(), name_ws.as_pcwstr(), &mut luid)
+    }.as_bool();
+    if !looked_up {
+        return Err(Error::from_win32());
+    }
+
+    let token = TokenHandle::open_own(TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY)?;
+    let new_state = TOKEN_PRIVILEGES {
+        PrivilegeCount: 1,
+        Privileges: [LUID_AND_ATTRIBUTES {
+            Luid: luid,
+            Attributes: SE_PRIVILEGE_ENABLED,
+        }],
+    };
+    let adjusted = unsafe {
+        AdjustTokenPrivileges(token.0, false, &new_state, 0, null_mut(), null_mut())
+    }.as_bool();
+    if !adjusted {
+        return Err(Error::from_win32());
+    }
+
+    // the call also succeeds if the privilege is not held, but says so in the last error
+    let not_held = unsafe { GetLastError() } == ERROR_NOT_ALL_ASSIGNED;
+    Ok(!not_held)
+}
+
+/// Makes sure the privilege with the given name is enabled, logging a warning that mentions
+/// `purpose` if it cannot be. Each privilege is only enabled (and warned about) once per process.
+///
+/// Returns whether the privilege is enabled.
+pub(crate) fn ensure_privilege(name: &'static str, purpose: &str) -> bool {
+    let mut ensured = ENSURED_PRIVILEGES
+        .lock().expect_log("ENSURED_PRIVILEGES poisoned");
+    if let Some(enabled) = ensured.get(name) {
+        return *enabled;
+    }
+
+    let enabled = match enable_privilege(name) {
+        Ok(true) => {
+            debug!("enabled {}", name);
+            true
+        },
+        Ok(false) => {
+            warn!("the account servicerestarter runs as does not hold {}, which may be needed to {}", name, purpose);
+            false
+        },
+        Err(e) => {
+            warn!("failed to enable {}, which may be needed to {}: {}", name, purpose, e);
+            false
+        },
+    };
+    ensured.insert(name, enabled);
+    enabled
+}
@@ -0,0 +1,181 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use log::{error, warn};
+
+use crate::http::{self, HttpError, HttpUrl};
+use crate::process::{CommandOutcome, run_command};
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ProbeParseError {
+    UnknownKind(String),
+    Empty(String),
+    InvalidAddress(String),
+    InvalidUrl(String),
+    InvalidStatus(String),
+}
+impl fmt::Display for ProbeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKind(p) => write!(f, "probe {:?} does not start with a known kind (cmd:, tcp: or http:)", p),
+            Self::Empty(k) => write!(f, "{} probe is missing its target", k),
+            Self::InvalidAddress(a) => write!(f, "invalid address {:?} (expected host:port)", a),
+            Self::InvalidUrl(u) => write!(f, "unsupported URL {:?} (only plain http:// is supported)", u),
+            Self::InvalidStatus(s) => write!(f, "invalid expected status code {:?}", s),
+        }
+    }
+}
+impl std::error::Error for ProbeParseError {
+}
+
+
+/// What a probe found out about a service.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ProbeOutcome {
+    Healthy,
+    Unhealthy,
+
+    /// The probe itself could not be performed; this says nothing about the service.
+    Failed,
+}
+
+
+/// A liveness check of a service that the service control manager considers running.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Probe {
+    /// Runs a command line; the service is healthy if it exits with code 0.
+    Command(OsString),
+
+    /// Connects to a TCP port; the service is healthy if the connection is accepted.
+    Tcp { host: String, port: u16 },
+
+    /// Performs an HTTP GET request; the service is healthy if the response has the expected status
+    /// code or, if none is given, any 2xx status code. Redirects are not followed.
+    Http { url: HttpUrl, expected_status: Option<u16> },
+}
+impl Probe {
+    /// Parses a probe specification such as `cmd:C:\check.exe --quick`, `tcp:127.0.0.1:8080` or
+    /// `http:http://127.0.0.1:8080/health 204`.
+    pub fn parse(spec: &str) -> Result<Self, ProbeParseError> {
+        if let Some(command) = spec.strip_prefix("cmd:") {
+            if command.trim().is_empty() {
+                return Err(ProbeParseError::Empty("cmd".to_owned()));
+            }
+            return Ok(Self::Command(OsString::from(command)));
+        }
+        if let Some(address) = spec.strip_prefix("tcp:") {
+            if address.trim().is_empty() {
+                return Err(ProbeParseError::Empty("tcp".to_owned()));
+            }
+            let (host, port_str) = address.rsplit_once(':')
+                .ok_or_else(|| ProbeParseError::InvalidAddress(address.to_owned()))?;
+            let port: u16 = port_str.parse()
+                .map_err(|_| ProbeParseError::InvalidAddress(address.to_owned()))?;
+            // allow bracketed IPv6 literals such as [::1]:8080
+            let host = host.strip_prefix('[')
+                .and_then(|h| h.strip_suffix(']'))
+                .unwrap_or(host);
+            if host.is_empty() {
+                return Err(ProbeParseError::InvalidAddress(address.to_owned()));
+            }
+            return Ok(Self::Tcp { host: host.to_owned(), port });
+        }
+        if let Some(rest) = spec.strip_prefix("http:") {
+            let mut pieces = rest.split_whitespace();
+            let url_str = pieces.next()
+                .ok_or_else(|| ProbeParseError::Empty("http".to_owned()))?;
+            let url = HttpUrl::parse(url_str)
+                .map_err(|_| ProbeParseError::InvalidUrl(url_str.to_owned()))?;
+            let expected_status = match pieces.next() {
+                Some(status_str) => {
+                    let status: u16 = status_str.parse()
+                        .map_err(|_| ProbeParseError::InvalidStatus(status_str.to_owned()))?;
+                    Some(status)
+                },
+                None => None,
+            };
+            if let Some(extra) = pieces.next() {
+                return Err(ProbeParseError::InvalidStatus(extra.to_owned()));
+            }
+            return Ok(Self::Http { url, expected_status });
+        }
+        Err(ProbeParseError::UnknownKind(spec.to_owned()))
+    }
+
+    /// Checks the liveness of the given service.
+    ///
+    /// Commands may run for up to `command_timeout`; network operations time out after
+    /// `network_timeout`.
+    pub fn check(&self, service_name: &OsStr, command_timeout: Duration, network_timeout: Duration) -> ProbeOutcome {
+        match self {
+            Self::Command(command_line) => match run_command(command_line, command_timeout) {
+                Ok(CommandOutcome::Exited(0)) => ProbeOutcome::Healthy,
+                Ok(CommandOutcome::Exited(code)) => {
+                    warn!("probe of service {:?} exited with code {}", service_name, code);
+                    ProbeOutcome::Unhealthy
+                },
+                Ok(CommandOutcome::TimedOut) => {
+                    warn!("probe of service {:?} did not finish within {} ms", service_name, command_timeout.as_millis());
+                    ProbeOutcome::Unhealthy
+                },
+                Err(e) => {
+                    error!("failed to run probe {:?} of service {:?}: {}", command_line, service_name, e);
+                    ProbeOutcome::Failed
+                },
+            },
+            Self::Tcp { host, port } => {
+                let address = match (host.as_str(), *port).to_socket_addrs() {
+                    Ok(mut addrs) => match addrs.next() {
+                        Some(a) => a,
+                        None => {
+                            error!("probe host {:?} of service {:?} did not resolve to any address", host, service_name);
+                            return ProbeOutcome::Failed;
+                        },
+                    },
+                    Err(e) => {
+                        error!("failed to resolve probe host {:?} of service {:?}: {}", host, service_name, e);
+                        return ProbeOutcome::Failed;
+                    },
+                };
+                match TcpStream::connect_timeout(&address, network_timeout) {
+                    Ok(_) => ProbeOutcome::Healthy,
+                    Err(e) => {
+                        warn!("probe of service {:?} failed to connect to {}: {}", service_name, address, e);
+                        ProbeOutcome::Unhealthy
+                    },
+                }
+            },
+            Self::Http { url, expected_status } => {
+                match http::request("GET", url, None, &[], network_timeout) {
+                    Ok(status) => {
+                        let healthy = match expected_status {
+                            Some(es) => status == *es,
+                            None => (200..300).contains(&status),
+                        };
+                        if healthy {
+                            ProbeOutcome::Healthy
+                        } else {
+                            warn!("probe of service {:?} received HTTP status {}", service_name, status);
+                            ProbeOutcome::Unhealthy
+                        }
+                    },
+                    Err(HttpError::Io(e)) => {
+                        warn!("probe of service {:?} failed: {}", service_name, e);
+                        ProbeOutcome::Unhealthy
+                    },
+                    Err(HttpError::InvalidResponse(line)) => {
+                        warn!("probe of service {:?} received an invalid response {:?}", service_name, line);
+                        ProbeOutcome::Unhealthy
+                    },
+                    Err(e) => {
+                        error!("failed to probe service {:?}: {}", service_name, e);
+                        ProbeOutcome::Failed
+                    },
+                }
+            },
+        }
+    }
+}
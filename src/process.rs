@@ -0,0 +1,289 @@
+use std::ffi::{OsStr, OsString};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::ptr::null;
+use std::time::Duration;
+
+use log::error;
+use windows::core::{Error, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_TIMEOUT};
+use windows::Win32::System::Threading::{
+    CREATE_NO_WINDOW, CreateProcessW, GetExitCodeProcess, OpenProcess, PROCESS_INFORMATION,
+    PROCESS_SYNCHRONIZE, PROCESS_TERMINATE, STARTUPINFOW, TerminateProcess, WAIT_OBJECT_0,
+    WaitForSingleObject,
+};
+
+use crate::windows_utils::WideString;
+
+
+/// How long to wait for a process to actually go away after terminating it.
+const TERMINATION_WAIT: Duration = Duration::from_secs(5);
+
+/// The exit code given to processes terminated because they took too long.
+const TIMED_OUT_EXIT_CODE: u32 = 1;
+
+
+/// How a command run by [`run_command`] ended.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum CommandOutcome {
+    /// The command exited by itself with the given exit code.
+    Exited(u32),
+
+    /// The command did not exit within the timeout and was terminated.
+    TimedOut,
+}
+
+
+/// An owned handle to a process, closed when dropped.
+#[derive(Debug)]
+pub(crate) struct ProcessHandle(HANDLE);
+impl ProcessHandle {
+    /// Opens an existing process so that it can be terminated and waited upon.
+    pub fn open_for_termination(process_id: u32) -> Result<Self, Error> {
+        let handle = unsafe {
+            OpenProcess(PROCESS_TERMINATE | PROCESS_SYNCHRONIZE, false, process_id)
+        }?;
+        Ok(Self(handle))
+    }
+
+    /// Waits up to `timeout` for the process to exit. Returns whether it did.
+    pub fn wait(&self, timeout: Duration) -> Result<bool, Error> {
+        // INFINITE is u32::MAX; stay just below it
+        let timeout_ms = u32::try_from(timeout.as_millis())
+            .unwrap_or(u32::MAX - 1)
+            .min(u32::MAX - 1);
+        let wait_result = unsafe {
+            WaitForSingleObject(self.0, timeout_ms)
+        };
+        if wait_result == WAIT_OBJECT_0 {
+            Ok(true)
+        } else if wait_result == WAIT_TIMEOUT.0 {
+            Ok(false)
+        } else {
+            // WAIT_FAILED (WAIT_ABANDONED only applies to mutexes)
+            Err(Error::from_win32())
+        }
+    }
+
+    pub fn exit_code(&self) -> Result<u32, Error> {
+        let mut exit_code: u32 = 0;
+        let success = unsafe {
+            GetExitCodeProcess(self.0, &mut exit_code)
+        }.as_bool();
+        if success {
+            Ok(exit_code)
+        } else {
+            Err(Error::from_win32())
+        }
+    }
+
+    pub fn terminate(&self, exit_code: u32) -> Result<(), Error> {
+        let success = unsafe {
+            TerminateProcess(self.0, exit_code)
+        }.as_bool();
+        if success {
+            Ok(())
+        } else {
+            Err(Error::from_win32())
+        }
+    }
+}
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        let success = unsafe {
+            CloseHandle(self.0)
+        }.as_bool();
+        if !success {
+            eprintln!("failed to close process handle: {}", Error::from_win32());
+        }
+    }
+}
+
+
+/// Quotes a single argument so that it survives the command-line parsing of the Microsoft C
+/// runtime (`CommandLineToArgvW`) unchanged.
+pub(crate) fn quote_argument(argument: &OsStr) -> OsString {
+    const QUOTE: u16 = b'"' as u16;
+    const BACKSLASH: u16 = b'\\' as u16;
+
+    let arg_wide: Vec<u16> = argument.encode_wide().collect();
+    let needs_quoting = arg_wide.is_empty()
+        || arg_wide.iter().any(|c| *c == QUOTE || *c == b' ' as u16 || *c == b'\t' as u16);
+    if !needs_quoting {
+        return argument.to_os_string();
+    }
+
+    let mut quoted: Vec<u16> = Vec::with_capacity(arg_wide.len() + 2);
+    quoted.push(QUOTE);
+    let mut backslashes: usize = 0;
+    for &c in &arg_wide {
+        if c == BACKSLASH {
+            backslashes += 1;
+            continue;
+        }
+        if c == QUOTE {
+            // double the preceding backslashes and escape the quote
+            quoted.resize(quoted.len() + backslashes * 2 + 1, BACKSLASH);
+        } else {
+            quoted.resize(quoted.len() + backslashes, BACKSLASH);
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    // backslashes before the closing quote must be doubled too
+    quoted.resize(quoted.len() + backslashes * 2, BACKSLASH);
+    quoted.push(QUOTE);
+    OsString::from_wide(&quoted)
+}
+
+
+/// Splits a command line into its arguments the way the Microsoft C runtime does; the inverse of
+/// [`quote_argument`].
+pub(crate) fn split_command_line(command_line: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut in_quotes = false;
+    let mut backslashes: usize = 0;
+    for c in command_line.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            in_argument = true;
+            continue;
+        }
+        if c == '"' {
+            // 2n backslashes become n and the quote delimits; 2n+1 become n and the quote is literal
+            current.extend(std::iter::repeat_n('\\', backslashes / 2));
+            if backslashes % 2 == 1 {
+                current.push('"');
+            } else {
+                in_quotes = !in_quotes;
+            }
+            backslashes = 0;
+            in_argument = true;
+            continue;
+        }
+        current.extend(std::iter::repeat_n('\\', backslashes));
+        backslashes = 0;
+        if (c == ' ' || c == '\t') && !in_quotes {
+            if in_argument {
+                arguments.push(std::mem::take(&mut current));
+                in_argument = false;
+            }
+        } else {
+            current.push(c);
+            in_argument = true;
+        }
+    }
+    current.extend(std::iter::repeat_n('\\', backslashes));
+    if in_argument {
+        arguments.push(current);
+    }
+    arguments
+}
+
+
+/// Starts the given command line without a console window.
+pub(crate) fn spawn_command(command_line: &OsStr) -> Result<ProcessHandle, Error> {
+    // CreateProcessW may modify the command line buffer
+    let mut command_line_ws = WideString::from(command_line);
+
+    let startup_info = STARTUPINFOW {
+        cb: std::mem::size_of::<STARTUPINFOW>() as u32,
+        ..Default::default()
+    };
+    let mut process_info = PROCESS_INFORMATION::default();
+    let success = unsafe {
+        CreateProcessW(
+            PCWSTR::default(),
+            command_line_ws.as_pwstr(),
+            null(),
+            null(),
+            false,
+            CREATE_NO_WINDOW,
+            null(),
+            PCWSTR::default(),
+            &startup_info,
+            &mut process_info,
+        )
+    }.as_bool();
+    if !success {
+        return Err(Error::from_win32());
+    }
+
+    // we don't need the thread handle
+    let thread_closed = unsafe {
+        CloseHandle(process_info.hThread)
+    }.as_bool();
+    if !thread_closed {
+        error!("failed to close thread handle of spawned process: {}", Error::from_win32());
+    }
+
+    Ok(ProcessHandle(process_info.hProcess))
+}
+
+
+/// Runs the given command line and waits for it to exit.
+///
+/// If the command is still running once `timeout` has passed, it is terminated.
+pub(crate) fn run_command(command_line: &OsStr, timeout: Duration) -> Result<CommandOutcome, Error> {
+    let process = spawn_command(command_line)?;
+    if process.wait(timeout)? {
+        return Ok(CommandOutcome::Exited(process.exit_code()?));
+    }
+
+    process.terminate(TIMED_OUT_EXIT_CODE)?;
+    if !process.wait(TERMINATION_WAIT)? {
+        error!("process {:?} has not exited even after being terminated", command_line);
+    }
+    Ok(CommandOutcome::TimedOut)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(arguments: &[&str]) {
+        let quoted: Vec<String> = arguments.iter()
+            .map(|a| quote_argument(OsStr::new(a)).into_string().unwrap())
+            .collect();
+        let command_line = quoted.join(" ");
+        assert_eq!(split_command_line(&command_line), arguments, "command line: {}", command_line);
+    }
+
+    #[test]
+    fn plain_arguments_are_not_quoted() {
+        assert_eq!(quote_argument(OsStr::new("service")), OsString::from("service"));
+        assert_eq!(quote_argument(OsStr::new("C:\\dir\\")), OsString::from("C:\\dir\\"));
+    }
+
+    #[test]
+    fn empty_argument_is_quoted() {
+        assert_eq!(quote_argument(OsStr::new("")), OsString::from("\"\""));
+        round_trip(&["a", "", "b"]);
+    }
+
+    #[test]
+    fn arguments_with_spaces_round_trip() {
+        assert_eq!(quote_argument(OsStr::new("two words")), OsString::from("\"two words\""));
+        round_trip(&["C:\\Program Files\\x.exe", "two words", "tab\there"]);
+    }
+
+    #[test]
+    fn arguments_with_quotes_round_trip() {
+        assert_eq!(quote_argument(OsStr::new("say \"hi\"")), OsString::from("\"say \\\"hi\\\"\""));
+        round_trip(&["say \"hi\"", "\"", "a\\\"b"]);
+    }
+
+    #[test]
+    fn trailing_backslashes_round_trip() {
+        assert_eq!(quote_argument(OsStr::new("C:\\my dir\\")), OsString::from("\"C:\\my dir\\\\\""));
+        round_trip(&["C:\\my dir\\", "C:\\dir\\", "a b\\\\", "\\\\server\\share name\\"]);
+    }
+
+    #[test]
+    fn whitespace_between_arguments_is_collapsed() {
+        assert_eq!(split_command_line("  a \t b  "), vec!["a", "b"]);
+        assert_eq!(split_command_line(""), Vec::<String>::new());
+    }
+}
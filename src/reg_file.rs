@@ -0,0 +1,129 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+
+use crate::registry::RegistryValue;
+
+
+/// The line length after which regedit wraps hex data.
+const HEX_LINE_LENGTH: usize = 80;
+
+
+/// Escapes a string for use between double quotes. Strings that are not valid Unicode (i.e. contain
+/// unpaired surrogates) cannot be represented exactly; their invalid parts are replaced.
+fn escape_string(value: &OsStr) -> String {
+    value.to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+/// Appends hex data to a line in the form `hex(7):6e,00,...`, wrapping lines the way regedit does.
+fn push_hex(output: &mut String, prefix: &str, bytes: &[u8]) {
+    output.push_str(prefix);
+    let mut line_length = output.len();
+    for (i, b) in bytes.iter().enumerate() {
+        write!(output, "{:02x}", b).unwrap();
+        line_length += 2;
+        if i + 1 < bytes.len() {
+            output.push(',');
+            line_length += 1;
+            if line_length >= HEX_LINE_LENGTH - 3 {
+                output.push_str("\\\r\n  ");
+                line_length = 2;
+            }
+        }
+    }
+}
+
+/// Serializes a single value as a line of a `.reg` file, e.g. `"Name"=dword:0000000a`.
+fn format_value(name: &OsStr, value: &RegistryValue) -> String {
+    let mut line = String::new();
+    if name.is_empty() {
+        line.push('@');
+    } else {
+        write!(line, "\"{}\"", escape_string(name)).unwrap();
+    }
+    line.push('=');
+
+    match value {
+        // a string that is not valid Unicode would be mangled inside quotes; fall back to hex
+        RegistryValue::String(s) if s.to_str().is_some() => write!(line, "\"{}\"", escape_string(s)).unwrap(),
+        RegistryValue::Dword(dw) => write!(line, "dword:{:08x}", dw).unwrap(),
+        RegistryValue::Binary(bs) => push_hex(&mut line, "hex:", bs),
+        other => {
+            let prefix = format!("hex({:x}):", other.to_reg_value_type().0);
+            push_hex(&mut line, &prefix, &other.to_bytes());
+        },
+    }
+    line
+}
+
+/// Serializes the given values of the given key (e.g. `HKEY_LOCAL_MACHINE\SOFTWARE\Example`) in
+/// the format of `.reg` files, which can be imported using regedit or `reg import`.
+pub(crate) fn to_reg_file(key_path: &str, values: &[(OsString, RegistryValue)]) -> String {
+    keys_to_reg_file(&[(key_path.to_owned(), values)])
+}
+
+/// Serializes the given values of each of the given keys in the format of `.reg` files. Parent
+/// keys must come before their subkeys.
+pub(crate) fn keys_to_reg_file(keys: &[(String, &[(OsString, RegistryValue)])]) -> String {
+    let mut output = String::new();
+    output.push_str("Windows Registry Editor Version 5.00\r\n");
+    for (key_path, values) in keys {
+        output.push_str("\r\n");
+        write!(output, "[{}]\r\n", key_path).unwrap();
+        for (name, value) in values.iter() {
+            output.push_str(&format_value(name, value));
+            output.push_str("\r\n");
+        }
+    }
+    output.push_str("\r\n");
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::windows::ffi::OsStringExt;
+
+    #[test]
+    fn format_valid_string_is_quoted() {
+        let value = RegistryValue::String(OsString::from("C:\\Program Files\\\"x\""));
+        assert_eq!(
+            format_value(OsStr::new("Path"), &value),
+            "\"Path\"=\"C:\\\\Program Files\\\\\\\"x\\\"\"",
+        );
+    }
+
+    #[test]
+    fn format_lone_surrogate_string_falls_back_to_hex() {
+        let value = RegistryValue::String(OsString::from_wide(&[0x0061, 0xD800, 0x0062]));
+        assert_eq!(
+            format_value(OsStr::new("Name"), &value),
+            "\"Name\"=hex(1):61,00,00,d8,62,00,00,00",
+        );
+    }
+
+    #[test]
+    fn keys_are_written_in_order() {
+        let parent_values = vec![(OsString::from("A"), RegistryValue::Dword(1))];
+        let child_values = vec![(OsString::from("B"), RegistryValue::Dword(2))];
+        let keys = vec![
+            ("HKEY_LOCAL_MACHINE\\Example".to_owned(), parent_values.as_slice()),
+            ("HKEY_LOCAL_MACHINE\\Example\\Child".to_owned(), child_values.as_slice()),
+        ];
+        assert_eq!(
+            keys_to_reg_file(&keys),
+            concat!(
+                "Windows Registry Editor Version 5.00\r\n",
+                "\r\n",
+                "[HKEY_LOCAL_MACHINE\\Example]\r\n",
+                "\"A\"=dword:00000001\r\n",
+                "\r\n",
+                "[HKEY_LOCAL_MACHINE\\Example\\Child]\r\n",
+                "\"B\"=dword:00000002\r\n",
+                "\r\n",
+            ),
+        );
+    }
+}
@@ -1,25 +1,35 @@
 use std::ffi::{OsStr, OsString};
-use std::mem::size_of;
+use std::fmt;
+use std::mem::{ManuallyDrop, size_of};
 use std::os::windows::prelude::{OsStrExt, OsStringExt};
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
+use std::time::{Duration, SystemTime};
 
 use bitflags::bitflags;
-use windows::core::Error;
-use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, NO_ERROR};
+use log::warn;
+use windows::core::{Error, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{
+    CloseHandle, ERROR_FILE_NOT_FOUND, ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS, NO_ERROR, WAIT_TIMEOUT,
+};
 use windows::Win32::Storage::FileSystem::READ_CONTROL;
 use windows::Win32::System::Environment::ExpandEnvironmentStringsW;
 use windows::Win32::System::Registry::{
     HKEY, HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS,
     KEY_CREATE_SUB_KEY, KEY_ENUMERATE_SUB_KEYS, KEY_QUERY_VALUE, KEY_NOTIFY, KEY_SET_VALUE,
-    REG_BINARY, RegCloseKey, REG_DWORD, REG_DWORD_BIG_ENDIAN, REG_EXPAND_SZ,
-    REG_FULL_RESOURCE_DESCRIPTOR, REG_LINK, REG_MULTI_SZ, REG_NONE, RegOpenKeyExW,
-    REG_RESOURCE_LIST, REG_RESOURCE_REQUIREMENTS_LIST, RegQueryValueExW, REG_QWORD, REG_SAM_FLAGS,
-    REG_SZ, REG_VALUE_TYPE,
+    KEY_WOW64_32KEY, KEY_WOW64_64KEY, REG_BINARY, RegCloseKey, RegCreateKeyExW, RegDeleteKeyExW,
+    REG_DWORD, REG_DWORD_BIG_ENDIAN,
+    RegEnumKeyExW, RegEnumValueW, REG_EXPAND_SZ, REG_FULL_RESOURCE_DESCRIPTOR, REG_LINK, REG_MULTI_SZ, REG_NONE,
+    REG_NOTIFY_CHANGE_ATTRIBUTES, REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME,
+    REG_NOTIFY_CHANGE_SECURITY, REG_NOTIFY_FILTER, RegNotifyChangeKeyValue, RegOpenKeyExW,
+    REG_OPTION_NON_VOLATILE, REG_RESOURCE_LIST, REG_RESOURCE_REQUIREMENTS_LIST, RegQueryInfoKeyW,
+    RegQueryValueExW, REG_QWORD, REG_SAM_FLAGS, RegSetValueExW, REG_SZ, REG_VALUE_TYPE,
 };
 use windows::Win32::System::SystemServices::{DELETE, WRITE_DAC, WRITE_OWNER};
+use windows::Win32::System::Threading::{CreateEventW, WAIT_OBJECT_0, WaitForSingleObject};
 
 use crate::log_panic;
 use crate::extensions::ExpectExtension;
+use crate::timestamp::{filetime_to_system_time, unix_seconds_to_system_time};
 use crate::windows_utils::{OptionalWideString, WideString};
 
 
@@ -34,6 +44,29 @@ bitflags! {
         const READ_CONTROL = READ_CONTROL.0;
         const WRITE_DAC = WRITE_DAC;
         const WRITE_OWNER = WRITE_OWNER;
+
+        /// Use the 64-bit view of the registry, even from a 32-bit build. Ignored on 32-bit
+        /// Windows.
+        const WOW64_64KEY = KEY_WOW64_64KEY.0;
+
+        /// Use the 32-bit view of the registry, even from a 64-bit build.
+        const WOW64_32KEY = KEY_WOW64_32KEY.0;
+    }
+}
+impl RegistryPermissions {
+    /// Only the flags of these permissions that choose a view of the registry.
+    fn view(self) -> Self {
+        self & (Self::WOW64_64KEY | Self::WOW64_32KEY)
+    }
+
+    /// These permissions, asking for the given view of the registry unless a view has been chosen
+    /// explicitly.
+    fn with_view_or(self, view: Self) -> Self {
+        if self.view().is_empty() {
+            self | view.view()
+        } else {
+            self
+        }
     }
 }
 impl From<RegistryPermissions> for REG_SAM_FLAGS {
@@ -42,6 +75,60 @@ impl From<RegistryPermissions> for REG_SAM_FLAGS {
     }
 }
 
+bitflags! {
+    /// The kinds of changes that [`RegistryKeyHandle::wait_for_change`] waits for.
+    pub struct RegistryChangeFilter: u32 {
+        /// A subkey has been added or deleted.
+        const NAME = REG_NOTIFY_CHANGE_NAME.0;
+
+        /// The attributes of the key have changed.
+        const ATTRIBUTES = REG_NOTIFY_CHANGE_ATTRIBUTES.0;
+
+        /// A value has been added, changed or deleted.
+        const LAST_SET = REG_NOTIFY_CHANGE_LAST_SET.0;
+
+        /// The security descriptor of the key has changed.
+        const SECURITY = REG_NOTIFY_CHANGE_SECURITY.0;
+    }
+}
+impl From<RegistryChangeFilter> for REG_NOTIFY_FILTER {
+    fn from(filter: RegistryChangeFilter) -> Self {
+        REG_NOTIFY_FILTER(filter.bits())
+    }
+}
+
+
+/// A registry value had a different type than required.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct UnexpectedValueType {
+    pub name: String,
+    pub expected: &'static str,
+    pub preview: String,
+}
+impl UnexpectedValueType {
+    pub fn new(name: &str, expected: &'static str, value: &RegistryValue) -> Self {
+        Self {
+            name: name.to_owned(),
+            expected,
+            preview: value.preview().to_string(),
+        }
+    }
+}
+impl fmt::Display for UnexpectedValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "registry value {} is {}; expected {}", self.name, self.preview, self.expected)
+    }
+}
+impl std::error::Error for UnexpectedValueType {
+}
+
+
+/// How often to try reading a value that keeps growing while it is being read.
+const MAX_READ_ATTEMPTS: usize = 5;
+
+/// How many bytes of binary data to show in a [`RegistryValuePreview`].
+const PREVIEW_BYTES: usize = 32;
+
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum RegistryValue {
@@ -57,6 +144,10 @@ pub enum RegistryValue {
     FullResourceDescriptor(Vec<u8>),
     ResourceRequirementsList(Vec<u8>),
     Qword(u64),
+
+    /// A value of a type not known to this program, kept as its type number and raw bytes so that
+    /// it can be shown, exported and written back unchanged.
+    Unknown { reg_type: u32, bytes: Vec<u8> },
 }
 impl RegistryValue {
     pub fn to_reg_value_type(&self) -> REG_VALUE_TYPE {
@@ -73,6 +164,59 @@ impl RegistryValue {
             Self::FullResourceDescriptor(_) => REG_FULL_RESOURCE_DESCRIPTOR,
             Self::ResourceRequirementsList(_) => REG_RESOURCE_REQUIREMENTS_LIST,
             Self::Qword(_) => REG_QWORD,
+            Self::Unknown { reg_type, bytes: _ } => REG_VALUE_TYPE(*reg_type),
+        }
+    }
+
+    /// The name of the type of this value as shown by the Registry Editor, e.g. `REG_SZ`. Types
+    /// not known to this program have no name; [`RegistryValue::preview`] shows their number.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::None(_) => "REG_NONE",
+            Self::String(_) => "REG_SZ",
+            Self::ExpandString { unexpanded: _, expanded: _ } => "REG_EXPAND_SZ",
+            Self::Binary(_) => "REG_BINARY",
+            Self::Dword(_) => "REG_DWORD",
+            Self::DwordBigEndian(_) => "REG_DWORD_BIG_ENDIAN",
+            Self::Link(_) => "REG_LINK",
+            Self::MultiString(_) => "REG_MULTI_SZ",
+            Self::ResourceList(_) => "REG_RESOURCE_LIST",
+            Self::FullResourceDescriptor(_) => "REG_FULL_RESOURCE_DESCRIPTOR",
+            Self::ResourceRequirementsList(_) => "REG_RESOURCE_REQUIREMENTS_LIST",
+            Self::Qword(_) => "REG_QWORD",
+            Self::Unknown { reg_type: _, bytes: _ } => "unknown type",
+        }
+    }
+
+    /// A description of this value for log messages consisting of its type name and its value,
+    /// e.g. `REG_BINARY (1024 bytes: 00 11 22 ...)`. At most the first few bytes of binary data are
+    /// shown.
+    pub fn preview(&self) -> RegistryValuePreview<'_> {
+        RegistryValuePreview(self)
+    }
+
+    /// Interprets this value as a number of seconds since the Unix epoch, as stored e.g. in
+    /// `LastRestartUnixTime`. Returns `None` unless this is a REG_DWORD or REG_QWORD.
+    pub fn to_unix_time(&self) -> Option<SystemTime> {
+        match self {
+            Self::Dword(dw) => Some(unix_seconds_to_system_time((*dw).into())),
+            Self::DwordBigEndian(dw) => Some(unix_seconds_to_system_time((*dw).into())),
+            Self::Qword(qw) => Some(unix_seconds_to_system_time(*qw)),
+            _ => None,
+        }
+    }
+
+    /// Interprets this value as a FILETIME, as Windows stores timestamps in the registry. Returns
+    /// `None` unless this is a REG_QWORD or an 8-byte REG_BINARY.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn to_filetime(&self) -> Option<SystemTime> {
+        match self {
+            Self::Qword(qw) => Some(filetime_to_system_time(*qw)),
+            Self::Binary(bs) => {
+                let bytes: [u8; 8] = bs.as_slice().try_into().ok()?;
+                Some(filetime_to_system_time(u64::from_le_bytes(bytes)))
+            },
+            _ => None,
         }
     }
 
@@ -92,8 +236,10 @@ impl RegistryValue {
                     if s_ws.contains(&0x00) {
                         log_panic!("string at index {} in a multi-string contains a NUL character", i);
                     }
-                    if s_ws.len() == 0 {
-                        log_panic!("string at index {} in a multi-string is empty", i);
+                    if s_ws.is_empty() {
+                        // an empty string would end the list prematurely
+                        warn!("skipping empty string at index {} in a multi-string", i);
+                        continue;
                     }
                     ws.extend(&s_ws);
                     ws.push(0x0000);
@@ -110,6 +256,7 @@ impl RegistryValue {
             Self::FullResourceDescriptor(bs) => bs.clone(),
             Self::ResourceRequirementsList(bs) => bs.clone(),
             Self::Qword(qw) => Vec::from(qw.to_le_bytes()),
+            Self::Unknown { reg_type: _, bytes } => bytes.clone(),
         }
     }
 
@@ -119,6 +266,10 @@ impl RegistryValue {
             REG_SZ => RegistryValue::String(bytes_to_os_string(bs)),
             REG_EXPAND_SZ => os_string_to_expand_value(bytes_to_os_string(bs)),
             REG_BINARY => RegistryValue::Binary(Vec::from(bs)),
+            // a zero-length numeric value can be stored; treat it as zero
+            REG_DWORD if bs.is_empty() => RegistryValue::Dword(0),
+            REG_DWORD_BIG_ENDIAN if bs.is_empty() => RegistryValue::DwordBigEndian(0),
+            REG_QWORD if bs.is_empty() => RegistryValue::Qword(0),
             REG_DWORD => RegistryValue::Dword(u32::from_le_bytes(bs.try_into().expect_log("DWORD value has incorrect length"))),
             REG_DWORD_BIG_ENDIAN => RegistryValue::DwordBigEndian(u32::from_be_bytes(bs.try_into().expect_log("DWORD value has incorrect length"))),
             REG_LINK => RegistryValue::Link(bytes_to_os_string(bs)),
@@ -127,7 +278,7 @@ impl RegistryValue {
             REG_FULL_RESOURCE_DESCRIPTOR => RegistryValue::FullResourceDescriptor(Vec::from(bs)),
             REG_RESOURCE_REQUIREMENTS_LIST => Self::ResourceRequirementsList(Vec::from(bs)),
             REG_QWORD => Self::Qword(u64::from_le_bytes(bs.try_into().expect_log("QWORD value has incorrect length"))),
-            _ => log_panic!("unknown registry value type 0x{:X}", reg_value_type.0),
+            other => RegistryValue::Unknown { reg_type: other.0, bytes: Vec::from(bs) },
         }
     }
 }
@@ -153,16 +304,101 @@ impl From<PredefinedKey> for HKEY {
     }
 }
 
+/// Read access to the values of an opened registry key.
+///
+/// Implemented by [`RegistryKeyHandle`]; the abstraction allows the service loop to operate on
+/// something other than the actual registry.
+pub(crate) trait RegistryAccess {
+    fn open_subkey(
+        &self,
+        subkey: Option<&OsStr>,
+        permissions: RegistryPermissions,
+    ) -> Result<Self, Error> where Self: Sized;
+
+    fn create_subkey(
+        &self,
+        subkey: &OsStr,
+        permissions: RegistryPermissions,
+    ) -> Result<Self, Error> where Self: Sized;
+
+    fn delete_subkey(&self, subkey: &OsStr) -> Result<(), Error>;
+
+    fn read_value(&self, value_name: Option<&OsStr>) -> Result<RegistryValue, Error>;
+
+    fn write_value(&self, value_name: Option<&OsStr>, value: &RegistryValue) -> Result<(), Error>;
+
+    fn read_value_optional(&self, value_name: Option<&OsStr>) -> Result<Option<RegistryValue>, Error> {
+        match self.read_value(value_name) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => {
+                if e.win32_error().map(|we| we == ERROR_FILE_NOT_FOUND).unwrap_or(false) {
+                    Ok(None)
+                } else {
+                    Err(e)
+                }
+            },
+        }
+    }
+}
+
+
+/// Displays a registry value in a bounded form; see [`RegistryValue::preview`].
+#[derive(Clone, Copy, Debug)]
+pub struct RegistryValuePreview<'a>(&'a RegistryValue);
+impl<'a> fmt::Display for RegistryValuePreview<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RegistryValue::Unknown { reg_type, bytes: _ } => write!(f, "{} 0x{:X} ", self.0.type_name(), reg_type)?,
+            _ => write!(f, "{} ", self.0.type_name())?,
+        }
+        let bytes = match self.0 {
+            RegistryValue::String(s) => return write!(f, "{:?}", s),
+            RegistryValue::ExpandString { unexpanded, expanded: _ } => return write!(f, "{:?}", unexpanded),
+            RegistryValue::Link(s) => return write!(f, "{:?}", s),
+            RegistryValue::MultiString(ss) => return write!(f, "{:?}", ss),
+            RegistryValue::Dword(dw) => return write!(f, "{}", dw),
+            RegistryValue::DwordBigEndian(dw) => return write!(f, "{}", dw),
+            RegistryValue::Qword(qw) => return write!(f, "{}", qw),
+            RegistryValue::None(bs) => bs,
+            RegistryValue::Binary(bs) => bs,
+            RegistryValue::ResourceList(bs) => bs,
+            RegistryValue::FullResourceDescriptor(bs) => bs,
+            RegistryValue::ResourceRequirementsList(bs) => bs,
+            RegistryValue::Unknown { reg_type: _, bytes } => bytes,
+        };
+
+        write!(f, "({} bytes", bytes.len())?;
+        if !bytes.is_empty() {
+            write!(f, ":")?;
+            for b in bytes.iter().take(PREVIEW_BYTES) {
+                write!(f, " {:02x}", b)?;
+            }
+            if bytes.len() > PREVIEW_BYTES {
+                write!(f, " ...")?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+
+/// An open registry key, along with the view of the registry it was opened in.
+///
+/// A key is opened in the view given by [`RegistryPermissions::WOW64_64KEY`] or
+/// [`RegistryPermissions::WOW64_32KEY`], if any; subkeys opened or created through it stay in that
+/// view unless another one is given.
 #[derive(Debug)]
-pub struct RegistryKeyHandle(HKEY);
+pub struct RegistryKeyHandle(HKEY, RegistryPermissions);
 impl RegistryKeyHandle {
     fn open_relative(
         parent: HKEY,
+        parent_view: RegistryPermissions,
         subkey: Option<&OsStr>,
         permissions: RegistryPermissions,
     ) -> Result<Self, Error> {
         let mut hkey = HKEY::default();
         let subkey_ws = OptionalWideString::from(subkey);
+        let permissions = permissions.with_view_or(parent_view);
 
         let err_code = unsafe {
             RegOpenKeyExW(
@@ -174,7 +410,37 @@ impl RegistryKeyHandle {
             )
         };
         if err_code == NO_ERROR {
-            Ok(Self(hkey))
+            Ok(Self(hkey, permissions.view()))
+        } else {
+            Err(err_code.into())
+        }
+    }
+
+    fn create_relative(
+        parent: HKEY,
+        parent_view: RegistryPermissions,
+        subkey: &OsStr,
+        permissions: RegistryPermissions,
+    ) -> Result<Self, Error> {
+        let mut hkey = HKEY::default();
+        let subkey_ws = WideString::from(subkey);
+        let permissions = permissions.with_view_or(parent_view);
+
+        let err_code = unsafe {
+            RegCreateKeyExW(
+                parent,
+                subkey_ws.as_pcwstr(),
+                0,
+                PCWSTR::default(),
+                REG_OPTION_NON_VOLATILE,
+                permissions.into(),
+                null(),
+                &mut hkey,
+                null_mut(),
+            )
+        };
+        if err_code == NO_ERROR {
+            Ok(Self(hkey, permissions.view()))
         } else {
             Err(err_code.into())
         }
@@ -186,7 +452,7 @@ impl RegistryKeyHandle {
         permissions: RegistryPermissions,
     ) -> Result<Self, Error> {
         let parent_hkey = HKEY::from(predefined);
-        Self::open_relative(parent_hkey, subkey, permissions)
+        Self::open_relative(parent_hkey, RegistryPermissions::empty(), subkey, permissions)
     }
 
     pub fn open_subkey(
@@ -194,7 +460,30 @@ impl RegistryKeyHandle {
         subkey: Option<&OsStr>,
         permissions: RegistryPermissions,
     ) -> Result<Self, Error> {
-        Self::open_relative(self.0, subkey, permissions)
+        Self::open_relative(self.0, self.1, subkey, permissions)
+    }
+
+    /// Opens the given subkey, creating it (and any missing intermediate keys) if necessary.
+    pub fn create_subkey(
+        &self,
+        subkey: &OsStr,
+        permissions: RegistryPermissions,
+    ) -> Result<Self, Error> {
+        Self::create_relative(self.0, self.1, subkey, permissions)
+    }
+
+    /// Deletes the given subkey, which must not have any subkeys itself, from the view of the
+    /// registry this key was opened in.
+    pub fn delete_subkey(&self, subkey: &OsStr) -> Result<(), Error> {
+        let subkey_ws = WideString::from(subkey);
+        let err_code = unsafe {
+            RegDeleteKeyExW(self.0, subkey_ws.as_pcwstr(), self.1.bits(), 0)
+        };
+        if err_code == NO_ERROR {
+            Ok(())
+        } else {
+            Err(err_code.into())
+        }
     }
 
     pub fn read_value(
@@ -203,56 +492,283 @@ impl RegistryKeyHandle {
     ) -> Result<RegistryValue, Error> {
         let value_name_ws = OptionalWideString::from(value_name);
 
-        // get buffer size
-        let mut byte_count = 0u32;
-        let size_status = unsafe {
-            RegQueryValueExW(
+        let mut attempt = 1;
+        loop {
+            // get buffer size
+            let mut byte_count = 0u32;
+            let size_status = unsafe {
+                RegQueryValueExW(
+                    self.0,
+                    value_name_ws.as_pcwstr(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    &mut byte_count,
+                )
+            };
+            if size_status != NO_ERROR {
+                return Err(size_status.into());
+            }
+
+            let byte_count_usize: usize = byte_count.try_into().unwrap();
+            let mut buf = vec![0u8; byte_count_usize];
+            let mut reg_value_type = REG_VALUE_TYPE::default();
+            let status = unsafe {
+                RegQueryValueExW(
+                    self.0,
+                    value_name_ws.as_pcwstr(),
+                    null_mut(),
+                    &mut reg_value_type,
+                    data_pointer(&mut buf),
+                    &mut byte_count,
+                )
+            };
+            // if the value has grown since we asked for its size, the call fails with
+            // ERROR_MORE_DATA or, if we passed no buffer because the value was empty, succeeds
+            // and only reports the new size
+            let grown = status == ERROR_MORE_DATA
+                || (status == NO_ERROR && byte_count as usize > buf.len());
+            if grown {
+                if attempt < MAX_READ_ATTEMPTS {
+                    attempt += 1;
+                    continue;
+                }
+                return Err(ERROR_MORE_DATA.into());
+            }
+            if status != NO_ERROR {
+                return Err(status.into());
+            }
+
+            // the value might also have shrunk
+            buf.truncate(byte_count.try_into().unwrap());
+            return Ok(RegistryValue::decode_raw(reg_value_type, &buf));
+        }
+    }
+
+    pub fn write_value(
+        &self,
+        value_name: Option<&OsStr>,
+        value: &RegistryValue,
+    ) -> Result<(), Error> {
+        let value_name_ws = OptionalWideString::from(value_name);
+        let bs = value.to_bytes();
+        let byte_count: u32 = bs.len().try_into()
+            .expect_log("registry value too long");
+
+        let status = unsafe {
+            RegSetValueExW(
                 self.0,
                 value_name_ws.as_pcwstr(),
-                null_mut(),
-                null_mut(),
-                null_mut(),
-                &mut byte_count,
+                0,
+                value.to_reg_value_type(),
+                bs.as_ptr(),
+                byte_count,
             )
         };
-        if size_status != NO_ERROR {
-            return Err(size_status.into());
+        if status != NO_ERROR {
+            return Err(status.into());
         }
+        Ok(())
+    }
 
-        let byte_count_usize: usize = byte_count.try_into().unwrap();
-        let mut buf = vec![0u8; byte_count_usize];
-        let mut reg_value_type = REG_VALUE_TYPE::default();
-        let status = unsafe {
-            RegQueryValueExW(
+    /// Reads all values of this key, in the order in which the registry returns them. The default
+    /// value, if set, has an empty name.
+    pub fn enumerate_values(&self) -> Result<Vec<(OsString, RegistryValue)>, Error> {
+        // find out how large the buffers must be
+        let mut value_count = 0u32;
+        let mut max_name_chars = 0u32;
+        let mut max_data_bytes = 0u32;
+        let info_status = unsafe {
+            RegQueryInfoKeyW(
                 self.0,
-                value_name_ws.as_pcwstr(),
+                PWSTR::default(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                &mut value_count,
+                &mut max_name_chars,
+                &mut max_data_bytes,
+                null_mut(),
                 null_mut(),
-                &mut reg_value_type,
-                buf.as_mut_ptr(),
-                &mut byte_count,
             )
         };
-        if status != NO_ERROR {
-            return Err(status.into());
+        if info_status != NO_ERROR {
+            return Err(info_status.into());
         }
 
-        Ok(RegistryValue::decode_raw(reg_value_type, &buf))
+        // the name length does not include the terminating NUL
+        let mut name_buf = vec![0u16; (max_name_chars as usize) + 1];
+        let mut data_buf = vec![0u8; max_data_bytes as usize];
+        let mut values = Vec::with_capacity(value_count as usize);
+        let mut index = 0u32;
+        loop {
+            let mut name_chars: u32 = name_buf.len().try_into().unwrap();
+            let mut data_bytes: u32 = data_buf.len().try_into().unwrap();
+            let mut reg_value_type = 0u32;
+            let status = unsafe {
+                RegEnumValueW(
+                    self.0,
+                    index,
+                    PWSTR(name_buf.as_mut_ptr()),
+                    &mut name_chars,
+                    null_mut(),
+                    &mut reg_value_type,
+                    data_pointer(&mut data_buf),
+                    &mut data_bytes,
+                )
+            };
+            if status == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+            // a value that has been added or grown in the meantime is reported as ERROR_MORE_DATA
+            // or, if we passed no data buffer because all values were empty, as success with a
+            // size larger than the buffer
+            let grown = status == ERROR_MORE_DATA
+                || (status == NO_ERROR && data_bytes as usize > data_buf.len());
+            if grown {
+                let new_name_len = (name_buf.len() * 2).max(256);
+                name_buf.resize(new_name_len, 0);
+                let new_data_len = (data_buf.len() * 2).max(data_bytes as usize);
+                data_buf.resize(new_data_len, 0);
+                continue;
+            }
+            if status != NO_ERROR {
+                return Err(status.into());
+            }
+
+            let name = OsString::from_wide(&name_buf[..name_chars as usize]);
+            let value = RegistryValue::decode_raw(REG_VALUE_TYPE(reg_value_type), &data_buf[..data_bytes as usize]);
+            values.push((name, value));
+            index += 1;
+        }
+
+        Ok(values)
     }
 
-    pub fn read_value_optional(
-        &self,
-        value_name: Option<&OsStr>,
-    ) -> Result<Option<RegistryValue>, Error> {
-        match self.read_value(value_name) {
-            Ok(v) => Ok(Some(v)),
-            Err(e) => {
-                if e.win32_error().map(|we| we == ERROR_FILE_NOT_FOUND).unwrap_or(false) {
-                    Ok(None)
-                } else {
-                    Err(e)
-                }
-            },
+    /// Lists the names of the direct subkeys of this key, in the order in which the registry
+    /// returns them.
+    ///
+    /// The key must have been opened with [`RegistryPermissions::ENUMERATE_SUB_KEYS`].
+    pub fn enumerate_subkeys(&self) -> Result<Vec<OsString>, Error> {
+        // key names are limited to 255 characters
+        let mut name_buf = vec![0u16; 256];
+        let mut subkeys = Vec::new();
+        let mut index = 0u32;
+        loop {
+            let mut name_chars: u32 = name_buf.len().try_into().unwrap();
+            let status = unsafe {
+                RegEnumKeyExW(
+                    self.0,
+                    index,
+                    PWSTR(name_buf.as_mut_ptr()),
+                    &mut name_chars,
+                    null_mut(),
+                    PWSTR::default(),
+                    null_mut(),
+                    null_mut(),
+                )
+            };
+            if status == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+            if status == ERROR_MORE_DATA {
+                let new_name_len = name_buf.len() * 2;
+                name_buf.resize(new_name_len, 0);
+                continue;
+            }
+            if status != NO_ERROR {
+                return Err(status.into());
+            }
+
+            subkeys.push(OsString::from_wide(&name_buf[..name_chars as usize]));
+            index += 1;
         }
+
+        Ok(subkeys)
+    }
+
+    /// Waits up to `timeout` (or indefinitely if `None`) until this key changes in one of the ways
+    /// given in `filter`. Returns whether it did. Changes to subkeys are not taken into account.
+    ///
+    /// The key must have been opened with [`RegistryPermissions::NOTIFY`]. The notification is
+    /// registered anew on each call, so changes made between two calls are not reported.
+    pub fn wait_for_change(&self, filter: RegistryChangeFilter, timeout: Option<Duration>) -> Result<bool, Error> {
+        let event = unsafe {
+            CreateEventW(null(), true, false, PCWSTR::default())
+        }?;
+
+        let notify_status = unsafe {
+            RegNotifyChangeKeyValue(self.0, false, REG_NOTIFY_FILTER::from(filter), event, true)
+        };
+        let wait_res = if notify_status == NO_ERROR {
+            // INFINITE is u32::MAX; stay just below it for actual timeouts
+            let timeout_ms = match timeout {
+                Some(t) => u32::try_from(t.as_millis())
+                    .unwrap_or(u32::MAX - 1)
+                    .min(u32::MAX - 1),
+                None => u32::MAX,
+            };
+            let wait_result = unsafe {
+                WaitForSingleObject(event, timeout_ms)
+            };
+            if wait_result == WAIT_OBJECT_0 {
+                Ok(true)
+            } else if wait_result == WAIT_TIMEOUT.0 {
+                Ok(false)
+            } else {
+                Err(Error::from_win32())
+            }
+        } else {
+            Err(notify_status.into())
+        };
+
+        unsafe {
+            CloseHandle(event)
+        };
+        wait_res
+    }
+
+    /// Takes ownership of a raw registry key handle.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid, open registry key handle that is not owned by anything else; it is
+    /// closed using `RegCloseKey` when the returned value is dropped. Subkeys are opened in the
+    /// default view of the registry.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub unsafe fn from_raw(handle: HKEY) -> Self {
+        Self(handle, RegistryPermissions::empty())
+    }
+
+    /// Relinquishes ownership of the raw registry key handle without closing it. The caller becomes
+    /// responsible for closing it using `RegCloseKey`.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn into_raw(self) -> HKEY {
+        let this = ManuallyDrop::new(self);
+        this.0
+    }
+}
+impl RegistryAccess for RegistryKeyHandle {
+    fn open_subkey(&self, subkey: Option<&OsStr>, permissions: RegistryPermissions) -> Result<Self, Error> {
+        RegistryKeyHandle::open_subkey(self, subkey, permissions)
+    }
+
+    fn create_subkey(&self, subkey: &OsStr, permissions: RegistryPermissions) -> Result<Self, Error> {
+        RegistryKeyHandle::create_subkey(self, subkey, permissions)
+    }
+
+    fn delete_subkey(&self, subkey: &OsStr) -> Result<(), Error> {
+        RegistryKeyHandle::delete_subkey(self, subkey)
+    }
+
+    fn read_value(&self, value_name: Option<&OsStr>) -> Result<RegistryValue, Error> {
+        RegistryKeyHandle::read_value(self, value_name)
+    }
+
+    fn write_value(&self, value_name: Option<&OsStr>, value: &RegistryValue) -> Result<(), Error> {
+        RegistryKeyHandle::write_value(self, value_name, value)
     }
 }
 impl Drop for RegistryKeyHandle {
@@ -267,6 +783,8 @@ impl Drop for RegistryKeyHandle {
 }
 
 
+/// Encodes a string as the bytes of a NUL-terminated UTF-16 string value. Unpaired surrogates are
+/// written as they are.
 fn os_str_to_bytes(os_str: &OsStr) -> Vec<u8> {
     let mut ws = Vec::new();
     ws.extend(os_str.encode_wide());
@@ -280,7 +798,21 @@ fn os_str_to_bytes(os_str: &OsStr) -> Vec<u8> {
     bs
 }
 
-fn bytes_to_os_string(bs: &[u8]) -> OsString {
+/// The pointer to pass as the data buffer to the registry functions: null for an empty buffer, as
+/// the pointer of an empty `Vec` does not point to valid memory.
+fn data_pointer(buf: &mut [u8]) -> *mut u8 {
+    if buf.is_empty() {
+        null_mut()
+    } else {
+        buf.as_mut_ptr()
+    }
+}
+
+/// Reinterprets the bytes of a string value as UTF-16 code units.
+///
+/// The code units are taken as they are; in particular, unpaired surrogates are kept, since
+/// `OsString` (unlike `String`) can represent them and passes them back to the API unchanged.
+fn bytes_to_wide(bs: &[u8]) -> Vec<u16> {
     if bs.len() % 2 != 0 {
         log_panic!("bytes length not divisible by 2");
     }
@@ -293,6 +825,11 @@ fn bytes_to_os_string(bs: &[u8]) -> OsString {
         ];
         ws.push(u16::from_ne_bytes(byte_array));
     }
+    ws
+}
+
+fn bytes_to_os_string(bs: &[u8]) -> OsString {
+    let mut ws = bytes_to_wide(bs);
 
     // strip single trailing NUL
     if ws.last().map(|l| *l == 0x0000).unwrap_or(false) {
@@ -303,28 +840,19 @@ fn bytes_to_os_string(bs: &[u8]) -> OsString {
 }
 
 fn bytes_to_multi_os_string(bs: &[u8]) -> Vec<OsString> {
-    if bs.len() % 2 != 0 {
-        log_panic!("bytes length not divisible by 2");
-    }
-
-    let mut ws = Vec::with_capacity(bs.len()/2);
-    for i in 0..bs.len()/2 {
-        let byte_array = [
-            bs[2*i + 0],
-            bs[2*i + 1],
-        ];
-        ws.push(u16::from_ne_bytes(byte_array));
-    }
+    let ws = bytes_to_wide(bs);
 
-    let mut ss = Vec::with_capacity(ws.iter().filter(|w| **w == 0x0000).count());
-    for slice in ws.split(|w| *w == 0x0000) {
-        if slice.len() == 0 {
-            break;
-        }
-        ss.push(OsString::from_wide(slice));
+    // the list is terminated by an empty string; drop it along with any superfluous NULs (or
+    // none, if the terminator is missing)
+    let mut slices: Vec<&[u16]> = ws.split(|w| *w == 0x0000).collect();
+    while slices.last().map(|l| l.is_empty()).unwrap_or(false) {
+        slices.pop();
     }
 
-    ss
+    // empty strings in the middle cannot be written by the API, but keep them and what follows
+    slices.into_iter()
+        .map(OsString::from_wide)
+        .collect()
 }
 
 fn os_string_to_expand_value(os_string: OsString) -> RegistryValue {
@@ -369,3 +897,254 @@ fn os_string_to_expand_value(os_string: OsString) -> RegistryValue {
         expanded,
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    /// A temporary subkey of `HKEY_CURRENT_USER\Software`, deleted again when dropped.
+    struct ScratchKey {
+        software: RegistryKeyHandle,
+        name: OsString,
+        key: RegistryKeyHandle,
+    }
+    impl ScratchKey {
+        fn create(purpose: &str) -> Self {
+            let software = RegistryKeyHandle::open_predefined(
+                PredefinedKey::CurrentUser,
+                Some(OsStr::new("Software")),
+                RegistryPermissions::CREATE_SUB_KEY,
+            ).expect("failed to open HKEY_CURRENT_USER\\Software");
+            let name = OsString::from(format!("servicerestarter-test-{}-{}", purpose, std::process::id()));
+            let key = software.create_subkey(
+                &name,
+                RegistryPermissions::QUERY_VALUE | RegistryPermissions::SET_VALUE
+                    | RegistryPermissions::CREATE_SUB_KEY | RegistryPermissions::ENUMERATE_SUB_KEYS,
+            ).expect("failed to create scratch key");
+            Self {
+                software,
+                name,
+                key,
+            }
+        }
+    }
+    impl Drop for ScratchKey {
+        fn drop(&mut self) {
+            if let Err(e) = self.software.delete_subkey(&self.name) {
+                eprintln!("failed to delete scratch key {:?}: {}", self.name, e);
+            }
+        }
+    }
+
+    fn wide_bytes(ws: &[u16]) -> Vec<u8> {
+        ws.iter()
+            .flat_map(|w| w.to_ne_bytes())
+            .collect()
+    }
+
+    fn os_strings(strs: &[&str]) -> Vec<OsString> {
+        strs.iter().map(OsString::from).collect()
+    }
+
+    fn write_and_read(key: &RegistryKeyHandle, name: &str, value: &RegistryValue) -> RegistryValue {
+        let name = OsString::from(name);
+        key.write_value(Some(&name), value).expect("failed to write value");
+        key.read_value(Some(&name)).expect("failed to read value")
+    }
+
+    #[test]
+    fn zero_length_values_round_trip() {
+        let scratch = ScratchKey::create("zero-length");
+
+        let binary = RegistryValue::Binary(Vec::new());
+        assert_eq!(write_and_read(&scratch.key, "Binary", &binary), binary);
+
+        let string = RegistryValue::String(OsString::new());
+        assert_eq!(write_and_read(&scratch.key, "String", &string), string);
+
+        // not even a terminating NUL
+        let raw_string = RegistryValue::Unknown { reg_type: REG_SZ.0, bytes: Vec::new() };
+        assert_eq!(write_and_read(&scratch.key, "RawString", &raw_string), string);
+
+        let raw_dword = RegistryValue::Unknown { reg_type: REG_DWORD.0, bytes: Vec::new() };
+        assert_eq!(write_and_read(&scratch.key, "RawDword", &raw_dword), RegistryValue::Dword(0));
+    }
+
+    #[test]
+    fn enumerate_empty_and_non_empty_values() {
+        let scratch = ScratchKey::create("enumerate");
+        let values = vec![
+            (OsString::from("Empty"), RegistryValue::Binary(Vec::new())),
+            (OsString::from("String"), RegistryValue::String(OsString::from("some text"))),
+            (OsString::from("List"), RegistryValue::MultiString(vec![OsString::from("a"), OsString::from("b")])),
+        ];
+        for (name, value) in &values {
+            scratch.key.write_value(Some(name), value).expect("failed to write value");
+        }
+
+        let mut enumerated = scratch.key.enumerate_values().expect("failed to enumerate values");
+        enumerated.sort();
+        let mut expected = values;
+        expected.sort();
+        assert_eq!(enumerated, expected);
+    }
+
+    #[test]
+    fn enumerate_only_empty_values() {
+        let scratch = ScratchKey::create("enumerate-empty");
+        scratch.key.write_value(Some(OsStr::new("Empty")), &RegistryValue::None(Vec::new()))
+            .expect("failed to write value");
+
+        let enumerated = scratch.key.enumerate_values().expect("failed to enumerate values");
+        assert_eq!(enumerated, vec![(OsString::from("Empty"), RegistryValue::None(Vec::new()))]);
+    }
+
+    #[test]
+    fn enumerate_subkeys_lists_direct_subkeys() {
+        let scratch = ScratchKey::create("enum-subkeys");
+        assert_eq!(scratch.key.enumerate_subkeys().unwrap(), Vec::<OsString>::new());
+
+        let first = scratch.key.create_subkey(OsStr::new("First"), RegistryPermissions::CREATE_SUB_KEY).unwrap();
+        let nested = first.create_subkey(OsStr::new("Nested"), RegistryPermissions::QUERY_VALUE).unwrap();
+        scratch.key.create_subkey(OsStr::new("Second"), RegistryPermissions::QUERY_VALUE).unwrap();
+
+        let mut subkeys = scratch.key.enumerate_subkeys().unwrap();
+        subkeys.sort();
+        assert_eq!(subkeys, os_strings(&["First", "Second"]));
+
+        // scratch keys must be empty to be deleted
+        drop(nested);
+        first.delete_subkey(OsStr::new("Nested")).unwrap();
+        drop(first);
+        scratch.key.delete_subkey(OsStr::new("First")).unwrap();
+        scratch.key.delete_subkey(OsStr::new("Second")).unwrap();
+    }
+
+    #[test]
+    fn explicit_view_is_kept() {
+        let query = RegistryPermissions::QUERY_VALUE;
+        let view_64 = RegistryPermissions::WOW64_64KEY;
+        let view_32 = RegistryPermissions::WOW64_32KEY;
+        assert_eq!(query.with_view_or(RegistryPermissions::empty()), query);
+        assert_eq!(query.with_view_or(view_64), query | view_64);
+        assert_eq!((query | view_32).with_view_or(view_64), query | view_32);
+        assert_eq!((query | view_32).view(), view_32);
+    }
+
+    #[test]
+    fn subkeys_stay_in_the_view_of_their_parent() {
+        let scratch = ScratchKey::create("view");
+        assert_eq!(scratch.key.1, RegistryPermissions::empty());
+
+        let hklm_64 = RegistryKeyHandle::open_predefined(
+            PredefinedKey::LocalMachine,
+            None,
+            RegistryPermissions::QUERY_VALUE | RegistryPermissions::WOW64_64KEY,
+        ).unwrap();
+        let software = hklm_64.open_subkey(Some(OsStr::new("SOFTWARE")), RegistryPermissions::QUERY_VALUE).unwrap();
+        assert_eq!(software.1, RegistryPermissions::WOW64_64KEY);
+    }
+
+    #[test]
+    fn raw_handle_round_trips() {
+        let scratch = ScratchKey::create("raw-handle");
+        scratch.key.write_value(Some(OsStr::new("Answer")), &RegistryValue::Dword(42)).unwrap();
+
+        // a second handle to the same key
+        let handle = scratch.key.open_subkey(None, RegistryPermissions::QUERY_VALUE).unwrap();
+        let raw = handle.into_raw();
+        let rewrapped = unsafe { RegistryKeyHandle::from_raw(raw) };
+        assert_eq!(rewrapped.read_value(Some(OsStr::new("Answer"))).unwrap(), RegistryValue::Dword(42));
+    }
+
+    #[test]
+    fn filetime_values_are_decoded() {
+        // 2009-02-13 23:31:30 UTC
+        let filetime: u64 = 128_790_414_900_000_000;
+        let expected = UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+
+        assert_eq!(RegistryValue::Qword(filetime).to_filetime(), Some(expected));
+        assert_eq!(RegistryValue::Binary(filetime.to_le_bytes().to_vec()).to_filetime(), Some(expected));
+        assert_eq!(RegistryValue::Binary(filetime.to_le_bytes()[..7].to_vec()).to_filetime(), None);
+        assert_eq!(RegistryValue::Binary(Vec::new()).to_filetime(), None);
+        assert_eq!(RegistryValue::Dword(42).to_filetime(), None);
+    }
+
+    #[test]
+    fn multi_string_empty_list_round_trips() {
+        let value = RegistryValue::MultiString(Vec::new());
+        let bytes = value.to_bytes();
+        assert_eq!(bytes, wide_bytes(&[0]));
+        assert_eq!(RegistryValue::decode_raw(REG_MULTI_SZ, &bytes), value);
+
+        // the registry may also hand out an empty or double-NUL multi-string
+        assert_eq!(bytes_to_multi_os_string(&[]), Vec::<OsString>::new());
+        assert_eq!(bytes_to_multi_os_string(&wide_bytes(&[0, 0])), Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn multi_string_empty_elements_are_skipped() {
+        // an empty element would end the list, so it is not written
+        let single_empty = RegistryValue::MultiString(os_strings(&[""]));
+        assert_eq!(single_empty.to_bytes(), wide_bytes(&[0]));
+        assert_eq!(
+            RegistryValue::decode_raw(REG_MULTI_SZ, &single_empty.to_bytes()),
+            RegistryValue::MultiString(Vec::new()),
+        );
+
+        let inner_empty = RegistryValue::MultiString(os_strings(&["a", "", "b"]));
+        let bytes = inner_empty.to_bytes();
+        assert_eq!(bytes, wide_bytes(&[0x61, 0, 0x62, 0, 0]));
+        assert_eq!(
+            RegistryValue::decode_raw(REG_MULTI_SZ, &bytes),
+            RegistryValue::MultiString(os_strings(&["a", "b"])),
+        );
+
+        // if one has been written anyway, it is kept along with what follows
+        assert_eq!(
+            bytes_to_multi_os_string(&wide_bytes(&[0x61, 0, 0, 0x62, 0, 0])),
+            os_strings(&["a", "", "b"]),
+        );
+    }
+
+    #[test]
+    fn multi_string_without_terminator() {
+        assert_eq!(bytes_to_multi_os_string(&wide_bytes(&[0x61, 0, 0x62])), os_strings(&["a", "b"]));
+        assert_eq!(bytes_to_multi_os_string(&wide_bytes(&[0x61, 0, 0x62, 0])), os_strings(&["a", "b"]));
+        assert_eq!(bytes_to_multi_os_string(&wide_bytes(&[0x61, 0, 0x62, 0, 0, 0])), os_strings(&["a", "b"]));
+    }
+
+    #[test]
+    fn multi_string_round_trips() {
+        let value = RegistryValue::MultiString(os_strings(&["one", "two words", "three"]));
+        assert_eq!(RegistryValue::decode_raw(REG_MULTI_SZ, &value.to_bytes()), value);
+    }
+
+    /// "a", an unpaired high surrogate, "b"
+    const LONE_SURROGATE: [u16; 3] = [0x0061, 0xD800, 0x0062];
+
+    #[test]
+    fn lone_surrogate_string_round_trips() {
+        let os_string = OsString::from_wide(&LONE_SURROGATE);
+        assert!(os_string.to_str().is_none());
+
+        let bytes = os_str_to_bytes(&os_string);
+        assert_eq!(bytes, wide_bytes(&[0x0061, 0xD800, 0x0062, 0]));
+        assert_eq!(bytes_to_os_string(&bytes), os_string);
+        assert_eq!(bytes_to_os_string(&bytes).encode_wide().collect::<Vec<u16>>(), LONE_SURROGATE);
+    }
+
+    #[test]
+    fn lone_surrogate_multi_string_round_trips() {
+        let value = RegistryValue::MultiString(vec![
+            OsString::from_wide(&LONE_SURROGATE),
+            OsString::from("c"),
+        ]);
+        let bytes = value.to_bytes();
+        assert_eq!(bytes, wide_bytes(&[0x0061, 0xD800, 0x0062, 0, 0x0063, 0, 0]));
+        assert_eq!(RegistryValue::decode_raw(REG_MULTI_SZ, &bytes), value);
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// A small xorshift64* pseudorandom number generator.
+///
+/// Not suitable for anything security-related; it is only used to spread out timing.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct XorShiftRng(u64);
+impl XorShiftRng {
+    pub fn new(seed: u64) -> Self {
+        // the state must never be zero
+        if seed == 0 {
+            Self(0x9E37_79B9_7F4A_7C15)
+        } else {
+            Self(seed)
+        }
+    }
+
+    /// Creates a generator seeded from the current time and the process ID.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let pid: u64 = std::process::id().into();
+        Self::new(nanos ^ pid.rotate_left(32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a value between `0` and `max`, inclusive.
+    pub fn next_up_to(&mut self, max: u64) -> u64 {
+        match max.checked_add(1) {
+            Some(range) => self.next_u64() % range,
+            None => self.next_u64(),
+        }
+    }
+}
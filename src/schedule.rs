@@ -0,0 +1,255 @@
+use std::fmt;
+
+use chrono::{Datelike, Timelike, Weekday};
+
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const ALL_DAYS: u8 = 0b111_1111;
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum ScheduleParseError {
+    MissingTimeRange(String),
+    InvalidTime(String),
+    UnknownDay(String),
+}
+impl fmt::Display for ScheduleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingTimeRange(w) => write!(f, "window {:?} does not contain a time range HH:MM-HH:MM", w),
+            Self::InvalidTime(t) => write!(f, "invalid time {:?} (expected HH:MM between 00:00 and 24:00)", t),
+            Self::UnknownDay(d) => write!(f, "unknown day of the week {:?} (expected Mon, Tue, Wed, Thu, Fri, Sat or Sun)", d),
+        }
+    }
+}
+impl std::error::Error for ScheduleParseError {
+}
+
+
+/// A recurring span of time during which services may be restarted.
+///
+/// If the end time is not after the start time, the window extends past midnight into the next
+/// day; it is then considered to belong to the day on which it starts.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct RestartWindow {
+    /// Days on which the window starts; bit 0 is Monday, bit 6 is Sunday.
+    days: u8,
+    start_minute: u32,
+    end_minute: u32,
+}
+impl RestartWindow {
+    /// Parses a window such as `18:00-08:00`, `Sat,Sun 00:00-24:00` or `Mon-Fri 17:00-09:00`.
+    pub fn parse(text: &str) -> Result<Self, ScheduleParseError> {
+        let text = text.trim();
+        let (days_str, range_str) = match text.rsplit_once(char::is_whitespace) {
+            Some((d, r)) => (Some(d.trim()), r),
+            None => (None, text),
+        };
+
+        let (start_str, end_str) = range_str.split_once('-')
+            .ok_or_else(|| ScheduleParseError::MissingTimeRange(text.to_owned()))?;
+        let start_minute = parse_time(start_str)?;
+        let end_minute = parse_time(end_str)?;
+
+        let days = match days_str {
+            Some(ds) => parse_days(ds)?,
+            None => ALL_DAYS,
+        };
+
+        Ok(Self {
+            days,
+            start_minute,
+            end_minute,
+        })
+    }
+
+    fn starts_on(&self, weekday: Weekday) -> bool {
+        self.days & (1 << weekday.num_days_from_monday()) != 0
+    }
+
+    /// Whether the given time of the given day of the week falls within this window.
+    pub fn contains(&self, weekday: Weekday, minute_of_day: u32) -> bool {
+        if self.start_minute < self.end_minute {
+            self.starts_on(weekday)
+                && minute_of_day >= self.start_minute
+                && minute_of_day < self.end_minute
+        } else {
+            // wraps around midnight
+            (self.starts_on(weekday) && minute_of_day >= self.start_minute)
+                || (self.starts_on(weekday.pred()) && minute_of_day < self.end_minute)
+        }
+    }
+}
+
+
+fn parse_time(text: &str) -> Result<u32, ScheduleParseError> {
+    let invalid = || ScheduleParseError::InvalidTime(text.to_owned());
+
+    let (hour_str, minute_str) = text.trim().split_once(':')
+        .ok_or_else(invalid)?;
+    let hour: u32 = hour_str.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute_str.parse().map_err(|_| invalid())?;
+    if minute >= 60 {
+        return Err(invalid());
+    }
+
+    let minute_of_day = hour * 60 + minute;
+    if minute_of_day > MINUTES_PER_DAY {
+        return Err(invalid());
+    }
+    Ok(minute_of_day)
+}
+
+fn parse_day(text: &str) -> Result<u32, ScheduleParseError> {
+    let day: Weekday = text.parse()
+        .map_err(|_| ScheduleParseError::UnknownDay(text.to_owned()))?;
+    Ok(day.num_days_from_monday())
+}
+
+/// Parses a comma-separated list of days and day ranges such as `Mon-Fri` or `Mon,Wed,Sat-Sun`.
+fn parse_days(text: &str) -> Result<u8, ScheduleParseError> {
+    let mut days: u8 = 0;
+    for piece in text.split(',') {
+        let piece = piece.trim();
+        match piece.split_once('-') {
+            Some((first_str, last_str)) => {
+                let first = parse_day(first_str.trim())?;
+                let last = parse_day(last_str.trim())?;
+
+                // ranges such as Fri-Mon wrap around the end of the week
+                let mut day = first;
+                loop {
+                    days |= 1 << day;
+                    if day == last {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            },
+            None => {
+                days |= 1 << parse_day(piece)?;
+            },
+        }
+    }
+    Ok(days)
+}
+
+
+/// Whether the given point in time falls within at least one of the windows.
+pub(crate) fn is_within_windows<T: Datelike + Timelike>(windows: &[RestartWindow], time: &T) -> bool {
+    let weekday = time.weekday();
+    let minute_of_day = time.hour() * 60 + time.minute();
+    windows.iter().any(|w| w.contains(weekday, minute_of_day))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn minute(hour: u32, minute: u32) -> u32 {
+        hour * 60 + minute
+    }
+
+    #[test]
+    fn plain_window() {
+        let window = RestartWindow::parse("09:00-17:00").unwrap();
+        assert_eq!(window, RestartWindow { days: ALL_DAYS, start_minute: minute(9, 0), end_minute: minute(17, 0) });
+        assert!(!window.contains(Weekday::Wed, minute(8, 59)));
+        assert!(window.contains(Weekday::Wed, minute(9, 0)));
+        assert!(window.contains(Weekday::Wed, minute(16, 59)));
+        assert!(!window.contains(Weekday::Wed, minute(17, 0)));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let window = RestartWindow::parse("Fri 18:00-08:00").unwrap();
+        assert!(!window.contains(Weekday::Fri, minute(17, 59)));
+        assert!(window.contains(Weekday::Fri, minute(18, 0)));
+        assert!(window.contains(Weekday::Fri, minute(23, 59)));
+
+        // the morning after belongs to the window that started the day before
+        assert!(window.contains(Weekday::Sat, minute(0, 0)));
+        assert!(window.contains(Weekday::Sat, minute(7, 59)));
+        assert!(!window.contains(Weekday::Sat, minute(8, 0)));
+        assert!(!window.contains(Weekday::Sat, minute(18, 0)));
+
+        // ...and not the morning of the day it starts
+        assert!(!window.contains(Weekday::Fri, minute(7, 0)));
+    }
+
+    #[test]
+    fn window_starting_on_sunday_wraps_into_monday() {
+        let window = RestartWindow::parse("Sun 22:00-02:00").unwrap();
+        assert!(window.contains(Weekday::Mon, minute(1, 0)));
+        assert!(!window.contains(Weekday::Sun, minute(1, 0)));
+    }
+
+    #[test]
+    fn end_of_day() {
+        let window = RestartWindow::parse("00:00-24:00").unwrap();
+        assert_eq!(window.end_minute, MINUTES_PER_DAY);
+        assert!(window.contains(Weekday::Tue, minute(0, 0)));
+        assert!(window.contains(Weekday::Tue, minute(23, 59)));
+
+        assert_eq!(RestartWindow::parse("00:00-24:01"), Err(ScheduleParseError::InvalidTime("24:01".to_owned())));
+        assert_eq!(RestartWindow::parse("12:60-13:00"), Err(ScheduleParseError::InvalidTime("12:60".to_owned())));
+        assert_eq!(RestartWindow::parse("12-13:00"), Err(ScheduleParseError::InvalidTime("12".to_owned())));
+        assert_eq!(RestartWindow::parse("noon-13:00"), Err(ScheduleParseError::InvalidTime("noon".to_owned())));
+    }
+
+    #[test]
+    fn missing_time_range() {
+        assert_eq!(RestartWindow::parse("Mon 09:00"), Err(ScheduleParseError::MissingTimeRange("Mon 09:00".to_owned())));
+    }
+
+    #[test]
+    fn day_lists_and_ranges() {
+        let weekend = RestartWindow::parse("Sat,Sun 00:00-24:00").unwrap();
+        assert_eq!(weekend.days, 0b110_0000);
+
+        let long_weekend = RestartWindow::parse("Fri-Mon 10:00-11:00").unwrap();
+        assert_eq!(long_weekend.days, 0b111_0001);
+        assert!(long_weekend.contains(Weekday::Sun, minute(10, 30)));
+        assert!(!long_weekend.contains(Weekday::Wed, minute(10, 30)));
+
+        let mixed = RestartWindow::parse("Mon, Wed-Thu 10:00-11:00").unwrap();
+        assert_eq!(mixed.days, 0b000_1101);
+
+        let single = RestartWindow::parse("Tue-Tue 10:00-11:00").unwrap();
+        assert_eq!(single.days, 0b000_0010);
+    }
+
+    #[test]
+    fn unknown_days() {
+        assert_eq!(RestartWindow::parse("Funday 10:00-11:00"), Err(ScheduleParseError::UnknownDay("Funday".to_owned())));
+        assert_eq!(RestartWindow::parse("Mon-Xyz 10:00-11:00"), Err(ScheduleParseError::UnknownDay("Xyz".to_owned())));
+        assert_eq!(RestartWindow::parse("Mon,,Tue 10:00-11:00"), Err(ScheduleParseError::UnknownDay("".to_owned())));
+    }
+
+    #[test]
+    fn same_start_and_end_spans_a_whole_day() {
+        let window = RestartWindow::parse("Mon 08:00-08:00").unwrap();
+        assert!(!window.contains(Weekday::Mon, minute(7, 59)));
+        assert!(window.contains(Weekday::Mon, minute(8, 0)));
+        assert!(window.contains(Weekday::Tue, minute(7, 59)));
+        assert!(!window.contains(Weekday::Tue, minute(8, 0)));
+    }
+
+    #[test]
+    fn local_time_in_windows() {
+        let windows = vec![
+            RestartWindow::parse("Mon-Fri 18:00-08:00").unwrap(),
+            RestartWindow::parse("Sat,Sun 00:00-24:00").unwrap(),
+        ];
+        // 2024-01-01 is a Monday
+        let monday_noon = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let monday_evening = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(19, 30, 0).unwrap();
+        let saturday_noon = NaiveDate::from_ymd_opt(2024, 1, 6).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        assert!(!is_within_windows(&windows, &monday_noon));
+        assert!(is_within_windows(&windows, &monday_evening));
+        assert!(is_within_windows(&windows, &saturday_noon));
+        assert!(!is_within_windows(&[], &saturday_noon));
+    }
+}
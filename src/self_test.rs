@@ -0,0 +1,125 @@
+use std::ffi::{OsStr, OsString};
+use std::os::windows::ffi::OsStringExt;
+
+use log::{error, info};
+
+use crate::registry::{PredefinedKey, RegistryKeyHandle, RegistryPermissions, RegistryValue};
+
+
+/// Exit code of `self-test` if a value did not survive the round trip or the test could not be
+/// performed.
+const EXIT_SELF_TEST_FAILED: i32 = 1;
+
+
+/// `a`, an unpaired high surrogate, `b`; not valid Unicode, but a valid `OsString` and registry
+/// string.
+const LONE_SURROGATE: [u16; 3] = [0x0061, 0xD800, 0x0062];
+
+
+/// One value of each type, plus the edge cases of empty data and strings that are not valid
+/// Unicode.
+fn sample_values() -> Vec<(&'static str, RegistryValue)> {
+    vec![
+        ("None", RegistryValue::None(vec![0x01, 0x02, 0x03])),
+        ("NoneEmpty", RegistryValue::None(Vec::new())),
+        ("String", RegistryValue::String(OsString::from("servicerestarter self-test \u{00E4}\u{20AC}"))),
+        ("StringEmpty", RegistryValue::String(OsString::new())),
+        ("StringLoneSurrogate", RegistryValue::String(OsString::from_wide(&LONE_SURROGATE))),
+        ("ExpandString", RegistryValue::ExpandString {
+            // no variables, so that expansion does not change it
+            unexpanded: OsString::from("C:\\no\\variables"),
+            expanded: OsString::from("C:\\no\\variables"),
+        }),
+        ("Binary", RegistryValue::Binary(vec![0x00, 0xFF, 0x10, 0x80])),
+        ("BinaryEmpty", RegistryValue::Binary(Vec::new())),
+        ("Dword", RegistryValue::Dword(0xDEAD_BEEF)),
+        ("DwordBigEndian", RegistryValue::DwordBigEndian(0x0102_0304)),
+        ("Link", RegistryValue::Link(OsString::from("\\Registry\\Machine\\Software"))),
+        ("MultiString", RegistryValue::MultiString(vec![
+            OsString::from("one"),
+            OsString::from("two words"),
+            OsString::from("three"),
+        ])),
+        ("MultiStringEmpty", RegistryValue::MultiString(Vec::new())),
+        ("MultiStringLoneSurrogate", RegistryValue::MultiString(vec![
+            OsString::from("before"),
+            OsString::from_wide(&LONE_SURROGATE),
+            OsString::from("after"),
+        ])),
+        ("ResourceList", RegistryValue::ResourceList(vec![0x01, 0x00, 0x00, 0x00])),
+        ("FullResourceDescriptor", RegistryValue::FullResourceDescriptor(vec![0x02, 0x00, 0x00, 0x00])),
+        ("ResourceRequirementsList", RegistryValue::ResourceRequirementsList(vec![0x03, 0x00, 0x00, 0x00])),
+        ("Qword", RegistryValue::Qword(0xFEDC_BA98_7654_3210)),
+        // the registry accepts any type number
+        ("Unknown", RegistryValue::Unknown { reg_type: 0x5EC5, bytes: vec![0x04, 0x05, 0x06] }),
+    ]
+}
+
+/// Writes each sample value and reads it back. Returns the number of values that did not survive.
+fn round_trip_values(key: &RegistryKeyHandle) -> usize {
+    let mut failures = 0;
+    for (name, value) in sample_values() {
+        let name_os = OsString::from(name);
+        if let Err(e) = key.write_value(Some(&name_os), &value) {
+            error!("failed to write {}: {}", name, e);
+            failures += 1;
+            continue;
+        }
+        match key.read_value(Some(&name_os)) {
+            Ok(read) if read == value => info!("{}: ok", name),
+            Ok(read) => {
+                error!("{}: wrote {} but read back {}", name, value.preview(), read.preview());
+                failures += 1;
+            },
+            Err(e) => {
+                error!("failed to read back {}: {}", name, e);
+                failures += 1;
+            },
+        }
+    }
+    failures
+}
+
+/// Checks that registry values survive being written and read back on this system, using a
+/// temporary subkey of `HKEY_CURRENT_USER\Software` that is deleted afterwards. Returns the
+/// process exit code.
+pub(crate) fn self_test() -> i32 {
+    let software_res = RegistryKeyHandle::open_predefined(
+        PredefinedKey::CurrentUser,
+        Some(OsStr::new("Software")),
+        RegistryPermissions::CREATE_SUB_KEY,
+    );
+    let software = match software_res {
+        Ok(s) => s,
+        Err(e) => {
+            error!("failed to open HKEY_CURRENT_USER\\Software: {}", e);
+            return EXIT_SELF_TEST_FAILED;
+        },
+    };
+
+    let test_key_name = OsString::from(format!("servicerestarter-self-test-{}", std::process::id()));
+    let test_key_res = software.create_subkey(
+        &test_key_name,
+        RegistryPermissions::QUERY_VALUE | RegistryPermissions::SET_VALUE,
+    );
+    let failures = match test_key_res {
+        Ok(test_key) => round_trip_values(&test_key),
+        Err(e) => {
+            error!("failed to create HKEY_CURRENT_USER\\Software\\{}: {}", test_key_name.to_string_lossy(), e);
+            return EXIT_SELF_TEST_FAILED;
+        },
+    };
+
+    let mut exit_code = 0;
+    if let Err(e) = software.delete_subkey(&test_key_name) {
+        error!("failed to delete HKEY_CURRENT_USER\\Software\\{}: {}", test_key_name.to_string_lossy(), e);
+        exit_code = EXIT_SELF_TEST_FAILED;
+    }
+    if failures > 0 {
+        error!("{} of {} values did not survive the round trip", failures, sample_values().len());
+        exit_code = EXIT_SELF_TEST_FAILED;
+    } else {
+        info!("all values survived the round trip");
+    }
+    exit_code
+}
@@ -1,25 +1,38 @@
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::mem::{ManuallyDrop, size_of};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
 use std::ptr::null_mut;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use bitflags::bitflags;
 use from_to_repr::FromToRepr;
+use log::warn;
 use windows::core::{Error, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{
+    BOOL, ERROR_INSUFFICIENT_BUFFER, ERROR_MORE_DATA, ERROR_SERVICE_DATABASE_LOCKED,
+    ERROR_SERVICE_DOES_NOT_EXIST,
+};
 use windows::Win32::Security::SC_HANDLE;
 use windows::Win32::Storage::FileSystem::READ_CONTROL;
 use windows::Win32::System::Services::{
-    CloseServiceHandle, ControlService, CreateServiceW, DeleteService, ENUM_SERVICE_TYPE,
-    OpenSCManagerW, OpenServiceW, QueryServiceStatus, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE,
-    SC_MANAGER_ENUMERATE_SERVICE, SC_MANAGER_LOCK, SC_MANAGER_MODIFY_BOOT_CONFIG,
-    SC_MANAGER_QUERY_LOCK_STATUS, SERVICE_ADAPTER, SERVICE_AUTO_START, SERVICE_BOOT_START,
-    SERVICE_CHANGE_CONFIG, SERVICE_CONTINUE_PENDING, SERVICE_CONTROL_STOP, SERVICE_DEMAND_START,
-    SERVICE_DISABLED, SERVICE_ENUMERATE_DEPENDENTS, SERVICE_ERROR_CRITICAL, SERVICE_ERROR_IGNORE,
+    ChangeServiceConfigW, CloseServiceHandle, ControlService, CreateServiceW, DeleteService,
+    ENUM_SERVICE_STATUS_PROCESSW, ENUM_SERVICE_TYPE, EnumServicesStatusExW, GetServiceDisplayNameW, GetServiceKeyNameW, OpenSCManagerW, OpenServiceW,
+    QUERY_SERVICE_CONFIGW, QUERY_SERVICE_LOCK_STATUSW, QueryServiceConfigW, QueryServiceLockStatusW, QueryServiceStatus, QueryServiceStatusEx,
+    SC_ENUM_PROCESS_INFO, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE, SC_MANAGER_ENUMERATE_SERVICE, SC_MANAGER_LOCK,
+    SC_MANAGER_MODIFY_BOOT_CONFIG, SC_MANAGER_QUERY_LOCK_STATUS, SC_STATUS_PROCESS_INFO,
+    SERVICE_ADAPTER, SERVICE_AUTO_START, SERVICE_BOOT_START, SERVICE_CHANGE_CONFIG,
+    SERVICE_CONTINUE_PENDING, SERVICE_CONTROL_STOP, SERVICE_DEMAND_START, SERVICE_DISABLED,
+    SERVICE_ENUMERATE_DEPENDENTS, SERVICE_ERROR_CRITICAL, SERVICE_ERROR_IGNORE,
     SERVICE_ERROR_NORMAL, SERVICE_ERROR_SEVERE, SERVICE_ERROR, SERVICE_FILE_SYSTEM_DRIVER,
-    SERVICE_INTERROGATE, SERVICE_KERNEL_DRIVER, SERVICE_PAUSE_CONTINUE, SERVICE_PAUSE_PENDING,
-    SERVICE_PAUSED, SERVICE_QUERY_CONFIG, SERVICE_QUERY_STATUS, SERVICE_RECOGNIZER_DRIVER,
-    SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_START_TYPE, SERVICE_START, SERVICE_STATUS,
-    SERVICE_STATUS_CURRENT_STATE, SERVICE_STOP_PENDING, SERVICE_STOP, SERVICE_STOPPED,
-    SERVICE_SYSTEM_START, SERVICE_USER_DEFINED_CONTROL, SERVICE_WIN32_OWN_PROCESS,
+    SERVICE_INTERROGATE, SERVICE_KERNEL_DRIVER, SERVICE_NO_CHANGE, SERVICE_PAUSE_CONTINUE,
+    SERVICE_PAUSE_PENDING, SERVICE_PAUSED, SERVICE_QUERY_CONFIG, SERVICE_QUERY_STATUS,
+    SERVICE_RECOGNIZER_DRIVER, SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_START_TYPE,
+    SERVICE_START, SERVICE_STATE_ALL, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_PROCESS,
+    SERVICE_STOP_PENDING, SERVICE_STOP, SERVICE_STOPPED, SERVICE_SYSTEM_START,
+    SERVICE_USER_DEFINED_CONTROL, SERVICE_WIN32, SERVICE_WIN32_OWN_PROCESS,
     SERVICE_WIN32_SHARE_PROCESS, SERVICES_ACTIVE_DATABASEW, StartServiceW,
 };
 use windows::Win32::System::SystemServices::{
@@ -27,7 +40,135 @@ use windows::Win32::System::SystemServices::{
 };
 
 use crate::extensions::ExpectExtension;
-use crate::windows_utils::{OptionalWideString, WideString};
+use crate::windows_utils::{OptionalWideString, WideMultiString, WideString};
+
+
+/// The maximum length of a service name, in UTF-16 code units.
+const MAX_SERVICE_NAME_LENGTH: usize = 256;
+
+
+/// Why a service name is not acceptable to the service control manager.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum NameError {
+    Empty,
+    TooLong { length: usize },
+    InvalidCharacter { character: char },
+}
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "service name is empty"),
+            Self::TooLong { length } => write!(f, "service name is {} characters long; at most {} are allowed", length, MAX_SERVICE_NAME_LENGTH),
+            Self::InvalidCharacter { character } => write!(f, "service name contains the invalid character {:?}", character),
+        }
+    }
+}
+impl std::error::Error for NameError {
+}
+
+/// Checks a service name against the rules of the service control manager: it must not be empty,
+/// must be at most 256 characters long and must not contain slashes, backslashes or NULs.
+pub(crate) fn validate_service_name(service_name: &OsStr) -> Result<(), NameError> {
+    let length = service_name.encode_wide().count();
+    if length == 0 {
+        return Err(NameError::Empty);
+    }
+    if length > MAX_SERVICE_NAME_LENGTH {
+        return Err(NameError::TooLong { length });
+    }
+    let invalid = service_name.to_string_lossy()
+        .chars()
+        .find(|c| *c == '/' || *c == '\\' || *c == '\0');
+    if let Some(character) = invalid {
+        return Err(NameError::InvalidCharacter { character });
+    }
+    Ok(())
+}
+
+
+/// How often and how patiently to retry operations that fail because the service control manager
+/// database is locked.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct LockedRetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+impl Default for LockedRetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+impl LockedRetryPolicy {
+    /// Performs the operation, retrying it according to this policy while it fails with
+    /// `ERROR_SERVICE_DATABASE_LOCKED`.
+    pub fn run<T, F: FnMut() -> Result<T, Error>>(&self, description: &str, mut operation: F) -> Result<T, Error> {
+        let mut retries_left = self.retries;
+        loop {
+            match operation() {
+                Err(e) if e.win32_error() == Some(ERROR_SERVICE_DATABASE_LOCKED) && retries_left > 0 => {
+                    retries_left -= 1;
+                    warn!(
+                        "failed to {} because the service database is locked; retrying in {} ms ({} retries left)",
+                        description, self.delay.as_millis(), retries_left,
+                    );
+                    sleep(self.delay);
+                },
+                other => return other,
+            }
+        }
+    }
+}
+
+
+/// Access to a service control manager, allowing services to be opened.
+///
+/// Implemented by [`ServiceControlManagerHandle`]; the abstraction allows the service loop to
+/// operate on something other than the actual service control manager.
+pub(crate) trait ServiceControl {
+    type Service: ServiceAccess;
+
+    fn open_service(
+        &self,
+        service_name: &OsStr,
+        desired_access: ServicePermissions,
+    ) -> Result<Self::Service, Error>;
+
+    fn resolve_display_name(&self, display_name: &OsStr) -> Result<OsString, Error>;
+
+    fn get_display_name(&self, key_name: &OsStr) -> Result<OsString, Error>;
+}
+
+
+/// Operations on an opened service.
+///
+/// Implemented by [`ServiceHandle`].
+pub(crate) trait ServiceAccess {
+    fn start(&self, args: Vec<&OsStr>) -> Result<(), Error>;
+    fn get_state(&self) -> Result<ServiceState, Error>;
+    fn get_process_id(&self) -> Result<Option<u32>, Error>;
+
+    /// The start type of the service, or `None` if it is not one of the known ones.
+    fn get_start_type(&self) -> Result<Option<ServiceStartType>, Error>;
+
+    /// Polls the state of the service every `poll_interval` until it is `desired` or `timeout` has
+    /// passed. Returns whether the desired state has been reached.
+    fn wait_for_state(&self, desired: ServiceState, timeout: Duration, poll_interval: Duration) -> Result<bool, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.get_state()? == desired {
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            sleep(poll_interval.min(deadline - now));
+        }
+    }
+}
 
 
 #[derive(Debug, Eq, PartialEq)]
@@ -68,13 +209,7 @@ impl ServiceControlManagerHandle {
         let start_name_ws = OptionalWideString::from(start_name);
         let password_ws = OptionalWideString::from(password);
 
-        let mut deps_os_str = OsString::new();
-        for dep in dependencies {
-            deps_os_str.push(dep);
-            deps_os_str.push("\0");
-        }
-        deps_os_str.push("\0");
-        let deps_ws = WideString::from(&deps_os_str);
+        let deps_ws: WideMultiString = dependencies.into_iter().collect();
 
         let service_handle = unsafe {
             CreateServiceW(
@@ -96,6 +231,23 @@ impl ServiceControlManagerHandle {
         Ok(ServiceHandle(service_handle))
     }
 
+    /// Creates a service as described by the given options; see [`ServiceInstallOptions`].
+    pub(crate) fn create_service_with(&self, options: &ServiceInstallOptions) -> Result<ServiceHandle, Error> {
+        self.create_service(
+            &options.service_name,
+            options.display_name.as_deref(),
+            options.desired_access,
+            options.service_type,
+            options.start_type,
+            options.error_control,
+            &options.path_and_args,
+            options.load_order_group.as_deref(),
+            options.dependencies.iter().map(|d| d.as_os_str()).collect(),
+            options.start_name.as_deref(),
+            options.password.as_deref(),
+        )
+    }
+
     pub(crate) fn open_service(
         &self,
         service_name: &OsStr,
@@ -112,6 +264,233 @@ impl ServiceControlManagerHandle {
         }?;
         Ok(ServiceHandle(service_handle))
     }
+
+    /// Checks whether a service with the given name is installed.
+    pub(crate) fn service_exists(&self, service_name: &OsStr) -> Result<bool, Error> {
+        match ServiceControlManagerHandle::open_service(self, service_name, ServicePermissions::empty()) {
+            Ok(_) => Ok(true),
+            Err(e) if e.win32_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Finds the (key) name of the service with the given display name.
+    pub(crate) fn resolve_display_name(&self, display_name: &OsStr) -> Result<OsString, Error> {
+        self.query_name(display_name, |scm, input, output, char_count| unsafe {
+            GetServiceKeyNameW(scm, input, output, char_count)
+        })
+    }
+
+    /// Finds the display name of the service with the given (key) name.
+    pub(crate) fn get_display_name(&self, key_name: &OsStr) -> Result<OsString, Error> {
+        self.query_name(key_name, |scm, input, output, char_count| unsafe {
+            GetServiceDisplayNameW(scm, input, output, char_count)
+        })
+    }
+
+    /// Lists all the Win32 services (but not drivers), whatever their state. Requires
+    /// [`ServiceControlManagerPermissions::ENUMERATE_SERVICE`].
+    pub(crate) fn enumerate_services(&self) -> Result<Vec<ServiceEntry>, Error> {
+        let mut entries = Vec::new();
+        let mut resume_handle: u32 = 0;
+        loop {
+            // find out how much memory the remaining services need
+            let mut bytes_needed: u32 = 0;
+            let mut services_returned: u32 = 0;
+            let succeeded = unsafe {
+                EnumServicesStatusExW(
+                    self.0,
+                    SC_ENUM_PROCESS_INFO,
+                    SERVICE_WIN32,
+                    SERVICE_STATE_ALL,
+                    null_mut(),
+                    0,
+                    &mut bytes_needed,
+                    &mut services_returned,
+                    &mut resume_handle,
+                    PCWSTR::default(),
+                )
+            }.as_bool();
+            if succeeded {
+                // no services left
+                return Ok(entries);
+            }
+            let err = Error::from_win32();
+            if err.win32_error() != Some(ERROR_MORE_DATA) {
+                return Err(err);
+            }
+
+            // u64 to ensure sufficient alignment
+            let word_count = (bytes_needed as usize).div_ceil(size_of::<u64>());
+            let mut buf: Vec<u64> = vec![0; word_count.max(1)];
+            let succeeded = unsafe {
+                EnumServicesStatusExW(
+                    self.0,
+                    SC_ENUM_PROCESS_INFO,
+                    SERVICE_WIN32,
+                    SERVICE_STATE_ALL,
+                    buf.as_mut_ptr() as *mut u8,
+                    (buf.len() * size_of::<u64>()) as u32,
+                    &mut bytes_needed,
+                    &mut services_returned,
+                    &mut resume_handle,
+                    PCWSTR::default(),
+                )
+            }.as_bool();
+            let more_services = if succeeded {
+                false
+            } else {
+                // services may have been added in the meantime
+                let err = Error::from_win32();
+                if err.win32_error() != Some(ERROR_MORE_DATA) {
+                    return Err(err);
+                }
+                true
+            };
+
+            let statuses = unsafe {
+                std::slice::from_raw_parts(
+                    buf.as_ptr() as *const ENUM_SERVICE_STATUS_PROCESSW,
+                    services_returned as usize,
+                )
+            };
+            for status in statuses {
+                entries.push(ServiceEntry {
+                    name: WideString::from(status.lpServiceName.0).to_os_string(),
+                    display_name: WideString::from(status.lpDisplayName.0).to_os_string(),
+                    state: ServiceState::try_from(status.ServiceStatusProcess.dwCurrentState).ok(),
+                    process_id: match status.ServiceStatusProcess.dwProcessId {
+                        0 => None,
+                        pid => Some(pid),
+                    },
+                });
+            }
+
+            if !more_services {
+                return Ok(entries);
+            }
+        }
+    }
+
+    /// Queries whether the service database is locked. Requires
+    /// [`ServiceControlManagerPermissions::QUERY_LOCK_STATUS`].
+    pub(crate) fn query_lock_status(&self) -> Result<LockStatus, Error> {
+        // find out how much memory we need
+        let mut bytes_needed: u32 = 0;
+        let succeeded = unsafe {
+            QueryServiceLockStatusW(
+                self.0,
+                null_mut(),
+                0,
+                &mut bytes_needed,
+            )
+        }.as_bool();
+        if !succeeded {
+            let err = Error::from_win32();
+            if err.win32_error() != Some(ERROR_INSUFFICIENT_BUFFER) {
+                return Err(err);
+            }
+        }
+
+        // u64 to ensure sufficient alignment
+        let word_count = (bytes_needed as usize).div_ceil(size_of::<u64>());
+        let mut buf: Vec<u64> = vec![0; word_count.max(1)];
+        let status_ptr = buf.as_mut_ptr() as *mut QUERY_SERVICE_LOCK_STATUSW;
+        let succeeded = unsafe {
+            QueryServiceLockStatusW(
+                self.0,
+                status_ptr,
+                (buf.len() * size_of::<u64>()) as u32,
+                &mut bytes_needed,
+            )
+        }.as_bool();
+        if !succeeded {
+            return Err(Error::from_win32());
+        }
+
+        let status = unsafe { &*status_ptr };
+        if status.fIsLocked == 0 {
+            return Ok(LockStatus::Unlocked);
+        }
+        let owner = if status.lpLockOwner.is_null() {
+            OsString::new()
+        } else {
+            WideString::from(status.lpLockOwner.0).to_os_string()
+        };
+        Ok(LockStatus::Locked {
+            owner,
+            duration: Duration::from_secs(status.dwLockDuration.into()),
+        })
+    }
+
+    /// Calls a function that maps one name of a service to another, growing the buffer as
+    /// necessary.
+    fn query_name<F>(&self, input: &OsStr, mut query: F) -> Result<OsString, Error>
+        where F: FnMut(SC_HANDLE, PCWSTR, PWSTR, &mut u32) -> BOOL
+    {
+        let input_ws = WideString::from(input);
+
+        // find out how much memory we need
+        let mut char_count: u32 = 0;
+        let mut empty_buf: [u16; 1] = [0];
+        let succeeded = query(self.0, input_ws.as_pcwstr(), PWSTR(empty_buf.as_mut_ptr()), &mut char_count).as_bool();
+        if succeeded {
+            // empty name?!
+            return Ok(OsString::new());
+        }
+        let err = Error::from_win32();
+        if err.win32_error() != Some(ERROR_INSUFFICIENT_BUFFER) {
+            return Err(err);
+        }
+
+        // the count does not include the terminating NUL
+        let mut buf: Vec<u16> = vec![0; (char_count as usize) + 1];
+        let mut char_count = buf.len() as u32;
+        let succeeded = query(self.0, input_ws.as_pcwstr(), PWSTR(buf.as_mut_ptr()), &mut char_count).as_bool();
+        if !succeeded {
+            return Err(Error::from_win32());
+        }
+        buf.truncate(char_count as usize);
+        Ok(OsString::from_wide(&buf))
+    }
+
+    /// Takes ownership of a raw service control manager handle.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid, open service control manager handle that is not owned by anything
+    /// else; it is closed using `CloseServiceHandle` when the returned value is dropped.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub unsafe fn from_raw(handle: SC_HANDLE) -> Self {
+        Self(handle)
+    }
+
+    /// Relinquishes ownership of the raw service control manager handle without closing it. The
+    /// caller becomes responsible for closing it using `CloseServiceHandle`.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn into_raw(self) -> SC_HANDLE {
+        let this = ManuallyDrop::new(self);
+        this.0
+    }
+}
+impl ServiceControl for ServiceControlManagerHandle {
+    type Service = ServiceHandle;
+
+    fn open_service(
+        &self,
+        service_name: &OsStr,
+        desired_access: ServicePermissions,
+    ) -> Result<Self::Service, Error> {
+        ServiceControlManagerHandle::open_service(self, service_name, desired_access)
+    }
+
+    fn resolve_display_name(&self, display_name: &OsStr) -> Result<OsString, Error> {
+        ServiceControlManagerHandle::resolve_display_name(self, display_name)
+    }
+
+    fn get_display_name(&self, key_name: &OsStr) -> Result<OsString, Error> {
+        ServiceControlManagerHandle::get_display_name(self, key_name)
+    }
 }
 impl Drop for ServiceControlManagerHandle {
     fn drop(&mut self) {
@@ -129,6 +508,142 @@ impl Hash for ServiceControlManagerHandle {
 }
 
 
+/// Whether the service database is locked, as returned by
+/// [`ServiceControlManagerHandle::query_lock_status`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum LockStatus {
+    Unlocked,
+    Locked {
+        /// The account holding the lock; empty if unknown.
+        owner: OsString,
+
+        /// How long the lock has been held.
+        duration: Duration,
+    },
+}
+
+
+/// A service, as returned by [`ServiceControlManagerHandle::enumerate_services`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ServiceEntry {
+    pub name: OsString,
+    pub display_name: OsString,
+
+    /// The state of the service, or `None` if it is not one of the known ones.
+    pub state: Option<ServiceState>,
+
+    /// The ID of the process hosting the service, or `None` if it is not running.
+    pub process_id: Option<u32>,
+}
+
+
+/// Parts of the configuration of a service, as returned by [`ServiceHandle::query_config`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ServiceConfig {
+    /// The command line used to start the service (executable path and arguments).
+    pub binary_path: OsString,
+    pub display_name: OsString,
+
+    /// The start type of the service, or `None` if it is not one of the known ones.
+    pub start_type: Option<ServiceStartType>,
+}
+
+
+/// The settings of a service to be created using
+/// [`ServiceControlManagerHandle::create_service_with`].
+///
+/// Apart from the name and the command line, everything has a default: a service running in its
+/// own process as LocalSystem, started on demand, with normal error control, no display name (i.e.
+/// the service name is shown), no load order group and no dependencies. The returned handle has no
+/// access rights beyond those every handle has.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ServiceInstallOptions {
+    pub service_name: OsString,
+
+    /// The command line used to start the service (executable path and arguments).
+    pub path_and_args: OsString,
+
+    pub display_name: Option<OsString>,
+    pub desired_access: ServicePermissions,
+    pub service_type: ServiceType,
+    pub start_type: ServiceStartType,
+    pub error_control: ServiceErrorControl,
+    pub load_order_group: Option<OsString>,
+    pub dependencies: Vec<OsString>,
+
+    /// The account the service runs as, or `None` for LocalSystem.
+    pub start_name: Option<OsString>,
+    pub password: Option<OsString>,
+}
+impl ServiceInstallOptions {
+    pub fn new(service_name: &OsStr, path_and_args: &OsStr) -> Self {
+        Self {
+            service_name: service_name.to_os_string(),
+            path_and_args: path_and_args.to_os_string(),
+            display_name: None,
+            desired_access: ServicePermissions::empty(),
+            service_type: ServiceType::WIN32_OWN_PROCESS,
+            start_type: ServiceStartType::Demand,
+            error_control: ServiceErrorControl::Normal,
+            load_order_group: None,
+            dependencies: Vec::new(),
+            start_name: None,
+            password: None,
+        }
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn display_name(mut self, display_name: &OsStr) -> Self {
+        self.display_name = Some(display_name.to_os_string());
+        self
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn desired_access(mut self, desired_access: ServicePermissions) -> Self {
+        self.desired_access = desired_access;
+        self
+    }
+
+    pub fn service_type(mut self, service_type: ServiceType) -> Self {
+        self.service_type = service_type;
+        self
+    }
+
+    pub fn start_type(mut self, start_type: ServiceStartType) -> Self {
+        self.start_type = start_type;
+        self
+    }
+
+    pub fn error_control(mut self, error_control: ServiceErrorControl) -> Self {
+        self.error_control = error_control;
+        self
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn load_order_group(mut self, load_order_group: &OsStr) -> Self {
+        self.load_order_group = Some(load_order_group.to_os_string());
+        self
+    }
+
+    /// Adds a service (or, prefixed with `+`, a load order group) that must be running before this
+    /// one can start.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn dependency(mut self, dependency: &OsStr) -> Self {
+        self.dependencies.push(dependency.to_os_string());
+        self
+    }
+
+    /// Runs the service as the given account (e.g. `NT AUTHORITY\LocalService` or `.\user`); the
+    /// password may be `None` for built-in and managed service accounts.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn account(mut self, start_name: &OsStr, password: Option<&OsStr>) -> Self {
+        self.start_name = Some(start_name.to_os_string());
+        self.password = password.map(|p| p.to_os_string());
+        self
+    }
+}
+
+
 #[derive(Debug, Eq, PartialEq)]
 #[repr(transparent)]
 pub(crate) struct ServiceHandle(SC_HANDLE);
@@ -137,14 +652,8 @@ impl ServiceHandle {
         &self,
         args: Vec<&OsStr>,
     ) -> Result<(), Error> {
-        let mut args_ws: Vec<OptionalWideString> = Vec::with_capacity(args.len() + 1);
-        for arg in args {
-            args_ws.push(OptionalWideString::some(arg.into()));
-        }
-
-        let args_ptrs: Vec<PWSTR> = args_ws.iter_mut()
-            .map(|a| a.as_pwstr())
-            .collect();
+        let mut args_ws: WideMultiString = args.into_iter().collect();
+        let args_ptrs: Vec<PWSTR> = args_ws.as_pwstrs();
 
         let succeeded = unsafe {
             StartServiceW(
@@ -159,7 +668,9 @@ impl ServiceHandle {
         }
     }
 
-    pub fn stop(&self) -> Result<(), Error> {
+    /// Asks the service to stop and returns the state it reported in response (generally
+    /// `StopPending`).
+    pub fn stop(&self) -> Result<ServiceState, Error> {
         let mut service_status = SERVICE_STATUS::default();
 
         let succeeded = unsafe {
@@ -170,7 +681,9 @@ impl ServiceHandle {
             )
         }.as_bool();
         if succeeded {
-            Ok(())
+            let service_state = service_status.dwCurrentState
+                .try_into().expect_log("unexpected service status value");
+            Ok(service_state)
         } else {
             Err(Error::from_win32())
         }
@@ -194,6 +707,120 @@ impl ServiceHandle {
         }
     }
 
+    pub fn query_config(&self) -> Result<ServiceConfig, Error> {
+        // find out how much memory we need
+        let mut bytes_needed: u32 = 0;
+        let succeeded = unsafe {
+            QueryServiceConfigW(
+                self.0,
+                null_mut(),
+                0,
+                &mut bytes_needed,
+            )
+        }.as_bool();
+        if !succeeded {
+            let err = Error::from_win32();
+            if err.win32_error() != Some(ERROR_INSUFFICIENT_BUFFER) {
+                return Err(err);
+            }
+        }
+
+        // u64 to ensure sufficient alignment
+        let word_count = (bytes_needed as usize).div_ceil(size_of::<u64>());
+        let mut buf: Vec<u64> = vec![0; word_count.max(1)];
+        let config_ptr = buf.as_mut_ptr() as *mut QUERY_SERVICE_CONFIGW;
+        let succeeded = unsafe {
+            QueryServiceConfigW(
+                self.0,
+                config_ptr,
+                (buf.len() * size_of::<u64>()) as u32,
+                &mut bytes_needed,
+            )
+        }.as_bool();
+        if !succeeded {
+            return Err(Error::from_win32());
+        }
+
+        let config = unsafe { &*config_ptr };
+        let binary_path = WideString::from(config.lpBinaryPathName.0).to_os_string();
+        let display_name = WideString::from(config.lpDisplayName.0).to_os_string();
+        let start_type = ServiceStartType::try_from(config.dwStartType.0).ok();
+        Ok(ServiceConfig {
+            binary_path,
+            display_name,
+            start_type,
+        })
+    }
+
+    /// Changes the configuration of the service. Settings passed as `None` are left unchanged.
+    pub fn change_config(
+        &self,
+        service_type: Option<ServiceType>,
+        start_type: Option<ServiceStartType>,
+        error_control: Option<ServiceErrorControl>,
+        path_and_args: Option<&OsStr>,
+        display_name: Option<&OsStr>,
+    ) -> Result<(), Error> {
+        let path_and_args_ws = OptionalWideString::from(path_and_args);
+        let display_name_ws = OptionalWideString::from(display_name);
+
+        let service_type_value = service_type
+            .map(|st| ENUM_SERVICE_TYPE::from(st).0)
+            .unwrap_or(SERVICE_NO_CHANGE);
+        let start_type_value = start_type
+            .map(SERVICE_START_TYPE::from)
+            .unwrap_or(SERVICE_START_TYPE(SERVICE_NO_CHANGE));
+        let error_control_value = error_control
+            .map(SERVICE_ERROR::from)
+            .unwrap_or(SERVICE_ERROR(SERVICE_NO_CHANGE));
+
+        let succeeded = unsafe {
+            ChangeServiceConfigW(
+                self.0,
+                service_type_value,
+                start_type_value,
+                error_control_value,
+                path_and_args_ws.as_pcwstr(),
+                PCWSTR::default(),
+                null_mut(),
+                PCWSTR::default(),
+                PCWSTR::default(),
+                PCWSTR::default(),
+                display_name_ws.as_pcwstr(),
+            )
+        }.as_bool();
+        if succeeded {
+            Ok(())
+        } else {
+            Err(Error::from_win32())
+        }
+    }
+
+    /// Returns the ID of the process hosting the service, or `None` if it is not running.
+    pub fn get_process_id(&self) -> Result<Option<u32>, Error> {
+        let mut status_process = SERVICE_STATUS_PROCESS::default();
+        let mut bytes_needed: u32 = 0;
+
+        let succeeded = unsafe {
+            QueryServiceStatusEx(
+                self.0,
+                SC_STATUS_PROCESS_INFO,
+                &mut status_process as *mut SERVICE_STATUS_PROCESS as *mut u8,
+                std::mem::size_of::<SERVICE_STATUS_PROCESS>() as u32,
+                &mut bytes_needed,
+            )
+        }.as_bool();
+        if succeeded {
+            if status_process.dwProcessId == 0 {
+                Ok(None)
+            } else {
+                Ok(Some(status_process.dwProcessId))
+            }
+        } else {
+            Err(Error::from_win32())
+        }
+    }
+
     pub fn delete(&self) -> Result<(), Error> {
         let succeeded = unsafe { DeleteService(self.0) }.as_bool();
         if succeeded {
@@ -202,6 +829,43 @@ impl ServiceHandle {
             Err(Error::from_win32())
         }
     }
+
+    /// Takes ownership of a raw service handle.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid, open service handle that is not owned by anything else; it is
+    /// closed using `CloseServiceHandle` when the returned value is dropped.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub unsafe fn from_raw(handle: SC_HANDLE) -> Self {
+        Self(handle)
+    }
+
+    /// Relinquishes ownership of the raw service handle without closing it. The caller becomes
+    /// responsible for closing it using `CloseServiceHandle`.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn into_raw(self) -> SC_HANDLE {
+        let this = ManuallyDrop::new(self);
+        this.0
+    }
+}
+impl ServiceAccess for ServiceHandle {
+    fn start(&self, args: Vec<&OsStr>) -> Result<(), Error> {
+        ServiceHandle::start(self, args)
+    }
+
+    fn get_state(&self) -> Result<ServiceState, Error> {
+        ServiceHandle::get_state(self)
+    }
+
+    fn get_process_id(&self) -> Result<Option<u32>, Error> {
+        ServiceHandle::get_process_id(self)
+    }
+
+    fn get_start_type(&self) -> Result<Option<ServiceStartType>, Error> {
+        ServiceHandle::query_config(self)
+            .map(|c| c.start_type)
+    }
 }
 impl Drop for ServiceHandle {
     fn drop(&mut self) {
@@ -299,6 +963,18 @@ pub(crate) enum ServiceState {
     PausePending = SERVICE_PAUSE_PENDING.0,
     Paused = SERVICE_PAUSED.0,
 }
+impl ServiceState {
+    #[inline] pub fn is_running(self) -> bool { self == Self::Running }
+    #[inline] pub fn is_stopped(self) -> bool { self == Self::Stopped }
+
+    /// Whether the service is on its way from one state to another, e.g. starting up.
+    pub fn is_transitioning(self) -> bool {
+        matches!(
+            self,
+            Self::StartPending | Self::StopPending | Self::ContinuePending | Self::PausePending
+        )
+    }
+}
 impl TryFrom<SERVICE_STATUS_CURRENT_STATE> for ServiceState {
     type Error = SERVICE_STATUS_CURRENT_STATE;
 
@@ -307,3 +983,121 @@ impl TryFrom<SERVICE_STATUS_CURRENT_STATE> for ServiceState {
             .map_err(|_| value)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_service_name_is_rejected() {
+        assert_eq!(validate_service_name(OsStr::new("")), Err(NameError::Empty));
+    }
+
+    #[test]
+    fn service_name_with_slashes_is_rejected() {
+        assert_eq!(validate_service_name(OsStr::new("a/b")), Err(NameError::InvalidCharacter { character: '/' }));
+        assert_eq!(validate_service_name(OsStr::new("a\\b")), Err(NameError::InvalidCharacter { character: '\\' }));
+        assert_eq!(validate_service_name(OsStr::new("a\0b")), Err(NameError::InvalidCharacter { character: '\0' }));
+    }
+
+    #[test]
+    fn service_name_length_limit() {
+        let longest = "a".repeat(MAX_SERVICE_NAME_LENGTH);
+        assert_eq!(validate_service_name(OsStr::new(&longest)), Ok(()));
+
+        let too_long = "a".repeat(MAX_SERVICE_NAME_LENGTH + 1);
+        assert_eq!(validate_service_name(OsStr::new(&too_long)), Err(NameError::TooLong { length: 257 }));
+    }
+
+    #[test]
+    fn service_name_length_is_counted_in_utf16_units() {
+        // each U+1F600 takes two UTF-16 code units
+        let longest = "\u{1F600}".repeat(MAX_SERVICE_NAME_LENGTH / 2);
+        assert_eq!(validate_service_name(OsStr::new(&longest)), Ok(()));
+
+        let too_long = format!("{}a", longest);
+        assert_eq!(validate_service_name(OsStr::new(&too_long)), Err(NameError::TooLong { length: 257 }));
+    }
+
+    #[test]
+    fn ordinary_service_names_are_accepted() {
+        assert_eq!(validate_service_name(OsStr::new("EventLog")), Ok(()));
+        assert_eq!(validate_service_name(OsStr::new("My Service-1.2_x")), Ok(()));
+    }
+
+    #[test]
+    fn install_options_defaults() {
+        let options = ServiceInstallOptions::new(OsStr::new("Example"), OsStr::new("example.exe service"));
+        assert_eq!(options.service_name, OsString::from("Example"));
+        assert_eq!(options.path_and_args, OsString::from("example.exe service"));
+        assert_eq!(options.display_name, None);
+        assert_eq!(options.desired_access, ServicePermissions::empty());
+        assert_eq!(options.service_type, ServiceType::WIN32_OWN_PROCESS);
+        assert_eq!(options.start_type, ServiceStartType::Demand);
+        assert_eq!(options.error_control, ServiceErrorControl::Normal);
+        assert_eq!(options.load_order_group, None);
+        assert_eq!(options.dependencies, Vec::<OsString>::new());
+        assert_eq!(options.start_name, None);
+        assert_eq!(options.password, None);
+    }
+
+    #[test]
+    fn install_options_builder() {
+        let options = ServiceInstallOptions::new(OsStr::new("Example"), OsStr::new("example.exe service"))
+            .display_name(OsStr::new("Example Service"))
+            .desired_access(ServicePermissions::START | ServicePermissions::QUERY_STATUS)
+            .service_type(ServiceType::WIN32_SHARE_PROCESS)
+            .start_type(ServiceStartType::Auto)
+            .error_control(ServiceErrorControl::Severe)
+            .load_order_group(OsStr::new("Network"))
+            .dependency(OsStr::new("Tcpip"))
+            .dependency(OsStr::new("+NetworkProvider"))
+            .account(OsStr::new("NT AUTHORITY\\LocalService"), None);
+        assert_eq!(options.display_name, Some(OsString::from("Example Service")));
+        assert_eq!(options.desired_access, ServicePermissions::START | ServicePermissions::QUERY_STATUS);
+        assert_eq!(options.service_type, ServiceType::WIN32_SHARE_PROCESS);
+        assert_eq!(options.start_type, ServiceStartType::Auto);
+        assert_eq!(options.error_control, ServiceErrorControl::Severe);
+        assert_eq!(options.load_order_group, Some(OsString::from("Network")));
+        assert_eq!(options.dependencies, vec![OsString::from("Tcpip"), OsString::from("+NetworkProvider")]);
+        assert_eq!(options.start_name, Some(OsString::from("NT AUTHORITY\\LocalService")));
+        assert_eq!(options.password, None);
+
+        let options = options.account(OsStr::new(".\\user"), Some(OsStr::new("secret")));
+        assert_eq!(options.start_name, Some(OsString::from(".\\user")));
+        assert_eq!(options.password, Some(OsString::from("secret")));
+    }
+
+    #[test]
+    fn service_state_predicates() {
+        assert!(ServiceState::Running.is_running());
+        assert!(!ServiceState::Running.is_stopped());
+        assert!(!ServiceState::Running.is_transitioning());
+
+        assert!(ServiceState::Stopped.is_stopped());
+        assert!(!ServiceState::Stopped.is_running());
+        assert!(!ServiceState::Stopped.is_transitioning());
+
+        assert!(!ServiceState::Paused.is_running());
+        assert!(!ServiceState::Paused.is_stopped());
+        assert!(!ServiceState::Paused.is_transitioning());
+
+        for state in [ServiceState::StartPending, ServiceState::StopPending, ServiceState::ContinuePending, ServiceState::PausePending] {
+            assert!(state.is_transitioning(), "{:?}", state);
+            assert!(!state.is_running(), "{:?}", state);
+            assert!(!state.is_stopped(), "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn raw_handles_round_trip() {
+        let scm = ServiceControlManagerHandle::open_local_active(ServiceControlManagerPermissions::CONNECT).unwrap();
+        let scm = unsafe { ServiceControlManagerHandle::from_raw(scm.into_raw()) };
+
+        // the event log runs on every Windows system
+        let service = scm.open_service(OsStr::new("EventLog"), ServicePermissions::QUERY_STATUS).unwrap();
+        let service = unsafe { ServiceHandle::from_raw(service.into_raw()) };
+        assert_eq!(service.get_state().unwrap(), ServiceState::Running);
+    }
+}
@@ -1,14 +1,28 @@
-use std::ffi::{OsStr, OsString};
+use std::ffi::{c_void, OsStr, OsString};
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::warn;
 
 use windows::core::{Error, PWSTR};
 use windows::Win32::System::Services::{
-    LPSERVICE_MAIN_FUNCTIONW, RegisterServiceCtrlHandlerW, SERVICE_STATUS, SERVICE_STATUS_HANDLE,
-    SERVICE_TABLE_ENTRYW, SetServiceStatus, StartServiceCtrlDispatcherW,
+    LPHANDLER_FUNCTION, LPHANDLER_FUNCTION_EX, LPSERVICE_MAIN_FUNCTIONW,
+    RegisterServiceCtrlHandlerExW, RegisterServiceCtrlHandlerW, SERVICE_STATUS,
+    SERVICE_STATUS_HANDLE, SERVICE_TABLE_ENTRYW, SetServiceStatus, StartServiceCtrlDispatcherW,
 };
 
+use crate::extensions::ExpectExtension;
 use crate::windows_utils::WideString;
 
 
+/// How often to try reporting the service status before giving up.
+const SET_STATUS_ATTEMPTS: usize = 3;
+
+/// How long to wait between attempts to report the service status.
+const SET_STATUS_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+
 pub(crate) struct ServiceTableEntry {
     pub name: OsString,
     pub main_func: LPSERVICE_MAIN_FUNCTIONW,
@@ -57,9 +71,12 @@ pub(crate) fn start_service_dispatcher(service_table: &[ServiceTableEntry]) -> R
     }
 }
 
+/// Registers the control message handler function of a service. The handler function only receives
+/// the control code.
+#[allow(dead_code)]
 pub(crate) fn register_service_control_handler(
     service_name: &OsStr,
-    handler_function: Option<unsafe extern "system" fn(dwcontrol: u32)>,
+    handler_function: LPHANDLER_FUNCTION,
 ) -> Result<ServiceStatusHandle, Error> {
     let service_name_ws = WideString::from(service_name);
     let handle = unsafe {
@@ -68,25 +85,82 @@ pub(crate) fn register_service_control_handler(
             handler_function,
         )
     }?;
-    Ok(ServiceStatusHandle(handle))
+    Ok(ServiceStatusHandle::new(handle))
+}
+
+/// Registers the extended control message handler function of a service, which also receives the
+/// event type and data of controls such as device, power and session change events.
+///
+/// `context` is passed to each call of the handler function, which allows a process hosting
+/// multiple services to tell them apart; it must remain valid as long as the service runs.
+pub(crate) fn register_service_control_handler_ex(
+    service_name: &OsStr,
+    handler_function: LPHANDLER_FUNCTION_EX,
+    context: *const c_void,
+) -> Result<ServiceStatusHandle, Error> {
+    let service_name_ws = WideString::from(service_name);
+    let handle = unsafe {
+        RegisterServiceCtrlHandlerExW(
+            service_name_ws.as_pcwstr(),
+            handler_function,
+            context,
+        )
+    }?;
+    Ok(ServiceStatusHandle::new(handle))
 }
 
 
 #[derive(Debug)]
-pub(crate) struct ServiceStatusHandle(SERVICE_STATUS_HANDLE);
+pub(crate) struct ServiceStatusHandle {
+    handle: SERVICE_STATUS_HANDLE,
+
+    /// The status most recently reported successfully. The lock is held while reporting, so this
+    /// matches what the service control manager has seen even if multiple threads report.
+    last_status: Mutex<Option<SERVICE_STATUS>>,
+}
 impl ServiceStatusHandle {
+    fn new(handle: SERVICE_STATUS_HANDLE) -> Self {
+        Self {
+            handle,
+            last_status: Mutex::new(None),
+        }
+    }
+
+    /// The status most recently reported successfully using [`ServiceStatusHandle::set_status`],
+    /// if any.
+    pub fn last_status(&self) -> Option<SERVICE_STATUS> {
+        *self.last_status
+            .lock().expect_log("last service status lock poisoned")
+    }
+
+    /// Reports the status of the service to the service control manager. Failures are logged and
+    /// retried a few times; the last error is returned if all attempts fail.
     pub fn set_status(
         &self,
         service_status: SERVICE_STATUS,
     ) -> Result<(), Error> {
-        let success = unsafe {
-            SetServiceStatus(self.0, &service_status)
-        }.as_bool();
-
-        if success {
-            Ok(())
-        } else {
-            Err(Error::from_win32())
+        let mut last_status = self.last_status
+            .lock().expect_log("last service status lock poisoned");
+        let mut attempt = 1;
+        loop {
+            let success = unsafe {
+                SetServiceStatus(self.handle, &service_status)
+            }.as_bool();
+            if success {
+                *last_status = Some(service_status);
+                return Ok(());
+            }
+
+            let err = Error::from_win32();
+            if attempt >= SET_STATUS_ATTEMPTS {
+                return Err(err);
+            }
+            warn!(
+                "failed to set service status (attempt {} of {}); retrying in {} ms: {}",
+                attempt, SET_STATUS_ATTEMPTS, SET_STATUS_RETRY_DELAY.as_millis(), err,
+            );
+            sleep(SET_STATUS_RETRY_DELAY);
+            attempt += 1;
         }
     }
 }
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+
+use crate::registry::{RegistryAccess, RegistryPermissions, RegistryValue, UnexpectedValueType};
+use crate::timestamp::{format_local, unix_seconds_now};
+
+
+/// Restarts of a single service that have not yet been written to the registry.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+struct PendingServiceStats {
+    pub restart_count: u64,
+    pub last_restart_unix_time: u64,
+
+    /// How long the most recently started instance took to be found running, if it has been.
+    pub last_start_duration: Option<Duration>,
+}
+
+
+/// Restart statistics which are periodically persisted to the registry.
+///
+/// For each service, the values `RestartCount`, `LastRestartUnixTime` and
+/// `LastStartDurationMilliseconds` are stored under the subkey `Stats\<servicename>` of the key
+/// passed to [`RestartStatistics::flush`].
+#[derive(Debug)]
+pub(crate) struct RestartStatistics {
+    pending: HashMap<OsString, PendingServiceStats>,
+    last_flush: Instant,
+}
+impl RestartStatistics {
+    pub fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn record_restart(&mut self, service_name: &OsStr) {
+        let now_unix = unix_seconds_now();
+
+        let entry = self.pending.entry(service_name.to_os_string()).or_default();
+        entry.restart_count = entry.restart_count.saturating_add(1);
+        entry.last_restart_unix_time = now_unix;
+    }
+
+    /// Records how long a service that has been started took to be found running.
+    pub fn record_start_duration(&mut self, service_name: &OsStr, duration: Duration) {
+        let entry = self.pending.entry(service_name.to_os_string()).or_default();
+        entry.last_start_duration = Some(duration);
+    }
+
+    /// Whether there are pending statistics and at least `interval` has passed since the last
+    /// flush.
+    pub fn is_flush_due(&self, interval: Duration) -> bool {
+        !self.pending.is_empty() && self.last_flush.elapsed() >= interval
+    }
+
+    /// Writes the pending statistics below the given key.
+    ///
+    /// Statistics that cannot be written are logged and dropped; failing to keep statistics is
+    /// not worth disrupting the actual monitoring.
+    pub fn flush<R: RegistryAccess>(&mut self, parameters_key: &R) {
+        self.last_flush = Instant::now();
+
+        for (service_name, pending) in self.pending.drain() {
+            let mut subkey_path = OsString::from("Stats\\");
+            subkey_path.push(&service_name);
+
+            let stats_key_res = parameters_key.create_subkey(
+                &subkey_path,
+                RegistryPermissions::QUERY_VALUE | RegistryPermissions::SET_VALUE,
+            );
+            let stats_key = match stats_key_res {
+                Ok(k) => k,
+                Err(e) => {
+                    error!("failed to open statistics key for service {:?}: {}", service_name, e);
+                    continue;
+                },
+            };
+
+            let restart_count_name = OsString::from("RestartCount");
+            let stored_count = match stats_key.read_value_optional(Some(&restart_count_name)) {
+                Ok(None) => 0,
+                Ok(Some(RegistryValue::Dword(dw))) => dw.into(),
+                Ok(Some(RegistryValue::DwordBigEndian(dw))) => dw.into(),
+                Ok(Some(RegistryValue::Qword(qw))) => qw,
+                Ok(Some(other)) => {
+                    warn!("{} (service {:?}); starting from zero", UnexpectedValueType::new("RestartCount", "REG_DWORD or REG_QWORD", &other), service_name);
+                    0
+                },
+                Err(e) => {
+                    error!("failed to read restart count of service {:?}: {}", service_name, e);
+                    continue;
+                },
+            };
+            let new_count = match stored_count.checked_add(pending.restart_count) {
+                Some(c) => c,
+                None => {
+                    warn!("restart count of service {:?} overflowed; keeping it at the maximum", service_name);
+                    u64::MAX
+                },
+            };
+
+            let count_res = stats_key.write_value(
+                Some(&restart_count_name),
+                &RegistryValue::Qword(new_count),
+            );
+            if let Err(e) = count_res {
+                error!("failed to write restart count of service {:?}: {}", service_name, e);
+                continue;
+            }
+
+            if let Some(duration) = pending.last_start_duration {
+                let duration_ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+                let duration_res = stats_key.write_value(
+                    Some(&OsString::from("LastStartDurationMilliseconds")),
+                    &RegistryValue::Qword(duration_ms),
+                );
+                if let Err(e) = duration_res {
+                    error!("failed to write last start duration of service {:?}: {}", service_name, e);
+                }
+            }
+
+            // only restarts update the time of the last restart
+            if pending.restart_count == 0 {
+                continue;
+            }
+
+            let last_restart_name = OsString::from("LastRestartUnixTime");
+            if let Ok(Some(previous)) = stats_key.read_value_optional(Some(&last_restart_name)) {
+                if let Some(previous_time) = previous.to_unix_time() {
+                    debug!(
+                        "service {:?} had last been restarted at {} before the {} restarts now being recorded",
+                        service_name, format_local(previous_time), pending.restart_count,
+                    );
+                }
+            }
+
+            let time_res = stats_key.write_value(
+                Some(&last_restart_name),
+                &RegistryValue::Qword(pending.last_restart_unix_time),
+            );
+            if let Err(e) = time_res {
+                error!("failed to write last restart time of service {:?}: {}", service_name, e);
+            }
+        }
+    }
+}
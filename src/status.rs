@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::{Arc, Mutex};
+
+use crate::extensions::ExpectExtension;
+use crate::json;
+use crate::service_control::ServiceState;
+
+
+/// What the loop last observed about a watched service.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct WatchedServiceStatus {
+    pub last_state: Option<ServiceState>,
+    pub restart_count: u64,
+}
+
+
+/// What happened during a single pass over the watched services.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct CheckSummary {
+    pub checked: usize,
+    pub restarted: usize,
+    pub errored: usize,
+}
+
+
+/// Why the loop is currently not starting any services.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum PauseReason {
+    ShuttingDown,
+    PowerSettling,
+    MaintenanceMode,
+    OutsideRestartWindow,
+}
+impl PauseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ShuttingDown => "shutting_down",
+            Self::PowerSettling => "power_settling",
+            Self::MaintenanceMode => "maintenance_mode",
+            Self::OutsideRestartWindow => "outside_restart_window",
+        }
+    }
+}
+
+
+/// A snapshot of the loop's view of the watched services, shared with the status endpoints.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct LoopStatus {
+    pub services: BTreeMap<OsString, WatchedServiceStatus>,
+    pub completed_passes: u64,
+    pub last_summary: CheckSummary,
+
+    /// Whether the watchdog considers the loop stuck.
+    pub stalled: bool,
+
+    /// The state we last reported to the service control manager, if running as a service.
+    pub reported_state: Option<ServiceState>,
+
+    /// Why stopped services were not started during the most recent pass, if they weren't.
+    pub pause_reason: Option<PauseReason>,
+}
+impl LoopStatus {
+    /// Whether at least one pass has been completed, all watched services were found running,
+    /// without errors, during the most recent one and the loop is not stuck.
+    pub fn is_healthy(&self) -> bool {
+        !self.stalled
+            && self.completed_passes > 0
+            && self.last_summary.errored == 0
+            && self.services.values().all(|s| s.last_state == Some(ServiceState::Running))
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\"services\":[");
+        for (i, (name, status)) in self.services.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"name\":");
+            json::push_string(&mut json, &name.to_string_lossy());
+            json.push_str(",\"last_state\":");
+            match status.last_state {
+                Some(s) => json::push_string(&mut json, &format!("{:?}", s)),
+                None => json.push_str("null"),
+            }
+            json.push_str(",\"restart_count\":");
+            json.push_str(&status.restart_count.to_string());
+            json.push('}');
+        }
+        json.push_str("],\"completed_passes\":");
+        json.push_str(&self.completed_passes.to_string());
+        json.push_str(",\"last_pass\":{\"checked\":");
+        json.push_str(&self.last_summary.checked.to_string());
+        json.push_str(",\"restarted\":");
+        json.push_str(&self.last_summary.restarted.to_string());
+        json.push_str(",\"errored\":");
+        json.push_str(&self.last_summary.errored.to_string());
+        json.push_str("},\"stalled\":");
+        json.push_str(if self.stalled { "true" } else { "false" });
+        json.push_str(",\"paused\":");
+        json.push_str(if self.pause_reason.is_some() { "true" } else { "false" });
+        json.push_str(",\"pause_reason\":");
+        match self.pause_reason {
+            Some(pr) => json::push_string(&mut json, pr.as_str()),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"reported_state\":");
+        match self.reported_state {
+            Some(s) => json::push_string(&mut json, &format!("{:?}", s)),
+            None => json.push_str("null"),
+        }
+        json.push('}');
+        json
+    }
+}
+
+
+/// The loop status, shared between the loop and the threads reporting on it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedStatus(Arc<Mutex<LoopStatus>>);
+impl SharedStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the current status.
+    pub fn snapshot(&self) -> LoopStatus {
+        self.0.lock().expect_log("status mutex is poisoned").clone()
+    }
+
+    /// Calls the given function with the current status without copying it.
+    pub fn inspect<T, F: FnOnce(&LoopStatus) -> T>(&self, f: F) -> T {
+        let guard = self.0.lock().expect_log("status mutex is poisoned");
+        f(&guard)
+    }
+
+    /// Modifies the status using the given function.
+    pub fn update<T, F: FnOnce(&mut LoopStatus) -> T>(&self, f: F) -> T {
+        let mut guard = self.0.lock().expect_log("status mutex is poisoned");
+        f(&mut guard)
+    }
+
+    pub fn set_service_state(&self, name: &OsStr, state: ServiceState) {
+        self.update(|s| {
+            s.services.entry(name.to_os_string()).or_default()
+                .last_state = Some(state);
+        });
+    }
+
+    pub fn record_restart(&self, name: &OsStr) {
+        self.update(|s| {
+            let entry = s.services.entry(name.to_os_string()).or_default();
+            entry.restart_count = entry.restart_count.saturating_add(1);
+        });
+    }
+
+    /// Marks a pass as completed, forgetting about all services not contained in the given list.
+    pub fn finish_pass(&self, names: &[OsString], summary: CheckSummary) {
+        self.update(|s| {
+            s.services.retain(|k, _| names.contains(k));
+            s.completed_passes = s.completed_passes.saturating_add(1);
+            s.last_summary = summary;
+        });
+    }
+}
@@ -0,0 +1,174 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::ptr::{null, null_mut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+use log::{debug, error, warn};
+use windows::core::Error;
+use windows::Win32::Foundation::{ERROR_PIPE_CONNECTED, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAGS_AND_ATTRIBUTES, FlushFileBuffers, PIPE_ACCESS_OUTBOUND,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+    PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+
+use crate::status::SharedStatus;
+use crate::windows_utils::WideString;
+
+
+const PIPE_BUFFER_SIZE: u32 = 4096;
+
+
+/// A named pipe which hands out the current loop status as JSON to every client that connects.
+pub(crate) struct StatusPipe {
+    pipe_name: OsString,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+impl StatusPipe {
+    /// Returns the name of the status pipe of the given service, `\\.\pipe\servicerestarter-<name>`.
+    pub fn pipe_name(service_name: &OsStr) -> OsString {
+        let mut pipe_name = OsString::from("\\\\.\\pipe\\servicerestarter-");
+        pipe_name.push(service_name);
+        pipe_name
+    }
+
+    /// Creates the named pipe and starts serving it on a background thread.
+    pub fn spawn(service_name: &OsStr, status: SharedStatus) -> Result<Self, io::Error> {
+        let pipe_name = Self::pipe_name(service_name);
+
+        // create the first instance here to catch errors early
+        let first_instance = create_pipe_instance(&pipe_name, true)?;
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let thread_pipe_name = pipe_name.clone();
+        let thread_stopping = Arc::clone(&stopping);
+        let thread = std::thread::Builder::new()
+            .name("status pipe".to_owned())
+            .spawn(move || serve(thread_pipe_name, first_instance, thread_stopping, status))?;
+
+        Ok(Self {
+            pipe_name,
+            stopping,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops serving the pipe and waits for the serving thread to finish.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        let thread = match self.thread.take() {
+            Some(t) => t,
+            None => return,
+        };
+
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // the serving thread is blocked waiting for a client; become one to wake it up
+        if let Err(e) = File::open(&self.pipe_name) {
+            warn!("failed to connect to status pipe {:?} to stop it: {}", self.pipe_name, e);
+        }
+
+        if thread.join().is_err() {
+            error!("status pipe thread panicked");
+        }
+    }
+}
+impl Drop for StatusPipe {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+
+fn create_pipe_instance(pipe_name: &OsStr, first: bool) -> Result<File, Error> {
+    let pipe_name_ws = WideString::from(pipe_name);
+    let open_mode = if first {
+        PIPE_ACCESS_OUTBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        PIPE_ACCESS_OUTBOUND | FILE_FLAGS_AND_ATTRIBUTES(0)
+    };
+
+    let handle = unsafe {
+        CreateNamedPipeW(
+            pipe_name_ws.as_pcwstr(),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUFFER_SIZE,
+            0,
+            0,
+            null(),
+        )
+    };
+    if handle.is_invalid() {
+        return Err(Error::from_win32());
+    }
+
+    // let File take care of closing the handle
+    Ok(unsafe { File::from_raw_handle(handle.0 as RawHandle) })
+}
+
+fn serve(pipe_name: OsString, first_instance: File, stopping: Arc<AtomicBool>, status: SharedStatus) {
+    let mut instance = first_instance;
+    loop {
+        let connected = unsafe {
+            ConnectNamedPipe(HANDLE(raw_handle(&instance)), null_mut())
+        }.as_bool();
+        let connect_error = if connected { None } else { Some(Error::from_win32()) };
+
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match connect_error {
+            Some(e) if e.win32_error() != Some(ERROR_PIPE_CONNECTED) => {
+                error!("failed to wait for a status pipe client: {}", e);
+            },
+            _ => {
+                // answer on a separate thread; waiting for a slow client must not hold up the next one
+                let json = status.snapshot().to_json();
+                let answer_res = std::thread::Builder::new()
+                    .name("status pipe client".to_owned())
+                    .spawn(move || answer(instance, json));
+                if let Err(e) = answer_res {
+                    error!("failed to spawn status pipe client thread: {}", e);
+                }
+            },
+        }
+
+        instance = match create_pipe_instance(&pipe_name, false) {
+            Ok(i) => i,
+            Err(e) => {
+                error!("failed to create another instance of status pipe {:?}; no longer serving it: {}", pipe_name, e);
+                return;
+            },
+        };
+    }
+}
+
+fn answer(mut instance: File, json: String) {
+    if let Err(e) = instance.write_all(json.as_bytes()) {
+        debug!("failed to write status to pipe client: {}", e);
+        return;
+    }
+
+    // ensure the client has read everything before the handle is closed
+    let flushed = unsafe { FlushFileBuffers(HANDLE(raw_handle(&instance))) }.as_bool();
+    if !flushed {
+        debug!("failed to flush status pipe: {}", Error::from_win32());
+    }
+}
+
+fn raw_handle(file: &File) -> isize {
+    file.as_raw_handle() as isize
+}
@@ -0,0 +1,117 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+
+
+/// The Unix epoch (1970-01-01) as a FILETIME, i.e. in 100-nanosecond intervals since 1601-01-01.
+const UNIX_EPOCH_AS_FILETIME: u64 = 116_444_736_000_000_000;
+
+/// The number of FILETIME intervals per second.
+const FILETIME_TICKS_PER_SECOND: u64 = 10_000_000;
+
+
+/// The current time as seconds since the Unix epoch.
+pub(crate) fn unix_seconds_now() -> u64 {
+    system_time_to_unix_seconds(SystemTime::now())
+}
+
+/// Converts a time to seconds since the Unix epoch. Times before the epoch become 0.
+pub(crate) fn system_time_to_unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Interprets seconds since the Unix epoch as a time.
+pub(crate) fn unix_seconds_to_system_time(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Interprets a FILETIME (100-nanosecond intervals since 1601-01-01) as a time.
+pub(crate) fn filetime_to_system_time(filetime: u64) -> SystemTime {
+    if filetime >= UNIX_EPOCH_AS_FILETIME {
+        let ticks = filetime - UNIX_EPOCH_AS_FILETIME;
+        UNIX_EPOCH
+            + Duration::from_secs(ticks / FILETIME_TICKS_PER_SECOND)
+            + Duration::from_nanos((ticks % FILETIME_TICKS_PER_SECOND) * 100)
+    } else {
+        let ticks = UNIX_EPOCH_AS_FILETIME - filetime;
+        UNIX_EPOCH
+            - Duration::from_secs(ticks / FILETIME_TICKS_PER_SECOND)
+            - Duration::from_nanos((ticks % FILETIME_TICKS_PER_SECOND) * 100)
+    }
+}
+
+/// Converts a time to a FILETIME (100-nanosecond intervals since 1601-01-01). Times that cannot be
+/// represented are clamped.
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) fn system_time_to_filetime(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => {
+            let ticks = since.as_secs()
+                .saturating_mul(FILETIME_TICKS_PER_SECOND)
+                .saturating_add(u64::from(since.subsec_nanos() / 100));
+            UNIX_EPOCH_AS_FILETIME.saturating_add(ticks)
+        },
+        Err(e) => {
+            let before = e.duration();
+            let ticks = before.as_secs()
+                .saturating_mul(FILETIME_TICKS_PER_SECOND)
+                .saturating_add(u64::from(before.subsec_nanos() / 100));
+            UNIX_EPOCH_AS_FILETIME.saturating_sub(ticks)
+        },
+    }
+}
+
+/// Formats a time in the local time zone for log messages, e.g. `2022-05-01 13:37:00 +0200`.
+pub(crate) fn format_local(time: SystemTime) -> String {
+    let local: DateTime<Local> = time.into();
+    local.format("%Y-%m-%d %H:%M:%S %z").to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_epoch_as_filetime_is_unix_epoch() {
+        assert_eq!(filetime_to_system_time(UNIX_EPOCH_AS_FILETIME), UNIX_EPOCH);
+        assert_eq!(system_time_to_filetime(UNIX_EPOCH), UNIX_EPOCH_AS_FILETIME);
+    }
+
+    #[test]
+    fn filetime_round_trips() {
+        let time = UNIX_EPOCH + Duration::new(1_234_567_890, 123_456_700);
+        let filetime = system_time_to_filetime(time);
+        assert_eq!(filetime, UNIX_EPOCH_AS_FILETIME + 1_234_567_890 * FILETIME_TICKS_PER_SECOND + 1_234_567);
+        assert_eq!(filetime_to_system_time(filetime), time);
+    }
+
+    #[test]
+    fn filetime_before_unix_epoch_round_trips() {
+        let time = UNIX_EPOCH - Duration::new(86_400, 500);
+        let filetime = system_time_to_filetime(time);
+        assert_eq!(filetime, UNIX_EPOCH_AS_FILETIME - 86_400 * FILETIME_TICKS_PER_SECOND - 5);
+        assert_eq!(filetime_to_system_time(filetime), time);
+    }
+
+    #[test]
+    fn filetime_zero_is_1601() {
+        let seconds_1601_to_1970 = UNIX_EPOCH_AS_FILETIME / FILETIME_TICKS_PER_SECOND;
+        assert_eq!(filetime_to_system_time(0), UNIX_EPOCH - Duration::from_secs(seconds_1601_to_1970));
+    }
+
+    #[test]
+    fn time_before_1601_is_clamped() {
+        let seconds_1601_to_1970 = UNIX_EPOCH_AS_FILETIME / FILETIME_TICKS_PER_SECOND;
+        let time = UNIX_EPOCH - Duration::from_secs(seconds_1601_to_1970 + 10);
+        assert_eq!(system_time_to_filetime(time), 0);
+    }
+
+    #[test]
+    fn sub_tick_precision_is_truncated() {
+        let time = UNIX_EPOCH + Duration::from_nanos(199);
+        assert_eq!(system_time_to_filetime(time), UNIX_EPOCH_AS_FILETIME + 1);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use windows::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+
+use crate::registry::{RegistryAccess, RegistryPermissions, RegistryValue, UnexpectedValueType};
+use crate::timestamp::{format_local, unix_seconds_now, unix_seconds_to_system_time};
+
+
+/// How often the loop has attempted to start a service since it was last seen running.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct RestartTracking {
+    pub attempts: u64,
+    pub gave_up: bool,
+    pub last_attempt_unix_time: u64,
+}
+
+
+fn state_subkey_path(service_name: &OsStr) -> OsString {
+    let mut subkey_path = OsString::from("State\\");
+    subkey_path.push(service_name);
+    subkey_path
+}
+
+fn read_u64_value<R: RegistryAccess>(key: &R, service_name: &OsStr, name: &str) -> Option<u64> {
+    match key.read_value_optional(Some(&OsString::from(name))) {
+        Ok(None) => None,
+        Ok(Some(RegistryValue::Dword(dw))) => Some(dw.into()),
+        Ok(Some(RegistryValue::DwordBigEndian(dw))) => Some(dw.into()),
+        Ok(Some(RegistryValue::Qword(qw))) => Some(qw),
+        Ok(Some(other)) => {
+            warn!("{} (service {:?}); ignoring it", UnexpectedValueType::new(name, "REG_DWORD or REG_QWORD", &other), service_name);
+            None
+        },
+        Err(e) => {
+            error!("failed to read restart state value {} of service {:?}: {}", name, service_name, e);
+            None
+        },
+    }
+}
+
+
+/// Restart attempts per service, persisted to the registry so that they survive restarts of
+/// servicerestarter itself.
+///
+/// For each service with pending attempts, the values `RestartAttempts`, `GaveUp` and
+/// `LastAttemptUnixTime` are stored under the subkey `State\<servicename>` of the parameters key.
+/// The state of a service is loaded the first time it is consulted; state older than the reset
+/// period is discarded at that point.
+#[derive(Debug, Default)]
+pub(crate) struct RestartTracker {
+    entries: HashMap<OsString, RestartTracking>,
+    loaded: HashSet<OsString>,
+    dirty: HashSet<OsString>,
+}
+impl RestartTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the persisted state of the given service unless this has already been done.
+    pub fn ensure_loaded<R: RegistryAccess>(&mut self, parameters_key: &R, service_name: &OsStr, reset_period: Duration) {
+        if !self.loaded.insert(service_name.to_os_string()) {
+            return;
+        }
+
+        let state_key_res = parameters_key.open_subkey(
+            Some(&state_subkey_path(service_name)),
+            RegistryPermissions::QUERY_VALUE,
+        );
+        let state_key = match state_key_res {
+            Ok(k) => k,
+            Err(e) if e.win32_error() == Some(ERROR_FILE_NOT_FOUND) => return,
+            Err(e) => {
+                error!("failed to open restart state key of service {:?}: {}", service_name, e);
+                return;
+            },
+        };
+
+        let tracking = RestartTracking {
+            attempts: read_u64_value(&state_key, service_name, "RestartAttempts").unwrap_or(0),
+            gave_up: read_u64_value(&state_key, service_name, "GaveUp").unwrap_or(0) != 0,
+            last_attempt_unix_time: read_u64_value(&state_key, service_name, "LastAttemptUnixTime").unwrap_or(0),
+        };
+
+        let age_secs = unix_seconds_now().saturating_sub(tracking.last_attempt_unix_time);
+        if age_secs > reset_period.as_secs() {
+            // stale; forget about it (and remove it from the registry at the next save)
+            self.dirty.insert(service_name.to_os_string());
+            return;
+        }
+
+        info!(
+            "resuming restart state of service {:?}: {} attempts, the last one at {}{}",
+            service_name, tracking.attempts,
+            format_local(unix_seconds_to_system_time(tracking.last_attempt_unix_time)),
+            if tracking.gave_up { ", given up" } else { "" },
+        );
+        self.entries.insert(service_name.to_os_string(), tracking);
+    }
+
+    pub fn get(&self, service_name: &OsStr) -> RestartTracking {
+        self.entries.get(service_name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn record_attempt(&mut self, service_name: &OsStr) {
+        let entry = self.entries.entry(service_name.to_os_string()).or_default();
+        entry.attempts = entry.attempts.saturating_add(1);
+        entry.last_attempt_unix_time = unix_seconds_now();
+        self.dirty.insert(service_name.to_os_string());
+    }
+
+    pub fn give_up(&mut self, service_name: &OsStr) {
+        let entry = self.entries.entry(service_name.to_os_string()).or_default();
+        entry.gave_up = true;
+        self.dirty.insert(service_name.to_os_string());
+    }
+
+    /// Forgets about previous attempts to start the given service.
+    pub fn clear(&mut self, service_name: &OsStr) {
+        if self.entries.remove(service_name).is_some() {
+            self.dirty.insert(service_name.to_os_string());
+        }
+    }
+
+    /// Whether there are changes that have not yet been written to the registry.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Writes the changed state below the given key.
+    ///
+    /// State that cannot be written is logged and dropped; it will be written again at the next
+    /// change.
+    pub fn save<R: RegistryAccess>(&mut self, parameters_key: &R) {
+        for service_name in self.dirty.drain() {
+            let subkey_path = state_subkey_path(&service_name);
+
+            let tracking = match self.entries.get(&service_name) {
+                Some(t) => t,
+                None => {
+                    match parameters_key.delete_subkey(&subkey_path) {
+                        Ok(()) => {},
+                        Err(e) if e.win32_error() == Some(ERROR_FILE_NOT_FOUND) => {},
+                        Err(e) => error!("failed to delete restart state key of service {:?}: {}", service_name, e),
+                    }
+                    continue;
+                },
+            };
+
+            let state_key_res = parameters_key.create_subkey(
+                &subkey_path,
+                RegistryPermissions::SET_VALUE,
+            );
+            let state_key = match state_key_res {
+                Ok(k) => k,
+                Err(e) => {
+                    error!("failed to open restart state key of service {:?}: {}", service_name, e);
+                    continue;
+                },
+            };
+
+            let values = [
+                ("RestartAttempts", RegistryValue::Qword(tracking.attempts)),
+                ("GaveUp", RegistryValue::Dword(if tracking.gave_up { 1 } else { 0 })),
+                ("LastAttemptUnixTime", RegistryValue::Qword(tracking.last_attempt_unix_time)),
+            ];
+            for (name, value) in &values {
+                if let Err(e) = state_key.write_value(Some(&OsString::from(name)), value) {
+                    error!("failed to write restart state value {} of service {:?}: {}", name, service_name, e);
+                    break;
+                }
+            }
+        }
+    }
+}
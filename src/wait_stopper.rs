@@ -1,29 +1,75 @@
+use std::fmt;
 use std::sync::{Condvar, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::extensions::ExpectExtension;
 
 
+/// Why the loop has been asked to stop.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum StopReason {
+    /// The service control manager sent a stop request.
+    ControlStop,
+
+    /// The system is shutting down.
+    Shutdown,
+
+    /// The maximum runtime given on the command line has been reached.
+    MaxRuntime,
+
+    /// The number of iterations given on the command line has been performed.
+    Iterations,
+
+    /// No service has needed to be started for the period given by `ExitAfterIdleMilliseconds`.
+    Idle,
+
+    /// Ctrl+C or Ctrl+Break was pressed in the console.
+    ConsoleCtrl,
+}
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ControlStop => write!(f, "stop request from the service control manager"),
+            Self::Shutdown => write!(f, "system shutdown"),
+            Self::MaxRuntime => write!(f, "maximum runtime reached"),
+            Self::Iterations => write!(f, "number of iterations reached"),
+            Self::Idle => write!(f, "idle period reached"),
+            Self::ConsoleCtrl => write!(f, "stop request from the console"),
+        }
+    }
+}
+
+
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 #[repr(transparent)]
-pub(crate) struct StopResult(bool);
+pub(crate) struct StopResult(Option<StopReason>);
 impl StopResult {
-    #[inline] pub fn wants_to_stop(&self) -> bool { self.0 }
+    #[inline] pub fn wants_to_stop(&self) -> bool { self.0.is_some() }
+    #[inline] pub fn reason(&self) -> Option<StopReason> { self.0 }
 
-    #[inline] pub fn new_wants_to_stop() -> Self { Self(true) }
-    #[inline] pub fn new_does_not_want_to_stop() -> Self { Self(false) }
+    #[inline] pub fn new_wants_to_stop(reason: StopReason) -> Self { Self(Some(reason)) }
+    #[inline] pub fn new_does_not_want_to_stop() -> Self { Self(None) }
+}
+
+
+#[derive(Debug)]
+struct WaitState {
+    stop_result: StopResult,
+    woken: bool,
 }
 
 
 #[derive(Debug)]
 pub(crate) struct WaitStopper {
-    mutex: Mutex<StopResult>,
+    mutex: Mutex<WaitState>,
     cond_var: Condvar,
 }
 impl WaitStopper {
     pub fn new() -> Self {
-        let mutex = Mutex::new(StopResult::new_does_not_want_to_stop());
+        let mutex = Mutex::new(WaitState {
+            stop_result: StopResult::new_does_not_want_to_stop(),
+            woken: false,
+        });
         let cond_var = Condvar::new();
         Self {
             mutex,
@@ -31,32 +77,138 @@ impl WaitStopper {
         }
     }
 
+    /// Waits until either a stop is requested or the full timeout has elapsed, whichever comes
+    /// first. Spurious wakeups do not cut the wait short.
     pub fn wait_until_stop_timeout(&self, timeout: Duration) -> StopResult {
-        let guard = self.mutex.lock()
+        self.wait_until_stop_deadline(Instant::now() + timeout)
+    }
+
+    /// Waits until either a stop is requested or the deadline has passed, whichever comes first.
+    ///
+    /// Spurious wakeups are waited out, so the only early return is due to a stop request.
+    pub fn wait_until_stop_deadline(&self, deadline: Instant) -> StopResult {
+        self.wait(deadline, false)
+    }
+
+    /// Like [`wait_until_stop_timeout`](Self::wait_until_stop_timeout), but additionally returns
+    /// early if [`wake`](Self::wake) is called (or has been called since the last such wait).
+    pub fn wait_until_stop_or_wake_timeout(&self, timeout: Duration) -> StopResult {
+        self.wait(Instant::now() + timeout, true)
+    }
+
+    fn wait(&self, deadline: Instant, wakeable: bool) -> StopResult {
+        let mut guard = self.mutex.lock()
             .expect_log("mutex is poisoned");
-        if guard.wants_to_stop() {
-            return *guard;
+        loop {
+            if guard.stop_result.wants_to_stop() {
+                return guard.stop_result;
+            }
+            if wakeable && guard.woken {
+                guard.woken = false;
+                return guard.stop_result;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return guard.stop_result;
+            }
+            let (new_guard, _timeout_result) = self.cond_var.wait_timeout(guard, remaining)
+                .expect_log("mutex is poisoned");
+            guard = new_guard;
         }
-        let (guard, _timeout_result) = self.cond_var.wait_timeout(guard, timeout)
-            .expect_log("mutex is poisoned");
-        return *guard;
     }
 
-    pub fn stop(&self) {
+    /// Asks waiting threads to stop. If a stop has already been requested, the original reason is
+    /// kept.
+    pub fn stop(&self, reason: StopReason) {
         {
             let mut guard = self.mutex.lock()
                 .expect_log("mutex is poisoned");
-            *guard = StopResult::new_wants_to_stop();
+            if !guard.stop_result.wants_to_stop() {
+                guard.stop_result = StopResult::new_wants_to_stop(reason);
+            }
         }
         self.cond_var.notify_all();
     }
 
-    pub fn wait_until_stop_timeout_opt(stopper: Option<&WaitStopper>, timeout: Duration) -> StopResult {
-        if let Some(s) = stopper {
-            s.wait_until_stop_timeout(timeout)
-        } else {
-            sleep(timeout);
-            StopResult::new_does_not_want_to_stop()
+    /// Cuts the current (or next) wakeable wait short without asking anyone to stop.
+    pub fn wake(&self) {
+        {
+            let mut guard = self.mutex.lock()
+                .expect_log("mutex is poisoned");
+            guard.woken = true;
         }
+        self.cond_var.notify_all();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(300);
+
+    /// Notifies the condition variable without changing the state, as a spurious wakeup would,
+    /// until the waiting thread returns.
+    fn notify_spuriously_during<F: FnOnce() -> StopResult>(stopper: &WaitStopper, wait: F) -> (StopResult, Duration) {
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    stopper.cond_var.notify_all();
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            });
+            let start = Instant::now();
+            let result = wait();
+            let elapsed = start.elapsed();
+            done.store(true, Ordering::SeqCst);
+            (result, elapsed)
+        })
+    }
+
+    #[test]
+    fn spurious_wakeups_do_not_shorten_wait() {
+        let stopper = WaitStopper::new();
+        let (result, elapsed) = notify_spuriously_during(&stopper, || stopper.wait_until_stop_timeout(TIMEOUT));
+        assert_eq!(result.reason(), None);
+        assert!(elapsed >= TIMEOUT, "returned after {:?}", elapsed);
+    }
+
+    #[test]
+    fn spurious_wakeups_do_not_shorten_wakeable_wait() {
+        let stopper = WaitStopper::new();
+        let (result, elapsed) = notify_spuriously_during(&stopper, || stopper.wait_until_stop_or_wake_timeout(TIMEOUT));
+        assert_eq!(result.reason(), None);
+        assert!(elapsed >= TIMEOUT, "returned after {:?}", elapsed);
+    }
+
+    #[test]
+    fn stop_ends_wait_early() {
+        let stopper = WaitStopper::new();
+        let start = Instant::now();
+        let result = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(50));
+                stopper.stop(StopReason::ConsoleCtrl);
+            });
+            stopper.wait_until_stop_timeout(Duration::from_secs(30))
+        });
+        assert_eq!(result.reason(), Some(StopReason::ConsoleCtrl));
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn wake_only_ends_wakeable_wait() {
+        let stopper = WaitStopper::new();
+        stopper.wake();
+        assert_eq!(stopper.wait_until_stop_timeout(Duration::from_millis(50)).reason(), None);
+
+        // the wake is still pending for the next wakeable wait
+        let start = Instant::now();
+        assert_eq!(stopper.wait_until_stop_or_wake_timeout(Duration::from_secs(30)).reason(), None);
+        assert!(start.elapsed() < Duration::from_secs(30));
     }
 }
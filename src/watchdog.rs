@@ -0,0 +1,121 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use crate::status::SharedStatus;
+
+
+/// How often the watchdog checks on the loop at most.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The exit code with which the process is terminated if the loop stalls and the watchdog has
+/// been told to exit.
+const STALLED_EXIT_CODE: i32 = 3;
+
+
+/// A background thread that complains if the loop does not check in on time, e.g. because it is
+/// stuck in a blocking call.
+///
+/// Before each sleep, the loop announces how long it will sleep using [`Watchdog::tick`]; the loop
+/// is considered stalled if it has not ticked again within that time plus the timeout.
+pub(crate) struct Watchdog {
+    start: Instant,
+    deadline_ms: Arc<AtomicU64>,
+    timeout: Duration,
+    stop_sender: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+impl Watchdog {
+    /// Starts watching. If `exit_on_stall` is set, the process exits once the loop has stalled so
+    /// that the service control manager can restart it according to its recovery settings;
+    /// otherwise, an error is logged and the status is marked as stalled until the loop recovers.
+    pub fn spawn(timeout: Duration, exit_on_stall: bool, status: SharedStatus) -> Result<Self, io::Error> {
+        let start = Instant::now();
+        let deadline_ms = Arc::new(AtomicU64::new(duration_to_ms(timeout)));
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let check_interval = (timeout / 2).clamp(Duration::from_millis(100), MAX_CHECK_INTERVAL);
+
+        let thread_deadline_ms = Arc::clone(&deadline_ms);
+        let thread = std::thread::Builder::new()
+            .name("watchdog".to_owned())
+            .spawn(move || {
+                let mut stalled = false;
+                loop {
+                    match stop_receiver.recv_timeout(check_interval) {
+                        Err(RecvTimeoutError::Timeout) => {},
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                    }
+
+                    let now_ms = duration_to_ms(start.elapsed());
+                    let deadline = thread_deadline_ms.load(Ordering::SeqCst);
+                    if now_ms > deadline && !stalled {
+                        stalled = true;
+                        error!("the loop has not checked in for {} ms longer than expected; it might be stuck", now_ms - deadline);
+                        status.update(|s| s.stalled = true);
+                        if exit_on_stall {
+                            error!("exiting so that the service control manager can restart us");
+                            log::logger().flush();
+                            std::process::exit(STALLED_EXIT_CODE);
+                        }
+                    } else if now_ms <= deadline && stalled {
+                        stalled = false;
+                        info!("the loop has checked in again");
+                        status.update(|s| s.stalled = false);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            start,
+            deadline_ms,
+            timeout,
+            stop_sender: Some(stop_sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Tells the watchdog that the loop is alive and will check in again after at most
+    /// `next_tick_in` (plus the timeout).
+    pub fn tick(&self, next_tick_in: Duration) {
+        let deadline = self.start.elapsed()
+            .saturating_add(next_tick_in)
+            .saturating_add(self.timeout);
+        self.deadline_ms.store(duration_to_ms(deadline), Ordering::SeqCst);
+    }
+
+    /// Stops watching and waits for the watchdog thread to finish.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        let thread = match self.thread.take() {
+            Some(t) => t,
+            None => return,
+        };
+
+        if let Some(sender) = self.stop_sender.take() {
+            // the thread also stops if the channel is closed, so a failure does not matter
+            let _ = sender.send(());
+        }
+
+        if thread.join().is_err() {
+            error!("watchdog thread panicked");
+        }
+    }
+}
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+
+fn duration_to_ms(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
@@ -86,15 +86,6 @@ pub(crate) struct OptionalWideString(Option<WideString>);
 impl OptionalWideString {
     #[inline]
     pub fn as_pcwstr(&self) -> PCWSTR { self.into() }
-
-    #[inline]
-    pub fn as_pwstr(&mut self) -> PWSTR { self.into() }
-
-    #[inline]
-    pub fn none() -> Self { Self(None) }
-
-    #[inline]
-    pub fn some(ws: WideString) -> Self { Self(Some(ws)) }
 }
 impl From<Option<&OsStr>> for OptionalWideString {
     fn from(s: Option<&OsStr>) -> Self {
@@ -117,11 +108,45 @@ impl From<&OptionalWideString> for PCWSTR {
         }
     }
 }
-impl From<&mut OptionalWideString> for PWSTR {
-    fn from(s: &mut OptionalWideString) -> Self {
-        match s.0.as_mut() {
-            Some(ws) => ws.into(),
-            None => PWSTR::default(),
+
+
+/// A list of NUL-terminated strings of u16 characters, stored contiguously and followed by an
+/// additional NUL (e.g. `a\0b\0\0`).
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct WideMultiString {
+    buffer: Vec<u16>,
+    offsets: Vec<usize>,
+}
+impl WideMultiString {
+    /// A pointer to the whole double-NUL-terminated list.
+    #[inline]
+    pub fn as_pcwstr(&self) -> PCWSTR { PCWSTR(self.buffer.as_ptr()) }
+
+    /// Pointers to each of the strings.
+    pub fn as_pwstrs(&mut self) -> Vec<PWSTR> {
+        let base = self.buffer.as_mut_ptr();
+        self.offsets.iter()
+            .map(|o| PWSTR(unsafe { base.add(*o) }))
+            .collect()
+    }
+}
+impl<S: AsRef<OsStr>> FromIterator<S> for WideMultiString {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut buffer = Vec::new();
+        let mut offsets = Vec::new();
+        for s in iter {
+            offsets.push(buffer.len());
+            buffer.extend(s.as_ref().encode_wide());
+            buffer.push(0x0000);
+        }
+        buffer.push(0x0000);
+        if offsets.is_empty() {
+            // an empty list is still terminated by two NULs
+            buffer.push(0x0000);
+        }
+        Self {
+            buffer,
+            offsets,
         }
     }
 }